@@ -1,5 +1,7 @@
+mod pattern;
 mod value;
 
+pub use pattern::*;
 pub use value::*;
 
 #[derive(Debug, PartialEq)]
@@ -61,18 +63,25 @@ impl Span {
 
 pub type Block = Vec<Operation>;
 pub type Label = String;
-pub type MatchBranch = (Value, Block);
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum OperationKind {
+    // replaced by `Local` or `Global` once resolved
     Variable {
         name: String,
     },
+    Local {
+        depth: usize,
+        index: usize,
+    },
+    Global {
+        index: usize,
+    },
     Value {
         value: Value,
     },
     Closure {
-        params: Vec<String>,
+        params: Params,
         body: Block,
     },
     Application {
@@ -86,11 +95,22 @@ pub enum OperationKind {
         names: Vec<String>,
         body: Block,
     },
-    Sequence {
-        subexpr_count: usize,
+    Pop,
+    // replaced by `SetLocal` or `SetGlobal` once resolved
+    Set {
+        name: String,
+    },
+    SetLocal {
+        depth: usize,
+        index: usize,
+    },
+    SetGlobal {
+        index: usize,
     },
     Match {
         branches: Vec<MatchBranch>,
+        // location of the value being matched, for when no branch matches
+        scrutinee: Span,
     },
     And {
         jump_to: Label,
@@ -134,7 +154,7 @@ impl Operation {
         }
     }
 
-    pub fn closure(params: Vec<String>, body: Block, span: Span) -> Self {
+    pub fn closure(params: Params, body: Block, span: Span) -> Self {
         Self {
             kind: OperationKind::Closure { params, body },
             span,
@@ -165,16 +185,26 @@ impl Operation {
         }
     }
 
-    pub fn seq(subexpr_count: usize, span: Span) -> Self {
+    pub fn pop(span: Span) -> Self {
+        Self {
+            kind: OperationKind::Pop,
+            span,
+        }
+    }
+
+    pub fn set(name: String, span: Span) -> Self {
         Self {
-            kind: OperationKind::Sequence { subexpr_count },
+            kind: OperationKind::Set { name },
             span,
         }
     }
 
-    pub fn match_(branches: Vec<MatchBranch>, span: Span) -> Self {
+    pub fn match_(branches: Vec<MatchBranch>, scrutinee: Span, span: Span) -> Self {
         Self {
-            kind: OperationKind::Match { branches },
+            kind: OperationKind::Match {
+                branches,
+                scrutinee,
+            },
             span,
         }
     }
@@ -225,13 +255,87 @@ impl Operation {
     }
 }
 
+/// The parameters of a lambda, in the order of the frame slots they bind.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params {
+    pub names: Vec<String>,
+    /// How many of the leading parameters must be passed.
+    pub required: usize,
+    /// The default values of the optional parameters that follow the required
+    /// ones. Each is evaluated in the scope of the parameters before it.
+    pub defaults: Vec<Block>,
+    /// Whether the last parameter collects the remaining arguments in a list.
+    pub rest: bool,
+}
+
+impl Params {
+    /// Parameters that must all be passed.
+    pub fn fixed(names: Vec<String>) -> Self {
+        Self {
+            required: names.len(),
+            names,
+            defaults: Vec::new(),
+            rest: false,
+        }
+    }
+}
+
+/// What an import statement brings into scope.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImportSource {
+    /// A built-in module, e.g. `(import image)`.
+    Module(String),
+    /// The top-level definitions of another file, e.g. `(import "helpers.scm")`.
+    File(String),
+}
+
+/// Which bindings of a module an import brings into scope, and under what names.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportFilter {
+    /// The only bindings to import, from `:only (name ...)`.
+    pub only: Option<Vec<String>>,
+    /// Prepended to the name of every imported binding, from `:prefix prefix`.
+    pub prefix: Option<String>,
+}
+
+impl ImportFilter {
+    /// The name the binding `name` of the module is imported as, if it is imported.
+    pub fn rename(&self, name: &str) -> Option<String> {
+        if let Some(only) = &self.only {
+            if !only.iter().any(|n| n == name) {
+                return None;
+            }
+        }
+        Some(match &self.prefix {
+            Some(prefix) => format!("{prefix}{name}"),
+            None => name.to_string(),
+        })
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum StatementKind {
-    Binding { name: String, body: Block },
-    Expression { body: Block },
-    Import { mod_name: String },
-    Display { body: Block },
-    Struct { id: String, fields: Vec<String> },
+    Binding {
+        name: String,
+        body: Block,
+    },
+    Expression {
+        body: Block,
+    },
+    Import {
+        source: ImportSource,
+        filter: ImportFilter,
+    },
+    Provide {
+        names: Vec<String>,
+    },
+    Display {
+        body: Block,
+    },
+    Struct {
+        id: String,
+        fields: Vec<String>,
+    },
 }
 
 #[derive(Debug, PartialEq)]
@@ -253,8 +357,12 @@ impl Statement {
         Self::new(StatementKind::Expression { body }, span)
     }
 
-    pub fn import(mod_name: String, span: Span) -> Self {
-        Self::new(StatementKind::Import { mod_name }, span)
+    pub fn import(source: ImportSource, filter: ImportFilter, span: Span) -> Self {
+        Self::new(StatementKind::Import { source, filter }, span)
+    }
+
+    pub fn provide(names: Vec<String>, span: Span) -> Self {
+        Self::new(StatementKind::Provide { names }, span)
     }
 
     pub fn display(body: Block, span: Span) -> Self {
@@ -0,0 +1,132 @@
+use std::collections::BTreeSet;
+
+use super::{Block, Value};
+
+/// A pattern of a `match` branch. Variables are numbered by the order they
+/// first appear in the branch, which is also the order of the slots of the
+/// frame the branch body runs in.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Variable(usize),
+    Literal(Value),
+    /// `(cons head tail)` or `(pair head tail)`.
+    Cons(Box<Pattern>, Box<Pattern>),
+    /// `(list p ...)`, matching lists of exactly that length.
+    List(Vec<Pattern>),
+    /// `(vector p ...)`, matching vectors of exactly that length.
+    Vector(Vec<Pattern>),
+    /// `(kind p ...)`, matching structs of type `kind` field by field.
+    Struct {
+        kind: String,
+        fields: Vec<Pattern>,
+    },
+    /// `(? pred p ...)`, matching values `pred` accepts that also match every
+    /// sub-pattern. `predicate` indexes the predicates of the branch.
+    Predicate {
+        predicate: usize,
+        patterns: Vec<Pattern>,
+    },
+    And(Vec<Pattern>),
+    Or(Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Collects the slots of the variables the pattern binds.
+    pub fn variables(&self, slots: &mut BTreeSet<usize>) {
+        match self {
+            Pattern::Variable(slot) => {
+                slots.insert(*slot);
+            }
+            Pattern::Cons(head, tail) => {
+                head.variables(slots);
+                tail.variables(slots);
+            }
+            Pattern::List(patterns)
+            | Pattern::Vector(patterns)
+            | Pattern::Struct {
+                fields: patterns, ..
+            }
+            | Pattern::Predicate { patterns, .. }
+            | Pattern::And(patterns)
+            | Pattern::Or(patterns) => {
+                for pattern in patterns {
+                    pattern.variables(slots);
+                }
+            }
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+        }
+    }
+
+    /// Returns the slot of a variable the pattern binds more than once, if
+    /// any. Only one alternative of an `or` pattern matches, so variables
+    /// bound by each of them are bound once.
+    pub fn repeated_variable(&self) -> Option<usize> {
+        self.repeated(&mut BTreeSet::new())
+    }
+
+    // adds the slots the pattern binds to `bound`, returning the first one
+    // that is already there
+    fn repeated(&self, bound: &mut BTreeSet<usize>) -> Option<usize> {
+        match self {
+            Pattern::Variable(slot) => (!bound.insert(*slot)).then_some(*slot),
+            Pattern::Cons(head, tail) => head.repeated(bound).or_else(|| tail.repeated(bound)),
+            Pattern::Or(alternatives) => {
+                let before = bound.clone();
+                for alternative in alternatives {
+                    let mut slots = before.clone();
+                    if let Some(slot) = alternative.repeated(&mut slots) {
+                        return Some(slot);
+                    }
+                    bound.extend(slots);
+                }
+                None
+            }
+            Pattern::List(patterns)
+            | Pattern::Vector(patterns)
+            | Pattern::Struct {
+                fields: patterns, ..
+            }
+            | Pattern::Predicate { patterns, .. }
+            | Pattern::And(patterns) => patterns.iter().find_map(|p| p.repeated(bound)),
+            Pattern::Wildcard | Pattern::Literal(_) => None,
+        }
+    }
+
+    /// Collects the names of the structs the pattern matches.
+    pub fn structs<'a>(&'a self, kinds: &mut Vec<&'a str>) {
+        match self {
+            Pattern::Struct { kind, fields } => {
+                kinds.push(kind);
+                for field in fields {
+                    field.structs(kinds);
+                }
+            }
+            Pattern::Cons(head, tail) => {
+                head.structs(kinds);
+                tail.structs(kinds);
+            }
+            Pattern::List(patterns)
+            | Pattern::Vector(patterns)
+            | Pattern::Predicate { patterns, .. }
+            | Pattern::And(patterns)
+            | Pattern::Or(patterns) => {
+                for pattern in patterns {
+                    pattern.structs(kinds);
+                }
+            }
+            Pattern::Variable(_) | Pattern::Wildcard | Pattern::Literal(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchBranch {
+    pub pattern: Pattern,
+    /// Names of the variables the pattern binds, by slot.
+    pub variables: Vec<String>,
+    /// Expressions for the predicates of the pattern's `?` sub-patterns. They
+    /// are evaluated in the enclosing scope before the pattern is tried.
+    pub predicates: Vec<Block>,
+    pub body: Block,
+}
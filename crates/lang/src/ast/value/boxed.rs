@@ -0,0 +1,46 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{FromValue, IntoValue, Value};
+
+/// A mutable cell holding a single value. Like vectors, clones share the same
+/// underlying cell.
+#[derive(Debug, Clone)]
+pub struct ValueBox(Rc<RefCell<Value>>);
+
+impl ValueBox {
+    pub fn new(value: Value) -> Self {
+        Self(Rc::new(RefCell::new(value)))
+    }
+
+    pub fn get(&self) -> Value {
+        self.0.borrow().clone()
+    }
+
+    pub fn set(&self, value: Value) {
+        *self.0.borrow_mut() = value;
+    }
+
+    /// Returns whether both handles refer to the same box.
+    pub fn ptr_eq(&self, other: &ValueBox) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl FromValue for ValueBox {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Box(cell) => Some(ValueBox(cell.clone())),
+            _ => None,
+        }
+    }
+
+    fn name() -> &'static str {
+        "box"
+    }
+}
+
+impl IntoValue for ValueBox {
+    fn into_value(self) -> Option<Value> {
+        Some(Value::Box(self.0))
+    }
+}
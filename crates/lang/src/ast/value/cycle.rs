@@ -0,0 +1,261 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::{self, Formatter},
+    rc::Rc,
+};
+
+use super::{Struct, Table, Value};
+
+// Vectors, boxes and mutable hash tables can contain themselves, so the
+// functions that walk through values keep track of the ones they've reached
+// by the address of their contents.
+
+// the address of a vector, box or mutable hash table
+fn address(value: &Value) -> Option<usize> {
+    match value {
+        Value::Vector(v) => Some(Rc::as_ptr(v) as *const () as usize),
+        Value::Box(b) => Some(Rc::as_ptr(b) as *const () as usize),
+        Value::Hash(table) if table.borrow().is_mutable() => {
+            Some(Rc::as_ptr(table) as *const () as usize)
+        }
+        _ => None,
+    }
+}
+
+// calls `f` with each value directly inside of `value`
+fn for_each_child(value: &Value, f: &mut impl FnMut(&Value)) {
+    match value {
+        Value::Pair(a, b) => {
+            f(a);
+            f(b);
+        }
+        Value::List(values) => values.iter().for_each(f),
+        Value::Vector(values) => values.borrow().iter().for_each(f),
+        Value::Box(value) => f(&value.borrow()),
+        Value::Hash(table) => {
+            for (key, value) in table.borrow().entries() {
+                f(key);
+                f(value);
+            }
+        }
+        Value::Struct(s) => s.values.iter().for_each(f),
+        _ => {}
+    }
+}
+
+/// Copies `value` for [`Value::snapshot`]. Each vector, box or table is
+/// copied once, so the copy contains itself where the value does.
+pub(super) fn snapshot(value: &Value, copies: &mut HashMap<usize, Value>) -> Value {
+    if let Some(copy) = address(value).and_then(|address| copies.get(&address)) {
+        return copy.clone();
+    }
+    match value {
+        Value::Pair(a, b) => {
+            Value::Pair(Box::new(snapshot(a, copies)), Box::new(snapshot(b, copies)))
+        }
+        Value::List(l) => Value::List(l.iter().map(|v| snapshot(v, copies)).collect()),
+        Value::Vector(v) => {
+            let copy = Rc::new(RefCell::new(Vec::new()));
+            copies.insert(address(value).unwrap(), Value::Vector(Rc::clone(&copy)));
+            let values = v.borrow().iter().map(|v| snapshot(v, copies)).collect();
+            *copy.borrow_mut() = values;
+            Value::Vector(copy)
+        }
+        Value::Box(b) => {
+            let copy = Rc::new(RefCell::new(Value::Void));
+            copies.insert(address(value).unwrap(), Value::Box(Rc::clone(&copy)));
+            let contents = snapshot(&b.borrow(), copies);
+            *copy.borrow_mut() = contents;
+            Value::Box(copy)
+        }
+        Value::Hash(table) if table.borrow().is_mutable() => {
+            let copy = Rc::new(RefCell::new(Table::new(true)));
+            copies.insert(address(value).unwrap(), Value::Hash(Rc::clone(&copy)));
+            for (key, value) in table.borrow().entries() {
+                let (key, value) = (snapshot(key, copies), snapshot(value, copies));
+                copy.borrow_mut().insert(key, value);
+            }
+            Value::Hash(copy)
+        }
+        Value::Struct(s) => Value::Struct(Struct {
+            values: s.values.iter().map(|v| snapshot(v, copies)).collect(),
+            ..s.clone()
+        }),
+        _ => value.clone(),
+    }
+}
+
+/// Prints values whose vectors, boxes and tables contain themselves with
+/// datum labels: the first time one is printed it's prefixed with `#n=`, and
+/// it's printed as `#n#` everywhere else.
+pub(super) struct Printer {
+    // the addresses of the values inside of themselves, with their label
+    // once they have one
+    labels: HashMap<usize, Option<usize>>,
+    next: usize,
+}
+
+impl Printer {
+    pub(super) fn new(value: &Value) -> Self {
+        let mut labels = HashMap::new();
+        find_cycles(value, &mut HashSet::new(), &mut HashSet::new(), &mut labels);
+        Self { labels, next: 0 }
+    }
+
+    // prints a vector, box or table with `contents`, labelling it if it is
+    // inside of itself
+    fn labelled(
+        &mut self,
+        value: &Value,
+        f: &mut Formatter<'_>,
+        contents: impl FnOnce(&mut Self, &mut Formatter<'_>) -> fmt::Result,
+    ) -> fmt::Result {
+        if let Some(label) = address(value).and_then(|address| self.labels.get_mut(&address)) {
+            if let Some(label) = label {
+                return write!(f, "#{}#", label);
+            }
+            *label = Some(self.next);
+            write!(f, "#{}=", self.next)?;
+            self.next += 1;
+        }
+        contents(self, f)
+    }
+
+    pub(super) fn print(&mut self, value: &Value, f: &mut Formatter<'_>) -> fmt::Result {
+        match value {
+            Value::Pair(a, b) => {
+                write!(f, "(pair ")?;
+                self.print(a, f)?;
+                write!(f, " ")?;
+                self.print(b, f)?;
+                write!(f, ")")
+            }
+            Value::List(l) => {
+                write!(f, "(list")?;
+                for item in l {
+                    write!(f, " ")?;
+                    self.print(item, f)?;
+                }
+                write!(f, ")")
+            }
+            Value::Vector(v) => self.labelled(value, f, |printer, f| {
+                write!(f, "(vector")?;
+                for item in v.borrow().iter() {
+                    write!(f, " ")?;
+                    printer.print(item, f)?;
+                }
+                write!(f, ")")
+            }),
+            Value::Box(b) => self.labelled(value, f, |printer, f| {
+                write!(f, "(box ")?;
+                printer.print(&b.borrow(), f)?;
+                write!(f, ")")
+            }),
+            Value::Hash(table) => self.labelled(value, f, |printer, f| {
+                let table = table.borrow();
+                if table.is_mutable() {
+                    write!(f, "(make-hash (list")?;
+                    for (key, value) in table.entries() {
+                        write!(f, " (pair ")?;
+                        printer.print(key, f)?;
+                        write!(f, " ")?;
+                        printer.print(value, f)?;
+                        write!(f, ")")?;
+                    }
+                    write!(f, "))")
+                } else {
+                    write!(f, "(hash")?;
+                    for (key, value) in table.entries() {
+                        write!(f, " ")?;
+                        printer.print(key, f)?;
+                        write!(f, " ")?;
+                        printer.print(value, f)?;
+                    }
+                    write!(f, ")")
+                }
+            }),
+            Value::Struct(s) => {
+                write!(f, "({}", s.kind)?;
+                for value in &s.values {
+                    write!(f, " ")?;
+                    self.print(value, f)?;
+                }
+                write!(f, ")")
+            }
+            _ => value.fmt_leaf(f),
+        }
+    }
+}
+
+// adds the addresses of the vectors, boxes and tables reached again while
+// walking through their contents to `cycles`
+fn find_cycles(
+    value: &Value,
+    path: &mut HashSet<usize>,
+    done: &mut HashSet<usize>,
+    cycles: &mut HashMap<usize, Option<usize>>,
+) {
+    let address = address(value);
+    if let Some(address) = address {
+        if path.contains(&address) {
+            cycles.insert(address, None);
+            return;
+        }
+        if !done.insert(address) {
+            return;
+        }
+        path.insert(address);
+    }
+    for_each_child(value, &mut |child| find_cycles(child, path, done, cycles));
+    if let Some(address) = address {
+        path.remove(&address);
+    }
+}
+
+/// Compares values for [`PartialEq`]. Vectors, boxes and tables being
+/// compared are assumed to be equal while comparing their contents, so values
+/// that contain themselves are equal when they can't be told apart.
+pub(super) fn equal(a: &Value, b: &Value, assumed: &mut HashSet<(usize, usize)>) -> bool {
+    match (a, b) {
+        (Value::Pair(a1, b1), Value::Pair(a2, b2)) => {
+            equal(a1, a2, assumed) && equal(b1, b2, assumed)
+        }
+        (Value::List(a), Value::List(b)) => all_equal(a, b, assumed),
+        (Value::Struct(a), Value::Struct(b)) => {
+            a.kind == b.kind && all_equal(&a.values, &b.values, assumed)
+        }
+        (Value::Vector(x), Value::Vector(y)) => {
+            Rc::ptr_eq(x, y)
+                || assume(a, b, assumed)
+                || all_equal(&x.borrow(), &y.borrow(), assumed)
+        }
+        (Value::Box(x), Value::Box(y)) => {
+            Rc::ptr_eq(x, y) || assume(a, b, assumed) || equal(&x.borrow(), &y.borrow(), assumed)
+        }
+        (Value::Hash(x), Value::Hash(y)) => {
+            Rc::ptr_eq(x, y) || assume(a, b, assumed) || {
+                let (x, y) = (x.borrow(), y.borrow());
+                x.is_mutable() == y.is_mutable()
+                    && x.len() == y.len()
+                    && x.entries().all(|(key, value)| {
+                        y.get(key).is_some_and(|other| equal(value, other, assumed))
+                    })
+            }
+        }
+        _ => a.eq_leaf(b),
+    }
+}
+
+// records that `a` and `b` are being compared, returning whether they already
+// were
+fn assume(a: &Value, b: &Value, assumed: &mut HashSet<(usize, usize)>) -> bool {
+    match (address(a), address(b)) {
+        (Some(a), Some(b)) => !assumed.insert((a, b)),
+        _ => false,
+    }
+}
+
+fn all_equal(a: &[Value], b: &[Value], assumed: &mut HashSet<(usize, usize)>) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b, assumed))
+}
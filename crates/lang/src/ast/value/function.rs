@@ -1,7 +1,5 @@
-use std::{cell::RefCell, rc::Rc};
-
 use super::{Closure, FromValue, NativeFn, Value};
-use crate::interpreter::{Env, ExecutionStack, RuntimeError};
+use crate::interpreter::RuntimeError;
 
 #[derive(Debug, Clone)]
 pub struct Function {
@@ -12,35 +10,7 @@ pub struct Function {
 
 impl Function {
     pub fn call(&self, args: &[Value]) -> Result<Value, RuntimeError> {
-        match &self._function {
-            Some(func) => return func.0(&args),
-            None => {}
-        }
-        match &self._closure {
-            Some(closure) => {
-                let env = closure
-                    .env
-                    .clone()
-                    .unwrap_or(Rc::new(RefCell::new(Env::new(None))));
-                let new_env = Rc::new(RefCell::new(
-                    env.borrow().extend(
-                        closure
-                            .params
-                            .clone()
-                            .into_iter()
-                            .zip(args.to_vec().into_iter()),
-                    ),
-                ));
-
-                let mut stack = ExecutionStack::new(new_env, closure.body.clone());
-                stack.run()?;
-                return stack
-                    .pop()
-                    .ok_or(RuntimeError::new("missing stack value".to_string(), None));
-            }
-            None => {}
-        }
-        unreachable!()
+        crate::interpreter::call_function(self.value(), args)
     }
 
     pub fn value(&self) -> Value {
@@ -0,0 +1,223 @@
+use std::{
+    cell::{Ref, RefCell},
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+use im_rc::{HashMap, Vector};
+
+use super::{FromValue, IntoValue, Number, Value};
+
+/// The entries of a hash table, which maps keys to values by `equal?`.
+/// Entries are kept in the order their keys were first added. Clones share
+/// their entries until they're changed, so extending a copy of a table is
+/// cheap.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    entries: Vector<(Value, Value)>,
+    // indices of the entries whose keys have each hash
+    buckets: HashMap<u64, Vec<usize>>,
+    mutable: bool,
+}
+
+impl Table {
+    pub fn new(mutable: bool) -> Self {
+        Self {
+            mutable,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the table can be changed in place with `hash-set!`.
+    pub fn is_mutable(&self) -> bool {
+        self.mutable
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &(Value, Value)> {
+        self.entries.iter()
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.position(key).map(|i| &self.entries[i].1)
+    }
+
+    /// Maps `key` to `value`, replacing the value it had, if any.
+    pub fn insert(&mut self, key: Value, value: Value) {
+        if let Some(i) = self.position(&key) {
+            self.entries[i].1 = value;
+            return;
+        }
+        self.buckets
+            .entry(hash_of(&key))
+            .or_default()
+            .push(self.entries.len());
+        self.entries.push_back((key, value));
+    }
+
+    fn position(&self, key: &Value) -> Option<usize> {
+        self.buckets
+            .get(&hash_of(key))?
+            .iter()
+            .copied()
+            .find(|&i| self.entries[i].0 == *key)
+    }
+}
+
+impl PartialEq for Table {
+    fn eq(&self, other: &Self) -> bool {
+        self.mutable == other.mutable
+            && self.len() == other.len()
+            && self
+                .entries
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+fn hash_of(value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+// the number of vectors, boxes and mutable tables whose contents are hashed
+// when hashing a value, since they can contain themselves. Values that are
+// `equal?` unfold into the same vectors, boxes and tables in the same order,
+// so they still hash the same.
+const MUTABLE_CONTENTS_HASHED: usize = 16;
+
+// values that are `equal?` hash the same, so numbers hash by their value
+// whatever their representation, and empty lists like null
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut budget = MUTABLE_CONTENTS_HASHED;
+        hash_value(self, state, &mut budget);
+    }
+}
+
+// hashes `value`, including the contents of at most `budget` more vectors,
+// boxes and mutable tables
+fn hash_value<H: Hasher>(value: &Value, state: &mut H, budget: &mut usize) {
+    match value {
+        Value::Integer(_) | Value::BigInteger(_) | Value::Rational(_) | Value::Float(_) => {
+            // exact and inexact numbers are compared through their
+            // floating-point value, where 0.0 and -0.0 are equal
+            let f = Number::from_value(value).unwrap().as_f64();
+            let f = if f == 0.0 { 0.0 } else { f };
+            f.to_bits().hash(state);
+        }
+        Value::Boolean(b) => b.hash(state),
+        Value::String(s) => s.hash(state),
+        Value::Char(c) => c.hash(state),
+        Value::Symbol(s) => s.hash(state),
+        Value::Pair(a, b) => {
+            hash_value(a, state, budget);
+            hash_value(b, state, budget);
+        }
+        Value::List(values) => hash_all(values, state, budget),
+        Value::Null => 0usize.hash(state),
+        Value::Vector(values) => {
+            if take(budget) {
+                hash_all(&values.borrow(), state, budget);
+            }
+        }
+        Value::Box(value) => {
+            if take(budget) {
+                hash_value(&value.borrow(), state, budget);
+            }
+        }
+        Value::Struct(s) => {
+            s.kind.hash(state);
+            hash_all(&s.values, state, budget);
+        }
+        Value::Hash(table) => {
+            let table = table.borrow();
+            if !table.is_mutable() || take(budget) {
+                hash_entries(&table, state);
+            }
+        }
+        Value::Closure(closure, _) => Rc::as_ptr(&closure.code).hash(state),
+        // functions are equal by name or by identity
+        Value::Function(..) | Value::Foreign(_) | Value::Void => {}
+    }
+}
+
+fn hash_all<H: Hasher>(values: &[Value], state: &mut H, budget: &mut usize) {
+    values.len().hash(state);
+    for value in values {
+        hash_value(value, state, budget);
+    }
+}
+
+// hashes the entries of `table` whatever order they're in. Tables that are
+// equal can hold their entries in different orders, so the vectors, boxes and
+// mutable tables inside of the entries aren't looked into.
+fn hash_entries<H: Hasher>(table: &Table, state: &mut H) {
+    let sum = table.entries().fold(0u64, |sum, (key, value)| {
+        let mut hasher = DefaultHasher::new();
+        hash_value(key, &mut hasher, &mut 0);
+        hash_value(value, &mut hasher, &mut 0);
+        sum.wrapping_add(hasher.finish())
+    });
+    table.len().hash(state);
+    sum.hash(state);
+}
+
+// uses up one of the contents that may still be hashed, if any are left
+fn take(budget: &mut usize) -> bool {
+    let left = *budget > 0;
+    *budget = budget.saturating_sub(1);
+    left
+}
+
+/// A hash table. Clones of a mutable table share the same entries, like
+/// vectors.
+#[derive(Debug, Clone)]
+pub struct HashTable(Rc<RefCell<Table>>);
+
+impl HashTable {
+    pub fn new(table: Table) -> Self {
+        Self(Rc::new(RefCell::new(table)))
+    }
+
+    pub fn borrow(&self) -> Ref<'_, Table> {
+        self.0.borrow()
+    }
+
+    pub fn insert(&self, key: Value, value: Value) {
+        self.0.borrow_mut().insert(key, value);
+    }
+
+    /// Returns whether both handles refer to the same table.
+    pub fn ptr_eq(&self, other: &HashTable) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl FromValue for HashTable {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Hash(table) => Some(HashTable(table.clone())),
+            _ => None,
+        }
+    }
+
+    fn name() -> &'static str {
+        "hash table"
+    }
+}
+
+impl IntoValue for HashTable {
+    fn into_value(self) -> Option<Value> {
+        Some(Value::Hash(self.0))
+    }
+}
@@ -1,48 +1,46 @@
-use super::{FromValue, IntoValue, Value};
+use std::{
+    cell::{Ref, RefCell},
+    rc::Rc,
+};
 
-macro_rules! implement_value_collection {
-    ($name:ident) => {
-        #[derive(Debug, Clone)]
-        pub struct $name(Vec<Value>);
+use super::{FromValue, IntoValue, Value};
 
-        impl $name {
-            pub fn empty() -> Self {
-                Self(Vec::new())
-            }
+#[derive(Debug, Clone)]
+pub struct List(Vec<Value>);
 
-            pub fn is_empty(&self) -> bool {
-                self.0.is_empty()
-            }
+impl List {
+    pub fn empty() -> Self {
+        Self(Vec::new())
+    }
 
-            pub fn len(&self) -> usize {
-                self.0.len()
-            }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 
-            pub fn values(&self) -> &[Value] {
-                &self.0
-            }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
 
-            pub fn values_vec(self) -> Vec<Value> {
-                self.0
-            }
-        }
+    pub fn values(&self) -> &[Value] {
+        &self.0
+    }
 
-        impl From<Vec<Value>> for $name {
-            fn from(vec: Vec<Value>) -> Self {
-                Self(vec)
-            }
-        }
+    pub fn values_vec(self) -> Vec<Value> {
+        self.0
+    }
+}
 
-        impl Into<Vec<Value>> for $name {
-            fn into(self) -> Vec<Value> {
-                self.0
-            }
-        }
-    };
+impl From<Vec<Value>> for List {
+    fn from(vec: Vec<Value>) -> Self {
+        Self(vec)
+    }
 }
 
-implement_value_collection!(List);
-implement_value_collection!(Vector);
+impl Into<Vec<Value>> for List {
+    fn into(self) -> Vec<Value> {
+        self.0
+    }
+}
 
 impl FromValue for List {
     fn from_value(value: &Value) -> Option<Self> {
@@ -68,10 +66,74 @@ impl IntoValue for List {
     }
 }
 
+/// A mutable vector. Clones share the same underlying storage, so changes made
+/// through one handle are visible through every other.
+#[derive(Debug, Clone)]
+pub struct Vector(Rc<RefCell<Vec<Value>>>);
+
+impl Vector {
+    pub fn empty() -> Self {
+        Self::from(Vec::new())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Value> {
+        self.0.borrow().get(index).cloned()
+    }
+
+    /// Replaces the element at `index`, returning `false` if it is out of bounds.
+    pub fn set(&self, index: usize, value: Value) -> bool {
+        match self.0.borrow_mut().get_mut(index) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn fill(&self, value: Value) {
+        self.0.borrow_mut().fill(value);
+    }
+
+    pub fn values(&self) -> Ref<'_, Vec<Value>> {
+        self.0.borrow()
+    }
+
+    /// Copies the current elements out of the vector.
+    pub fn values_vec(self) -> Vec<Value> {
+        self.0.borrow().clone()
+    }
+
+    /// Returns whether both handles refer to the same vector.
+    pub fn ptr_eq(&self, other: &Vector) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl From<Vec<Value>> for Vector {
+    fn from(vec: Vec<Value>) -> Self {
+        Self(Rc::new(RefCell::new(vec)))
+    }
+}
+
+impl Into<Vec<Value>> for Vector {
+    fn into(self) -> Vec<Value> {
+        self.values_vec()
+    }
+}
+
 impl FromValue for Vector {
     fn from_value(value: &Value) -> Option<Self> {
         match value {
-            Value::Vector(list) => Some(Vector(list.clone())),
+            Value::Vector(vec) => Some(Vector(vec.clone())),
             _ => None,
         }
     }
@@ -1,12 +1,24 @@
-use super::Block;
-use crate::interpreter::{Env, RuntimeError};
-use std::{any::Any, cell::RefCell, fmt::Debug, rc::Rc};
-
+use crate::interpreter::{Code, Env, Globals, RuntimeError, Scope};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    rc::Rc,
+};
+
+mod boxed;
+mod cycle;
 mod function;
+mod hash;
 mod list;
 #[macro_use]
 mod number;
+pub use boxed::ValueBox;
 pub use function::Function;
+pub use hash::{HashTable, Table};
 pub use list::{List, Vector};
 pub use number::*;
 
@@ -38,14 +50,15 @@ impl PartialEq for NativeFn {
 
 #[derive(Debug, Clone)]
 pub struct Closure {
-    pub params: Vec<String>,
-    pub body: Block,
-    pub env: Option<Rc<RefCell<Env>>>,
+    pub code: Rc<Code>,
+    // local variables the closure was created with, if any
+    pub env: Option<Rc<RefCell<Scope>>>,
+    pub globals: Rc<RefCell<Globals>>,
 }
 
 impl PartialEq for Closure {
     fn eq(&self, other: &Self) -> bool {
-        self.params == other.params && self.body == other.body
+        Rc::ptr_eq(&self.code, &other.code)
     }
 }
 
@@ -184,16 +197,21 @@ impl Struct {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub enum Value {
     Boolean(bool),
     Integer(i64),
+    // exact integers that don't fit in an `i64`
+    BigInteger(Rc<BigInt>),
+    Rational(Rc<BigRational>),
     Float(f64),
     String(String),
     Char(char),
     Pair(Box<Value>, Box<Value>),
     List(Vec<Value>),
-    Vector(Vec<Value>),
+    Vector(Rc<RefCell<Vec<Value>>>),
+    Box(Rc<RefCell<Value>>),
+    Hash(Rc<RefCell<Table>>),
     Symbol(String),
     Struct(Struct),
     Closure(Closure, Option<String>),
@@ -209,6 +227,7 @@ impl Value {
             Value::Boolean(b) => *b,
             Value::Integer(i) => *i != 0,
             Value::Float(f) => *f != 0.0,
+            Value::BigInteger(_) | Value::Rational(_) => true,
             Value::String(s) => !s.is_empty(),
             Value::Null => false,
             Value::Void => false,
@@ -216,12 +235,14 @@ impl Value {
         }
     }
 
+    /// Copies the value, including the contents of any vectors or boxes it
+    /// contains, so that later mutations don't affect the copy.
+    pub fn snapshot(&self) -> Value {
+        cycle::snapshot(self, &mut HashMap::new())
+    }
+
     pub fn numeric(&self) -> Option<f64> {
-        match self {
-            Value::Integer(i) => Some(*i as f64),
-            Value::Float(f) => Some(*f),
-            _ => None,
-        }
+        Number::from_value(self).map(|n| n.as_f64())
     }
 
     pub fn string(&self) -> Option<&str> {
@@ -234,12 +255,16 @@ impl Value {
     pub fn name(&self) -> &'static str {
         match self {
             Value::Boolean(_) => "boolean",
-            Value::Integer(_) | Value::Float(_) => "number",
+            Value::Integer(_) | Value::BigInteger(_) | Value::Rational(_) | Value::Float(_) => {
+                "number"
+            }
             Value::String(_) => "string",
             Value::Char(_) => "char",
             Value::Pair(_, _) => "pair",
             Value::List(_) => "list",
             Value::Vector(_) => "vector",
+            Value::Box(_) => "box",
+            Value::Hash(_) => "hash table",
             Value::Symbol(_) => "symbol",
             Value::Struct(_) => "struct",
             Value::Closure(_, _) | Value::Function(_, _) => "procedure",
@@ -250,49 +275,56 @@ impl Value {
     }
 }
 
+// values can contain themselves, so they're debugged as they're printed
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Pair(..)
+            | Value::List(_)
+            | Value::Vector(_)
+            | Value::Box(_)
+            | Value::Hash(_)
+            | Value::Struct(_) => cycle::Printer::new(self).print(self, f),
+            _ => self.fmt_leaf(f),
+        }
+    }
+}
+
+impl Value {
+    // prints a value that doesn't contain other values
+    fn fmt_leaf(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Value::Boolean(b) => write!(f, "{}", if *b { "#t" } else { "#f" }),
-            Value::Integer(i) => write!(f, "{}", i),
-            Value::Float(fl) => {
-                if fl.fract() == 0.0 {
-                    write!(f, "{}", *fl as i64)
-                } else {
-                    write!(f, "{}", fl)
-                }
+            Value::Integer(_) | Value::BigInteger(_) | Value::Rational(_) | Value::Float(_) => {
+                write!(f, "{}", Number::from_value(self).unwrap())
             }
             Value::String(s) => write!(f, "\"{}\"", s),
             Value::Char(c) => write!(f, "\\#{}", c),
-            Value::Pair(a, b) => write!(f, "(pair {} {})", a, b),
-            Value::List(l) => {
-                write!(f, "(list")?;
-                for item in l {
-                    write!(f, " {}", item)?;
-                }
-                write!(f, ")")
-            }
-            Value::Vector(v) => {
-                write!(f, "(vector")?;
-                for item in v {
-                    write!(f, " {}", item)?;
-                }
-                write!(f, ")")
-            }
             Value::Symbol(s) => write!(f, "{}", s),
             Value::Closure(c, name) => {
                 if let Some(name) = name {
                     write!(f, "{}", name)
                 } else {
-                    write!(f, "(lambda ({}) <body>)", c.params.join(" "))
-                }
-            }
-            Value::Struct(s) => {
-                write!(f, "({}", s.kind)?;
-                for value in &s.values {
-                    write!(f, " {}", value)?;
+                    let mut params = c
+                        .code
+                        .params
+                        .iter()
+                        .map(|p| crate::parser::base_name(p))
+                        .collect::<Vec<_>>();
+                    if c.code.rest {
+                        params.insert(params.len() - 1, ".");
+                    }
+                    match (c.code.rest, params.as_slice()) {
+                        (true, [_, rest]) => write!(f, "(lambda {rest} <body>)"),
+                        _ => write!(f, "(lambda ({}) <body>)", params.join(" ")),
+                    }
                 }
-                write!(f, ")")
             }
             Value::Function(_, name) => {
                 if let Some(name) = name {
@@ -304,25 +336,32 @@ impl std::fmt::Display for Value {
             Value::Foreign(_) => write!(f, "<foreign>"),
             Value::Null => write!(f, "null"),
             Value::Void => write!(f, "void"),
+            _ => unreachable!("a {} contains other values", self.name()),
         }
     }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Value) -> bool {
+        cycle::equal(self, other, &mut HashSet::new())
+    }
+}
+
+impl Value {
+    // compares values that aren't both pairs, lists, structs, vectors, boxes
+    // or tables
+    fn eq_leaf(&self, other: &Value) -> bool {
         match (self, other) {
             (Value::Boolean(a), Value::Boolean(b)) => *a == *b,
             (Value::Integer(a), Value::Integer(b)) => *a == *b,
-            (Value::Integer(a), Value::Float(b)) => (*a as f64) == *b,
-            (Value::Float(a), Value::Integer(b)) => *a == (*b as f64),
-            (Value::Float(a), Value::Float(b)) => *a == *b,
+            (
+                Value::Integer(_) | Value::BigInteger(_) | Value::Rational(_) | Value::Float(_),
+                Value::Integer(_) | Value::BigInteger(_) | Value::Rational(_) | Value::Float(_),
+            ) => Number::from_value(self) == Number::from_value(other),
             (Value::String(a), Value::String(b)) => *a == *b,
             (Value::Char(a), Value::Char(b)) => *a == *b,
-            (Value::List(a), Value::List(b)) => *a == *b,
-            (Value::Vector(a), Value::Vector(b)) => *a == *b,
             (Value::Symbol(a), Value::Symbol(b)) => *a == *b,
             (Value::Closure(a, _), Value::Closure(b, _)) => *a == *b,
-            (Value::Struct(a), Value::Struct(b)) => *a == *b,
             (Value::Function(a, c), Value::Function(b, d)) => c == d || *a == *b,
             (Value::Null, Value::Null) => true,
             (Value::List(a), Value::Null) => a.is_empty(),
@@ -414,6 +453,7 @@ impl FromValue for f64 {
         match value {
             Value::Float(f) => Some(*f),
             Value::Integer(i) => Some(*i as f64),
+            Value::BigInteger(_) | Value::Rational(_) => value.numeric(),
             _ => None,
         }
     }
@@ -423,8 +463,13 @@ impl FromValue for f64 {
     }
 }
 
+// natives such as the image and color functions compute with floats, and
+// hand integral results to the program as exact integers
 impl IntoValue for f64 {
     fn into_value(self) -> Option<Value> {
+        if self.fract() == 0.0 && (i64::MIN as f64..i64::MAX as f64).contains(&self) {
+            return Some(Value::Integer(self as i64));
+        }
         Some(Value::Float(self))
     }
 }
@@ -1,5 +1,11 @@
 use super::{Contract, FromValue, IntoValue, Value};
-use std::ops::{Add, Div, Mul, Rem, Sub};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Pow, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::rc::Rc;
 
 pub struct NonNegative;
 impl Contract for NonNegative {
@@ -25,211 +31,419 @@ impl Contract for Natural {
     }
 }
 
-/// A generic number type that can be converted to and from `Value`.
-#[derive(Copy, Clone)]
-pub struct Number(f64, /* is float */ bool);
+/// A number of the numeric tower: exact integers of any size, exact
+/// rationals, or inexact reals. Exact results are kept in their simplest form,
+/// so integers that fit in an `i64` are always `Integer` and rationals never
+/// have a denominator of 1.
+#[derive(Debug, Clone)]
+pub enum Number {
+    Integer(i64),
+    BigInteger(Rc<BigInt>),
+    Rational(Rc<BigRational>),
+    Float(f64),
+}
 
 impl Number {
-    pub fn new(value: f64) -> Self {
-        Number(value, value.fract() != 0.0)
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Number::Integer(i) => *i as f64,
+            Number::BigInteger(i) => i.to_f64().unwrap_or(f64::NAN),
+            Number::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            Number::Float(f) => *f,
+        }
     }
 
-    pub fn as_f64(self) -> f64 {
-        self.into()
+    pub fn is_exact(&self) -> bool {
+        !matches!(self, Number::Float(_))
     }
 
-    pub fn abs(self) -> Self {
-        Number(self.0.abs(), self.1)
+    pub fn is_integer(&self) -> bool {
+        match self {
+            Number::Integer(_) | Number::BigInteger(_) => true,
+            Number::Rational(_) => false,
+            Number::Float(f) => f.fract() == 0.0,
+        }
     }
-}
 
-impl std::fmt::Display for Number {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Integer(i) => *i == 0,
+            Number::Float(f) => *f == 0.0,
+            _ => false,
+        }
     }
-}
 
-// =
-impl PartialEq for Number {
-    fn eq(&self, other: &Number) -> bool {
-        self.0 == other.0
+    pub fn abs(&self) -> Self {
+        match self {
+            Number::Integer(i) => match i.checked_abs() {
+                Some(i) => Number::Integer(i),
+                None => Number::from_bigint(BigInt::from(*i).abs()),
+            },
+            Number::BigInteger(i) => Number::from_bigint(i.abs()),
+            Number::Rational(r) => Number::from_rational(r.abs()),
+            Number::Float(f) => Number::Float(f.abs()),
+        }
     }
-}
 
-impl PartialEq<f64> for Number {
-    fn eq(&self, other: &f64) -> bool {
-        self.0 == *other
+    /// The exact number closest to this one, or `None` for infinities and NaN.
+    pub fn to_exact(&self) -> Option<Self> {
+        match self {
+            Number::Float(f) => BigRational::from_float(*f).map(Number::from_rational),
+            n => Some(n.clone()),
+        }
     }
-}
 
-impl PartialEq<Number> for f64 {
-    fn eq(&self, other: &Number) -> bool {
-        *self == other.0
+    pub fn to_inexact(&self) -> Self {
+        Number::Float(self.as_f64())
     }
-}
 
-// +
-impl Add for Number {
-    type Output = Self;
+    /// The numerator of the number in lowest terms, inexact if it is.
+    pub fn numerator(&self) -> Option<Self> {
+        let exact = self.to_exact()?.to_rational()?;
+        let numerator = Number::from_bigint(exact.numer().clone());
+        Some(self.with_exactness(numerator))
+    }
 
-    fn add(self, other: Self) -> Self {
-        Number(self.0 + other.0, self.1 || other.1)
+    /// The denominator of the number in lowest terms, inexact if it is.
+    pub fn denominator(&self) -> Option<Self> {
+        let exact = self.to_exact()?.to_rational()?;
+        let denominator = Number::from_bigint(exact.denom().clone());
+        Some(self.with_exactness(denominator))
     }
-}
 
-impl Add<f64> for Number {
-    type Output = Self;
+    /// Divides the number by `other`, or returns `None` when dividing an exact
+    /// number by exact zero.
+    pub fn checked_div(&self, other: &Number) -> Option<Self> {
+        if other.is_exact() && other.is_zero() && self.is_exact() {
+            return None;
+        }
+        if let (Number::Integer(a), Number::Integer(b)) = (self, other) {
+            if a.checked_rem(*b) == Some(0) {
+                if let Some(quotient) = a.checked_div(*b) {
+                    return Some(Number::Integer(quotient));
+                }
+            }
+        }
+        match (self.to_rational(), other.to_rational()) {
+            (Some(a), Some(b)) => Some(Number::from_rational(a / b)),
+            _ => Some(Number::Float(self.as_f64() / other.as_f64())),
+        }
+    }
 
-    fn add(self, other: f64) -> Self {
-        Number::new(self.0 + other)
+    /// Integer division rounding towards zero. Returns `None` if either number
+    /// isn't an integer or `other` is zero.
+    pub fn quotient(&self, other: &Number) -> Option<Self> {
+        self.integer_division(other, |a, b| a / b, |a, b| (a / b).trunc())
     }
-}
 
-impl Add<Number> for f64 {
-    type Output = Number;
+    /// The remainder of `quotient`, which has the sign of the dividend.
+    pub fn remainder(&self, other: &Number) -> Option<Self> {
+        self.integer_division(other, |a, b| a % b, |a, b| a % b)
+    }
 
-    fn add(self, other: Number) -> Number {
-        Number::new(self + other.0)
+    /// The remainder of flooring division, which has the sign of the divisor.
+    pub fn modulo(&self, other: &Number) -> Option<Self> {
+        self.integer_division(other, |a, b| a.mod_floor(b), |a, b| a - b * (a / b).floor())
     }
-}
 
-// -
-impl Sub for Number {
-    type Output = Self;
+    pub fn floor(&self) -> Self {
+        self.round_with(|r| r.floor(), f64::floor)
+    }
 
-    fn sub(self, other: Self) -> Self {
-        Number(self.0 - other.0, self.1 || other.1)
+    pub fn ceiling(&self) -> Self {
+        self.round_with(|r| r.ceil(), f64::ceil)
     }
-}
 
-impl Sub<f64> for Number {
-    type Output = Self;
+    pub fn truncate(&self) -> Self {
+        self.round_with(|r| r.trunc(), f64::trunc)
+    }
 
-    fn sub(self, other: f64) -> Self {
-        Number::new(self.0 - other)
+    /// Rounds to the nearest integer, rounding halfway cases to the even one.
+    pub fn round(&self) -> Self {
+        self.round_with(
+            |r| {
+                let floor = r.floor();
+                let diff = r - &floor;
+                let half = BigRational::new(BigInt::from(1), BigInt::from(2));
+                if diff > half || (diff == half && floor.to_integer().is_odd()) {
+                    floor + BigRational::one()
+                } else {
+                    floor
+                }
+            },
+            f64::round_ties_even,
+        )
     }
-}
 
-impl Sub<Number> for f64 {
-    type Output = Number;
+    /// The square root of the number, which is exact if the number is the
+    /// square of an exact number.
+    pub fn sqrt(&self) -> Self {
+        if let Some(r) = self.to_rational() {
+            if !r.is_negative() {
+                let numer = r.numer().sqrt();
+                let denom = r.denom().sqrt();
+                if &numer * &numer == *r.numer() && &denom * &denom == *r.denom() {
+                    return Number::from_rational(BigRational::new(numer, denom));
+                }
+            }
+        }
+        Number::Float(self.as_f64().sqrt())
+    }
 
-    fn sub(self, other: Number) -> Number {
-        Number::new(self - other.0)
+    /// Raises the number to the power `exponent`, exactly if both are exact
+    /// and the exponent is an integer. Returns `None` when raising exact zero
+    /// to a negative power.
+    pub fn expt(&self, exponent: &Number) -> Option<Self> {
+        if let (Some(base), Some(power)) = (self.to_rational(), exponent.to_bigint()) {
+            if base.is_zero() && power.is_negative() {
+                return None;
+            }
+            // powers of 0, 1 and -1 stay small whatever the exponent
+            if base.is_zero() || base.abs().is_one() {
+                if base.is_negative() && power.is_even() {
+                    return Some(Number::Integer(1));
+                }
+                return Some(Number::from_rational(base));
+            }
+            if let Some(power) = power.to_i32() {
+                return Some(Number::from_rational(base.pow(power)));
+            }
+        }
+        Some(Number::Float(self.as_f64().powf(exponent.as_f64())))
+    }
+
+    /// Returns roughly how many bits the result of [`Number::expt`] takes up
+    /// when it is exact, so that callers can refuse to compute ones too large
+    /// to store.
+    pub fn expt_bits(&self, exponent: &Number) -> u64 {
+        match (self.to_rational(), exponent.to_bigint()) {
+            (Some(base), Some(power)) if !base.is_zero() && !base.abs().is_one() => {
+                let bits = base.numer().bits().max(base.denom().bits()) - 1;
+                power
+                    .magnitude()
+                    .to_u64()
+                    .map_or(u64::MAX, |power| power.saturating_mul(bits))
+            }
+            _ => 0,
+        }
     }
-}
 
-// *
-impl Mul for Number {
-    type Output = Self;
+    /// Parses an integer, a rational such as `1/3`, or a decimal number.
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Ok(i) = s.parse::<i64>() {
+            return Some(Number::Integer(i));
+        }
+        if let Ok(i) = s.parse::<BigInt>() {
+            return Some(Number::from_bigint(i));
+        }
+        if let Some((numer, denom)) = s.split_once('/') {
+            let numer = numer.parse::<BigInt>().ok()?;
+            let denom = denom.parse::<BigInt>().ok()?;
+            if denom.is_zero() {
+                return None;
+            }
+            return Some(Number::from_rational(BigRational::new(numer, denom)));
+        }
+        if s.contains('.') {
+            return s.parse::<f64>().ok().map(Number::Float);
+        }
+        None
+    }
 
-    fn mul(self, other: Self) -> Self {
-        Number::new(self.0 * other.0)
+    fn from_bigint(i: BigInt) -> Self {
+        match i.to_i64() {
+            Some(i) => Number::Integer(i),
+            None => Number::BigInteger(Rc::new(i)),
+        }
     }
-}
 
-impl Mul<f64> for Number {
-    type Output = Self;
+    fn from_rational(r: BigRational) -> Self {
+        if r.is_integer() {
+            Number::from_bigint(r.to_integer())
+        } else {
+            Number::Rational(Rc::new(r))
+        }
+    }
 
-    fn mul(self, other: f64) -> Self {
-        Number::new(self.0 * other)
+    // the exact value of the number, if it is exact
+    fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Number::Integer(i) => Some(BigRational::from_integer(BigInt::from(*i))),
+            Number::BigInteger(i) => Some(BigRational::from_integer(BigInt::clone(i))),
+            Number::Rational(r) => Some(BigRational::clone(r)),
+            Number::Float(_) => None,
+        }
     }
-}
 
-impl Mul<Number> for f64 {
-    type Output = Number;
+    // the value of the number, if it is an exact integer
+    fn to_bigint(&self) -> Option<BigInt> {
+        match self {
+            Number::Integer(i) => Some(BigInt::from(*i)),
+            Number::BigInteger(i) => Some(BigInt::clone(i)),
+            _ => None,
+        }
+    }
+
+    // `n`, made inexact if this number is
+    fn with_exactness(&self, n: Number) -> Number {
+        if self.is_exact() {
+            n
+        } else {
+            n.to_inexact()
+        }
+    }
 
-    fn mul(self, other: Number) -> Number {
-        Number::new(self * other.0)
+    // combines two numbers, exactly if both are exact and inexactly otherwise
+    fn combine(
+        &self,
+        other: &Number,
+        small: fn(i64, i64) -> Option<i64>,
+        exact: fn(BigRational, BigRational) -> BigRational,
+        inexact: fn(f64, f64) -> f64,
+    ) -> Number {
+        if let (Number::Integer(a), Number::Integer(b)) = (self, other) {
+            if let Some(n) = small(*a, *b) {
+                return Number::Integer(n);
+            }
+        }
+        match (self.to_rational(), other.to_rational()) {
+            (Some(a), Some(b)) => Number::from_rational(exact(a, b)),
+            _ => Number::Float(inexact(self.as_f64(), other.as_f64())),
+        }
     }
-}
 
-// /
-impl Div for Number {
-    type Output = Self;
+    fn integer_division(
+        &self,
+        other: &Number,
+        exact: fn(&BigInt, &BigInt) -> BigInt,
+        inexact: fn(f64, f64) -> f64,
+    ) -> Option<Number> {
+        if !self.is_integer() || !other.is_integer() || other.is_zero() {
+            return None;
+        }
+        match (self.to_bigint(), other.to_bigint()) {
+            (Some(a), Some(b)) => Some(Number::from_bigint(exact(&a, &b))),
+            _ => Some(Number::Float(inexact(self.as_f64(), other.as_f64()))),
+        }
+    }
 
-    fn div(self, other: Self) -> Self {
-        Number::new(self.0 / other.0)
+    fn round_with(&self, exact: fn(&BigRational) -> BigRational, inexact: fn(f64) -> f64) -> Self {
+        match self {
+            Number::Rational(r) => Number::from_rational(exact(r)),
+            Number::Float(f) => Number::Float(inexact(*f)),
+            n => n.clone(),
+        }
     }
 }
 
-impl Div<f64> for Number {
-    type Output = Self;
+impl std::fmt::Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Integer(i) => write!(f, "{}", i),
+            Number::BigInteger(i) => write!(f, "{}", i),
+            Number::Rational(r) => write!(f, "{}", r),
+            // inexact integers keep their decimal point to tell them apart
+            Number::Float(fl) if fl.is_finite() && fl.fract() == 0.0 => write!(f, "{:.1}", fl),
+            Number::Float(fl) => write!(f, "{}", fl),
+        }
+    }
+}
 
-    fn div(self, other: f64) -> Self {
-        Number::new(self.0 / other)
+// =
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
     }
 }
 
-impl Div<Number> for f64 {
-    type Output = Number;
+// <, <=, >, >=
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Integer(a), Number::Integer(b)) => Some(a.cmp(b)),
+            (Number::Float(a), Number::Float(b)) => a.partial_cmp(b),
+            (Number::Float(f), n) => compare_with_float(n, *f).map(Ordering::reverse),
+            (n, Number::Float(f)) => compare_with_float(n, *f),
+            _ => Some(self.to_rational()?.cmp(&other.to_rational()?)),
+        }
+    }
+}
 
-    fn div(self, other: Number) -> Number {
-        Number::new(self / other.0)
+// compares an exact number with a float by the float's exact value, since
+// rounding the exact number to a float would make large integers equal to
+// floats that aren't equal to each other
+fn compare_with_float(n: &Number, f: f64) -> Option<Ordering> {
+    match n {
+        _ if f.is_nan() => None,
+        _ if f == f64::INFINITY => Some(Ordering::Less),
+        _ if f == f64::NEG_INFINITY => Some(Ordering::Greater),
+        // integers up to 2^53 are exact as floats
+        Number::Integer(i) if i.unsigned_abs() <= 1 << 53 => (*i as f64).partial_cmp(&f),
+        n => Some(n.to_rational()?.cmp(&BigRational::from_float(f)?)),
     }
 }
 
-// %
-impl Rem for Number {
+// +
+impl Add for Number {
     type Output = Self;
 
-    fn rem(self, other: Self) -> Self {
-        Number::new(self.0 % other.0)
+    fn add(self, other: Self) -> Self {
+        self.combine(&other, i64::checked_add, |a, b| a + b, |a, b| a + b)
     }
 }
 
-impl Rem<f64> for Number {
+// -
+impl Sub for Number {
     type Output = Self;
 
-    fn rem(self, other: f64) -> Self {
-        Number::new(self.0 % other)
+    fn sub(self, other: Self) -> Self {
+        self.combine(&other, i64::checked_sub, |a, b| a - b, |a, b| a - b)
     }
 }
 
-impl Rem<Number> for f64 {
-    type Output = Number;
+impl Neg for Number {
+    type Output = Self;
 
-    fn rem(self, other: Number) -> Number {
-        Number::new(self % other.0)
+    fn neg(self) -> Self {
+        Number::Integer(0) - self
     }
 }
 
-// <, <=, >, >=
-impl PartialOrd for Number {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
-    }
-}
+// *
+impl Mul for Number {
+    type Output = Self;
 
-impl PartialOrd<f64> for Number {
-    fn partial_cmp(&self, other: &f64) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(other)
+    fn mul(self, other: Self) -> Self {
+        self.combine(&other, i64::checked_mul, |a, b| a * b, |a, b| a * b)
     }
 }
 
-impl PartialOrd<Number> for f64 {
-    fn partial_cmp(&self, other: &Number) -> Option<std::cmp::Ordering> {
-        self.partial_cmp(&other.0)
+// conversions
+impl From<i64> for Number {
+    fn from(value: i64) -> Self {
+        Number::Integer(value)
     }
 }
 
-// conversions
 impl From<f64> for Number {
     fn from(value: f64) -> Self {
-        Number::new(value)
+        Number::Float(value)
     }
 }
 
 impl From<Number> for f64 {
     fn from(value: Number) -> Self {
-        value.0
+        value.as_f64()
     }
 }
 
 impl FromValue for Number {
     fn from_value(value: &Value) -> Option<Self> {
         match value {
-            Value::Integer(i) => Some(Number(*i as f64, false)),
-            Value::Float(f) => Some(Number(*f, true)),
+            Value::Integer(i) => Some(Number::Integer(*i)),
+            Value::BigInteger(i) => Some(Number::BigInteger(Rc::clone(i))),
+            Value::Rational(r) => Some(Number::Rational(Rc::clone(r))),
+            Value::Float(f) => Some(Number::Float(*f)),
             _ => None,
         }
     }
@@ -241,11 +455,12 @@ impl FromValue for Number {
 
 impl IntoValue for Number {
     fn into_value(self) -> Option<Value> {
-        if self.1 {
-            Some(Value::Float(self.0))
-        } else {
-            Some(Value::Integer(self.0 as i64))
-        }
+        Some(match self {
+            Number::Integer(i) => Value::Integer(i),
+            Number::BigInteger(i) => Value::BigInteger(i),
+            Number::Rational(r) => Value::Rational(r),
+            Number::Float(f) => Value::Float(f),
+        })
     }
 }
 
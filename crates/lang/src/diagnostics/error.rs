@@ -32,26 +32,26 @@ impl ParseError {
         }
     }
 
-    // pub fn warn<S: Into<String>>(message: S, span: Option<Span>) -> Self {
-    //     Self {
-    //         level: ErrorLevel::Warning,
-    //         code: None,
-    //         message: message.into(),
-    //         span,
-    //         help: None,
-    //         note: None,
-    //     }
-    // }
+    pub fn warn<S: Into<String>>(message: S, span: Option<Span>) -> Self {
+        Self {
+            level: ErrorLevel::Warning,
+            code: None,
+            message: message.into(),
+            span,
+            help: None,
+            note: None,
+        }
+    }
 
     pub fn with_code(mut self, code: u32) -> Self {
         self.code = Some(ErrorCode::new(code));
         self
     }
 
-    // pub fn with_help(mut self, help: impl Into<String>) -> Self {
-    //     self.help = Some(help.into());
-    //     self
-    // }
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
 
     // pub fn with_note(mut self, note: impl Into<String>) -> Self {
     //     self.note = Some(note.into());
@@ -114,7 +114,7 @@ impl ParseError {
         let line_span = if let Some(span) = self.span {
             let loc = span.loc as usize;
             if loc >= src.len() {
-                return format!("Parser error: {}", self.message);
+                return format!("{}: {}", self.web_title(), self.message);
             }
 
             let end = loc + span.len as usize;
@@ -142,7 +142,8 @@ impl ParseError {
         });
 
         format!(
-            "Parser error{}: {}",
+            "{}{}: {}",
+            self.web_title(),
             if let Some(line_span) = line_span {
                 format!(" [{}]", line_span)
             } else {
@@ -152,6 +153,13 @@ impl ParseError {
         )
     }
 
+    fn web_title(&self) -> &'static str {
+        match self.level {
+            ErrorLevel::Warning => "Warning",
+            _ => "Parser error",
+        }
+    }
+
     pub fn emit(&self, file: &str, src: &str) {
         eprintln!("{}", self.emit_to_string(file, src));
     }
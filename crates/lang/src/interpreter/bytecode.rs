@@ -0,0 +1,148 @@
+use std::rc::Rc;
+
+use crate::ast::{Pattern, Span, Value};
+
+/// A single VM instruction. Jump targets are indices into the instructions of
+/// the code object the instruction belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// Pushes a value from the constant pool.
+    Constant(u32),
+    /// Pushes a copy of a value from the constant pool, with vectors of its
+    /// own that mutating doesn't change the constant.
+    CopyConstant(u32),
+    Local {
+        depth: u32,
+        index: u32,
+    },
+    Global(u32),
+    SetLocal {
+        depth: u32,
+        index: u32,
+    },
+    SetGlobal(u32),
+    /// Pushes a closure over one of the nested code objects.
+    Closure(u32),
+    /// Calls the function below `arity` arguments on the stack.
+    Call(u32),
+    /// Calls a function in place of the current frame.
+    TailCall(u32),
+    Return,
+    Pop,
+    Jump(u32),
+    /// Pops the guard of an `if` and jumps if it is false.
+    If(u32),
+    /// Pops the guard of a `cond` branch and jumps if it is false.
+    Cond(u32),
+    /// Pops an operand of `and`; if it is false, pushes it back and jumps.
+    And(u32),
+    /// Pops an operand of `or`; if it is true, pushes it back and jumps.
+    Or(u32),
+    /// Pops `n` values into a new frame of local variables.
+    EnterScope(u32),
+    /// Returns to the frame of local variables the current one extends.
+    ExitScope,
+    /// Matches the value below the predicates of a branch pattern against the
+    /// pattern. The predicates are always popped; on success the value is too
+    /// and the pattern's variables are bound in a new frame, otherwise it jumps
+    /// to `next`.
+    Match {
+        pattern: u32,
+        next: u32,
+    },
+    /// Pops the value no branch of a `match` accepted and fails.
+    NoMatch,
+    /// Fails with a message from the constant pool.
+    Fail(u32),
+}
+
+/// A compiled block of code, either a top-level statement or a lambda body.
+/// Code objects are immutable once compiled and shared by every closure made
+/// from them.
+#[derive(Debug, Default)]
+pub struct Code {
+    pub params: Vec<String>,
+    /// How many of the leading parameters must be passed.
+    pub required: usize,
+    /// Whether the last parameter collects the remaining arguments in a list.
+    pub rest: bool,
+    /// Where to start running for each number of optional parameters passed.
+    /// The instructions before each entry compute the defaults of the optional
+    /// parameters that weren't.
+    pub entries: Vec<u32>,
+    pub instructions: Vec<Instruction>,
+    /// The source location of each instruction, for error messages.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    /// Code objects of the lambdas created by `Closure` instructions.
+    pub functions: Vec<Rc<Code>>,
+    pub patterns: Vec<BranchPattern>,
+}
+
+/// The pattern of a `match` branch, along with the number of variables it
+/// binds and of predicates it expects on the stack.
+#[derive(Debug)]
+pub struct BranchPattern {
+    pub pattern: Pattern,
+    pub slots: usize,
+    pub predicates: usize,
+}
+
+impl Code {
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    /// Arranges the arguments of a call into the local variables the code runs
+    /// with, returning them along with the instruction to start at. Fails if
+    /// the code doesn't accept that many arguments, naming the function `name`
+    /// in the message.
+    pub fn bind(
+        &self,
+        mut args: Vec<Value>,
+        name: Option<&str>,
+    ) -> Result<(Vec<Value>, usize), String> {
+        let optional = self.entries.len().saturating_sub(1);
+        let max = self.required + optional;
+        let passed = args.len();
+        if passed < self.required || (!self.rest && passed > max) {
+            return Err(self.arity_error(name, passed));
+        }
+
+        let rest = self.rest.then(|| args.split_off(passed.min(max)));
+        let entry = self.entries.get(args.len() - self.required);
+        // parameters whose defaults are computed on entry
+        args.resize(max, Value::Void);
+        if let Some(rest) = rest {
+            args.push(match rest.is_empty() {
+                true => Value::Null,
+                false => Value::List(rest),
+            });
+        }
+        Ok((args, entry.map_or(0, |&pc| pc as usize)))
+    }
+
+    fn arity_error(&self, name: Option<&str>, passed: usize) -> String {
+        let optional = self.entries.len().saturating_sub(1);
+        let expected = if self.rest {
+            format!("at least {}", self.required)
+        } else if optional > 0 {
+            format!("{} to {}", self.required, self.required + optional)
+        } else {
+            self.required.to_string()
+        };
+        let noun = match expected.as_str() {
+            "1" | "at least 1" => "argument",
+            _ => "arguments",
+        };
+        let function = match name {
+            Some(name) => format!("Function `{}`", crate::parser::base_name(name)),
+            None => "Function".to_string(),
+        };
+        format!("{function} expected {expected} {noun}, passed {passed} instead")
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::bytecode::{BranchPattern, Code, Instruction};
+use super::RuntimeError;
+use crate::ast::*;
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Compiles a resolved block into a code object that returns the block's value.
+pub fn compile(block: Block, params: Params) -> Result<Rc<Code>> {
+    let Params {
+        names,
+        required,
+        defaults,
+        rest,
+    } = params;
+    let mut compiler = Compiler {
+        code: Code {
+            params: names,
+            required,
+            rest,
+            ..Code::default()
+        },
+        labels: HashMap::new(),
+        patches: Vec::new(),
+    };
+
+    // calls start at the default of the first optional parameter not passed,
+    // and compute the rest in order
+    for (i, default) in defaults.into_iter().enumerate() {
+        let entry = compiler.here();
+        compiler.code.entries.push(entry);
+        let span = default.last().map_or(Span::empty(), |op| op.span);
+        compiler.block(default, false, span)?;
+        compiler.emit(
+            Instruction::SetLocal {
+                depth: 0,
+                index: (required + i) as u32,
+            },
+            span,
+        );
+        compiler.emit(Instruction::Pop, span);
+    }
+    if !compiler.code.entries.is_empty() {
+        let entry = compiler.here();
+        compiler.code.entries.push(entry);
+    }
+
+    compiler.block(block, true, Span::empty())?;
+    compiler.finish()
+}
+
+fn has_vectors(value: &Value) -> bool {
+    match value {
+        Value::Vector(_) => true,
+        Value::List(values) => values.iter().any(has_vectors),
+        Value::Pair(head, tail) => has_vectors(head) || has_vectors(tail),
+        _ => false,
+    }
+}
+
+struct Compiler {
+    code: Code,
+    // instruction index each label of the block marks
+    labels: HashMap<Label, u32>,
+    // jumps to labels that may not have been reached yet
+    patches: Vec<(usize, Label)>,
+}
+
+impl Compiler {
+    fn finish(mut self) -> Result<Rc<Code>> {
+        for (at, label) in std::mem::take(&mut self.patches) {
+            let Some(&target) = self.labels.get(&label) else {
+                return Err(RuntimeError::new(
+                    format!("Label `{}` not found", label),
+                    Some(self.code.spans[at]),
+                ));
+            };
+            self.patch(at, target);
+        }
+        Ok(Rc::new(self.code))
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.code.instructions.push(instruction);
+        self.code.spans.push(span);
+        self.code.instructions.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        self.code.instructions.len() as u32
+    }
+
+    // points the jump at `at` to `target`
+    fn patch(&mut self, at: usize, target: u32) {
+        use Instruction::*;
+        self.code.instructions[at] = match self.code.instructions[at] {
+            Jump(_) => Jump(target),
+            If(_) => If(target),
+            Cond(_) => Cond(target),
+            And(_) => And(target),
+            Or(_) => Or(target),
+            Match { pattern, .. } => Match {
+                pattern,
+                next: target,
+            },
+            instruction => instruction,
+        };
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.code.constants.push(value);
+        self.code.constants.len() as u32 - 1
+    }
+
+    // compiles a block; blocks in tail position return from the code object
+    // themselves, so that calls at their end can replace the caller's frame
+    fn block(&mut self, block: Block, tail: bool, span: Span) -> Result<()> {
+        // only labels may follow an operation in tail position
+        let last = block
+            .iter()
+            .rposition(|op| !matches!(op.kind, OperationKind::Label { .. }));
+        let label_positions = block
+            .iter()
+            .enumerate()
+            .filter_map(|(i, op)| match &op.kind {
+                OperationKind::Label { name } => Some((name.clone(), i)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        let in_tail = |i: usize| tail && last.is_none_or(|last| i >= last);
+
+        let end_span = block.last().map_or(span, |op| op.span);
+        for (i, op) in block.into_iter().enumerate() {
+            let tail = in_tail(i);
+            self.operation(op, tail, &label_positions, &in_tail)?;
+        }
+        if tail {
+            self.emit(Instruction::Return, end_span);
+        }
+        Ok(())
+    }
+
+    fn operation(
+        &mut self,
+        op: Operation,
+        tail: bool,
+        labels: &HashMap<Label, usize>,
+        in_tail: &dyn Fn(usize) -> bool,
+    ) -> Result<()> {
+        let span = op.span;
+        match op.kind {
+            // each evaluation of a literal with vectors in it gets new ones
+            OperationKind::Value { value } if has_vectors(&value) => {
+                let index = self.constant(value);
+                self.emit(Instruction::CopyConstant(index), span);
+            }
+            OperationKind::Value { value } => {
+                let index = self.constant(value);
+                self.emit(Instruction::Constant(index), span);
+            }
+            OperationKind::Variable { name } | OperationKind::Set { name } => {
+                return Err(RuntimeError::new(
+                    format!("Identifier `{name}` was not resolved before execution"),
+                    Some(span),
+                ));
+            }
+            OperationKind::Local { depth, index } => {
+                self.emit(
+                    Instruction::Local {
+                        depth: depth as u32,
+                        index: index as u32,
+                    },
+                    span,
+                );
+            }
+            OperationKind::Global { index } => {
+                self.emit(Instruction::Global(index as u32), span);
+            }
+            OperationKind::SetLocal { depth, index } => {
+                self.emit(
+                    Instruction::SetLocal {
+                        depth: depth as u32,
+                        index: index as u32,
+                    },
+                    span,
+                );
+            }
+            OperationKind::SetGlobal { index } => {
+                self.emit(Instruction::SetGlobal(index as u32), span);
+            }
+            OperationKind::Closure { params, body } => {
+                let code = compile(body, params)?;
+                self.code.functions.push(code);
+                let index = self.code.functions.len() as u32 - 1;
+                self.emit(Instruction::Closure(index), span);
+            }
+            OperationKind::Application { arity } => {
+                if tail {
+                    self.emit(Instruction::TailCall(arity), span);
+                } else {
+                    self.emit(Instruction::Call(arity), span);
+                }
+            }
+            OperationKind::Pop => {
+                self.emit(Instruction::Pop, span);
+            }
+            OperationKind::If {
+                if_block,
+                else_block,
+            } => {
+                let guard = self.emit(Instruction::If(0), span);
+                self.block(if_block, tail, span)?;
+                let end = (!tail).then(|| self.emit(Instruction::Jump(0), span));
+                let target = self.here();
+                self.patch(guard, target);
+                self.block(else_block, tail, span)?;
+                if let Some(end) = end {
+                    let target = self.here();
+                    self.patch(end, target);
+                }
+            }
+            OperationKind::Let { names, body } => {
+                self.emit(Instruction::EnterScope(names.len() as u32), span);
+                self.block(body, tail, span)?;
+                if !tail {
+                    self.emit(Instruction::ExitScope, span);
+                }
+            }
+            OperationKind::Match {
+                branches,
+                scrutinee,
+            } => {
+                let mut ends = Vec::new();
+                for branch in branches {
+                    let predicates = branch.predicates.len();
+                    for predicate in branch.predicates {
+                        self.block(predicate, false, span)?;
+                    }
+                    self.code.patterns.push(BranchPattern {
+                        pattern: branch.pattern,
+                        slots: branch.variables.len(),
+                        predicates,
+                    });
+                    let pattern = self.code.patterns.len() as u32 - 1;
+                    let test = self.emit(Instruction::Match { pattern, next: 0 }, span);
+                    self.block(branch.body, tail, span)?;
+                    if !tail {
+                        self.emit(Instruction::ExitScope, span);
+                        ends.push(self.emit(Instruction::Jump(0), span));
+                    }
+                    let next = self.here();
+                    self.patch(test, next);
+                }
+                self.emit(Instruction::NoMatch, scrutinee);
+                let target = self.here();
+                for end in ends {
+                    self.patch(end, target);
+                }
+            }
+            OperationKind::And { jump_to } => {
+                let at = self.emit(Instruction::And(0), span);
+                self.patches.push((at, jump_to));
+            }
+            OperationKind::Or { jump_to } => {
+                let at = self.emit(Instruction::Or(0), span);
+                self.patches.push((at, jump_to));
+            }
+            OperationKind::Cond { body, end } => {
+                // the body finishes the whole cond, so it is in tail position
+                // if the cond is
+                let tail = labels.get(&end).is_some_and(|&i| in_tail(i));
+                let guard = self.emit(Instruction::Cond(0), span);
+                self.block(body, tail, span)?;
+                if !tail {
+                    let at = self.emit(Instruction::Jump(0), span);
+                    self.patches.push((at, end));
+                }
+                let next = self.here();
+                self.patch(guard, next);
+            }
+            OperationKind::Label { name } => {
+                let here = self.here();
+                self.labels.insert(name, here);
+            }
+            OperationKind::Exception {
+                message,
+                mod_name,
+                span: location,
+                source,
+            } => {
+                let message = format!(
+                    "{}{}{}",
+                    if let Some(mod_name) = mod_name {
+                        format!("[{mod_name}] ")
+                    } else {
+                        String::default()
+                    },
+                    if let Some(source) = source {
+                        format!("{}: ", source)
+                    } else {
+                        String::default()
+                    },
+                    message,
+                );
+                let index = self.constant(Value::String(message));
+                self.emit(Instruction::Fail(index), location.unwrap_or(span));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,32 +1,63 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use super::eval::{Output, Runner};
-use super::Env;
+use super::eval::{Output, Runner, DEFAULT_MEMORY_LIMIT};
+use super::Globals;
 use crate::diagnostics::ParseError;
-use crate::parser::parse;
+use crate::modules::{FileLoader, Files};
+use crate::parser::{parse_with, resolve_module, Macros};
 
 pub struct Engine {
-    env: Rc<RefCell<Env>>,
+    globals: Rc<RefCell<Globals>>,
+    memory_limit: usize,
+    files: Rc<Files>,
+    // macros defined by earlier runs
+    macros: RefCell<Macros>,
 }
 
 impl Engine {
     /// Instantiates a new engine instance with prelude imported.
     pub fn new() -> Self {
         Self {
-            env: Rc::new(RefCell::new(Env::new(None))),
+            globals: Rc::new(RefCell::new(Globals::new())),
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            files: Rc::default(),
+            macros: RefCell::default(),
         }
     }
 
+    /// Sets where the files imported with `(import "path")` are read from.
+    /// Without a loader, file imports fail.
+    pub fn with_loader(mut self, loader: impl FileLoader + 'static) -> Self {
+        self.files = Rc::new(Files::new(loader));
+        self
+    }
+
+    /// Sets the number of bytes a running program may use for its call stack.
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
     pub fn run(&self, code: &str) -> Result<Vec<Output>, ParseError> {
-        let ast = parse(&code)?;
-        let mut interpreter = Runner::new(ast, Some(self.env.clone()));
+        let parsed = parse_with(code, self.macros.borrow().clone(), &self.files)?;
+        let resolution = resolve_module(parsed.ast, &mut self.globals.borrow_mut(), &self.files)?;
+        *self.macros.borrow_mut() = parsed.macros;
+        let mut interpreter = Runner::new(resolution.ast, Some(self.globals.clone()))
+            .with_files(Rc::clone(&self.files))
+            .with_memory_limit(self.memory_limit);
         interpreter.execute();
-        Ok(interpreter.get_output())
+        let warnings = resolution.warnings.into_iter().map(Output::Warning);
+        Ok(warnings.chain(interpreter.get_output()).collect())
     }
 
-    pub fn check_syntax(&self, code: &str) -> Result<(), ParseError> {
-        parse(&code)?;
-        Ok(())
+    /// Checks that a program parses and resolves without running it,
+    /// returning any warnings about it.
+    pub fn check_syntax(&self, code: &str) -> Result<Vec<ParseError>, ParseError> {
+        let parsed = parse_with(code, self.macros.borrow().clone(), &self.files)?;
+        // resolve against a copy so that checking doesn't declare anything
+        let resolution =
+            resolve_module(parsed.ast, &mut self.globals.borrow().clone(), &self.files)?;
+        Ok(resolution.warnings)
     }
 }
@@ -1,36 +1,32 @@
 use super::RuntimeError;
 use crate::ast::{IntoValue, NativeFn, Value};
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, fmt::Debug, rc::Rc};
 
-#[derive(Debug, Clone)]
+thread_local! {
+    // built once and shared by every global table on the thread
+    static PRELUDE: Rc<Env> = {
+        let mut env = Env::new();
+        crate::modules::prelude::add_to(&mut env);
+        Rc::new(env)
+    };
+}
+
+/// A set of named bindings, such as the definitions a module provides.
+#[derive(Debug, Clone, Default)]
 pub struct Env {
     bindings: HashMap<String, Value>,
-    parent: Option<Box<Env>>,
 }
 
 impl Env {
-    pub fn new(parent: Option<Env>) -> Self {
-        let mut env = Self {
-            bindings: HashMap::new(),
-            parent: parent.map(Box::new),
-        };
-
-        crate::modules::prelude::add_to(&mut env);
-
-        env
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    pub fn get<K>(&self, key: K) -> Option<&Value>
+    pub fn get<K>(&self, key: K) -> Option<Value>
     where
         K: AsRef<str>,
     {
-        let value = self.bindings.get(key.as_ref());
-        if value.is_none() {
-            if let Some(parent) = &self.parent {
-                return parent.get(key);
-            }
-        }
-        value
+        self.bindings.get(key.as_ref()).cloned()
     }
 
     pub fn set(&mut self, key: String, value: Value) {
@@ -52,11 +48,199 @@ impl Env {
         return true;
     }
 
-    pub fn extend(&self, bindings: impl IntoIterator<Item = (String, Value)>) -> Env {
-        let mut new_env = self.clone();
-        for (name, value) in bindings.into_iter() {
-            new_env.set(name.clone(), value.clone());
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.bindings.keys()
+    }
+}
+
+impl IntoIterator for Env {
+    type Item = (String, Value);
+    type IntoIter = std::collections::hash_map::IntoIter<String, Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.bindings.into_iter()
+    }
+}
+
+#[derive(Clone)]
+struct GlobalSlot {
+    name: String,
+    // unset until the defining statement has run
+    value: Option<Value>,
+    // bound by the prelude and not redefined since
+    builtin: bool,
+}
+
+/// Top-level bindings, addressed by the slot indices the resolver assigns.
+#[derive(Clone)]
+pub struct Globals {
+    indices: HashMap<String, usize>,
+    slots: Vec<GlobalSlot>,
+}
+
+impl Globals {
+    /// Creates a table holding the prelude.
+    pub fn new() -> Self {
+        let mut globals = Self {
+            indices: HashMap::new(),
+            slots: Vec::new(),
+        };
+        PRELUDE.with(|prelude| {
+            for (name, value) in prelude.bindings.iter() {
+                let index = globals.declare(name);
+                globals.slots[index].value = Some(value.clone());
+                globals.slots[index].builtin = true;
+            }
+        });
+        globals
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).copied()
+    }
+
+    /// Returns the slot for `name`, adding an unset one if it doesn't exist yet.
+    pub fn declare(&mut self, name: &str) -> usize {
+        if let Some(index) = self.index_of(name) {
+            return index;
+        }
+        let index = self.slots.len();
+        self.indices.insert(name.to_string(), index);
+        self.slots.push(GlobalSlot {
+            name: name.to_string(),
+            value: None,
+            builtin: false,
+        });
+        index
+    }
+
+    /// Removes every slot declared after the table had `len` slots.
+    pub fn truncate(&mut self, len: usize) {
+        for slot in self.slots.drain(len..) {
+            self.indices.remove(&slot.name);
+        }
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        &self.slots[index].name
+    }
+
+    pub fn is_builtin(&self, index: usize) -> bool {
+        self.slots[index].builtin
+    }
+
+    pub fn get(&self, index: usize) -> Option<Value> {
+        self.slots.get(index)?.value.clone()
+    }
+
+    /// Binds `name` to `value`, replacing any previous definition.
+    pub fn define(&mut self, name: &str, value: Value) {
+        let index = self.declare(name);
+        self.slots[index].value = Some(value);
+        self.slots[index].builtin = false;
+    }
+
+    pub fn define_all(&mut self, env: Env) {
+        for (name, value) in env {
+            self.define(&name, value);
+        }
+    }
+
+    /// The bindings that weren't bound by the prelude, with their values,
+    /// skipping the ones that haven't been defined yet.
+    pub fn defined(&self) -> impl Iterator<Item = (&str, Value)> {
+        self.slots
+            .iter()
+            .filter(|slot| !slot.builtin)
+            .filter_map(|slot| Some((slot.name.as_str(), slot.value.clone()?)))
+    }
+
+    /// Replaces the value of a defined global, returning `false` if it hasn't
+    /// been defined yet.
+    pub fn set(&mut self, index: usize, value: Value) -> bool {
+        match &mut self.slots[index].value {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Default for Globals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Globals {
+    // closures refer back to the table, so the values themselves aren't printed
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Globals")
+            .field("len", &self.slots.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A frame of local variables, addressed by the slot indices the resolver
+/// assigns. Frames are shared between every scope and closure that can see them.
+#[derive(Debug)]
+pub struct Scope {
+    values: Vec<Value>,
+    parent: Option<Rc<RefCell<Scope>>>,
+}
+
+impl Scope {
+    pub fn new(values: Vec<Value>, parent: Option<Rc<RefCell<Scope>>>) -> Self {
+        Self { values, parent }
+    }
+
+    /// The number of variables in the frame.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The frame this one extends.
+    pub fn parent(&self) -> Option<Rc<RefCell<Scope>>> {
+        self.parent.clone()
+    }
+
+    /// Looks up the slot `index` in the frame `depth` levels up from this one.
+    pub fn get(&self, depth: usize, index: usize) -> Option<Value> {
+        if depth == 0 {
+            self.values.get(index).cloned()
+        } else {
+            self.parent.as_ref()?.borrow().get(depth - 1, index)
+        }
+    }
+
+    pub fn set(&mut self, depth: usize, index: usize, value: Value) -> bool {
+        if depth == 0 {
+            match self.values.get_mut(index) {
+                Some(slot) => {
+                    *slot = value;
+                    true
+                }
+                None => false,
+            }
+        } else {
+            match &self.parent {
+                Some(parent) => parent.borrow_mut().set(depth - 1, index, value),
+                None => false,
+            }
         }
-        new_env
     }
 }
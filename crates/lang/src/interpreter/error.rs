@@ -1,14 +1,59 @@
-use crate::ast::Span;
+use super::eval::Control;
+use crate::ast::{FromValue, IntoValue, Span, Value};
+use std::rc::Rc;
+
+// maximum number of stack frames printed in a backtrace
+const MAX_PRINTED_FRAMES: usize = 10;
 
 fn count_lines_up_to(s: &str, up_to: usize) -> usize {
     s[..up_to].chars().filter(|&c| c == '\n').count() + 1
 }
 
+/// A procedure call that was active when a runtime error occurred.
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub name: Option<String>,
+    // location of the call
+    pub span: Option<Span>,
+}
+
+/// The condition passed to an exception handler for errors that weren't
+/// raised with an explicit value, e.g. calls to `error` or failing natives.
+#[derive(Debug, Clone)]
+pub struct ErrorObject {
+    pub message: String,
+}
+
+impl IntoValue for ErrorObject {
+    fn into_value(self) -> Option<Value> {
+        Some(Value::Foreign(Rc::new(self)))
+    }
+}
+
+impl FromValue for ErrorObject {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Foreign(f) => f.downcast_ref::<ErrorObject>().cloned(),
+            _ => None,
+        }
+    }
+
+    fn name() -> &'static str {
+        "error object"
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RuntimeError {
     pub message: String,
     pub namespace: Option<String>,
     pub span: Option<Span>,
+    // active calls at the time of the error, innermost first
+    pub stack_trace: Vec<StackFrame>,
+    // value passed to `raise`, if the error was raised by the program
+    pub raised: Option<Box<Value>>,
+    // a transfer of control that isn't an error, unless nothing carries it out
+    pub(crate) control: Option<Box<Control>>,
 }
 
 impl RuntimeError {
@@ -17,6 +62,43 @@ impl RuntimeError {
             message,
             namespace: None,
             span,
+            stack_trace: Vec::new(),
+            raised: None,
+            control: None,
+        }
+    }
+
+    /// Creates an error that transfers control to a continuation.
+    pub(crate) fn control(control: Control) -> Self {
+        let message = match control {
+            Control::Capture(_) => "call/cc must be called directly by the program",
+            Control::Resume(..) => "continuation resumed outside of the program that captured it",
+        };
+        Self {
+            control: Some(Box::new(control)),
+            ..Self::new(message.to_string(), None)
+        }
+    }
+
+    /// Creates an error that carries an arbitrary raised value.
+    pub fn raise(value: Value) -> Self {
+        let message = match ErrorObject::from_value(&value) {
+            Some(object) => object.message,
+            None => format!("uncaught exception: {}", value),
+        };
+        Self {
+            raised: Some(Box::new(value)),
+            ..Self::new(message, None)
+        }
+    }
+
+    /// The value an exception handler receives for this error.
+    pub fn condition(&self) -> Value {
+        match &self.raised {
+            Some(value) => value.as_ref().clone(),
+            None => Value::Foreign(Rc::new(ErrorObject {
+                message: self.message.clone(),
+            })),
         }
     }
 
@@ -25,13 +107,14 @@ impl RuntimeError {
             let loc = span.loc as usize;
             if loc >= src.len() {
                 return format!(
-                    "Runtime error: {}{}",
+                    "Runtime error: {}{}{}",
                     if let Some(namespace) = &self.namespace {
                         format!("({}) ", namespace)
                     } else {
                         String::default()
                     },
-                    self.message
+                    self.message,
+                    self.emit_stack_trace(src)
                 );
             }
 
@@ -55,7 +138,7 @@ impl RuntimeError {
         };
 
         return format!(
-            "Runtime error{}: {}{}.",
+            "Runtime error{}: {}{}.{}",
             if let Some(line_span) = line_span {
                 format!(" [{}]", line_span)
             } else {
@@ -66,10 +149,36 @@ impl RuntimeError {
             } else {
                 String::default()
             },
-            self.message
+            self.message,
+            self.emit_stack_trace(src)
         );
     }
 
+    // racket-style list of the calls that led to the error
+    fn emit_stack_trace(&self, src: &str) -> String {
+        if self.stack_trace.is_empty() {
+            return String::default();
+        }
+
+        let mut lines = vec![String::from("\n  context...:")];
+        for frame in self.stack_trace.iter().take(MAX_PRINTED_FRAMES) {
+            let name = frame.name.as_deref().unwrap_or("<anonymous procedure>");
+            match frame.span.map(|span| span.loc as usize) {
+                Some(loc) if loc < src.len() => {
+                    let line = count_lines_up_to(src, loc);
+                    let col = loc - src[..loc].rfind('\n').unwrap_or(0);
+                    lines.push(format!("   {}:{} {}", line, col, name));
+                }
+                _ => lines.push(format!("   {}", name)),
+            }
+        }
+        if self.stack_trace.len() > MAX_PRINTED_FRAMES {
+            lines.push(String::from("   ..."));
+        }
+
+        lines.join("\n")
+    }
+
     pub fn emit(&self, src: &str) {
         eprintln!("{}", self.emit_to_string(src));
     }
@@ -1,72 +1,220 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::fmt;
 use std::rc::Rc;
 use std::vec::IntoIter;
 
-use super::{Env, RuntimeError};
+use super::bytecode::{Code, Instruction};
+use super::{compile, Env, Globals, RuntimeError, Scope, StackFrame};
 use crate::ast::*;
-use crate::parser::keyword::RESERVED_WORDS;
+use crate::diagnostics::ParseError;
+use crate::modules::Files;
 
-// wasm has a smaller stack size in debug mode
+// native functions that call back into the program (map, filter, ...) still
+// recurse on the rust stack, and wasm has a smaller stack size in debug mode
 #[cfg(debug_assertions)]
-const MAX_CALL_STACK_DEPTH: usize = 25;
+const MAX_CALL_STACK_DEPTH: usize = 64;
 
 #[cfg(not(debug_assertions))]
 const MAX_CALL_STACK_DEPTH: usize = 1000;
 
+/// Default number of bytes the frames and values of an execution stack may take up.
+pub const DEFAULT_MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+
+thread_local! {
+    // number of native functions running calls back into the program on this thread
+    static RUN_DEPTH: Cell<usize> = const { Cell::new(0) };
+    // identifies the next execution stack created on this thread
+    static NEXT_STACK_ID: Cell<u64> = const { Cell::new(0) };
+    // the stack a native function was called from while it runs, which the
+    // calls it makes back into the program run on
+    static CURRENT: Cell<Option<ExecutionStack>> = const { Cell::new(None) };
+}
+
 type Result<T> = std::result::Result<T, RuntimeError>;
 
 #[derive(Debug, Clone)]
 pub enum Output {
     Value(Value),
     Error(RuntimeError),
+    /// Something suspicious about the program that was found before it ran.
+    Warning(ParseError),
+}
+
+// a code object being executed, along with the local variables it can see
+#[derive(Clone)]
+struct Frame {
+    code: Rc<Code>,
+    // index of the next instruction
+    pc: usize,
+    scope: Option<Rc<RefCell<Scope>>>,
+    // the globals of the program the code belongs to
+    globals: Rc<RefCell<Globals>>,
+    // the procedure call the code is running for, if any
+    call: Option<StackFrame>,
+    // bytes taken up by the local variables of the scopes the frame created
+    memory: usize,
+    // set on the frame of a native function, whose calls back into the
+    // program run in frames above it
+    native: Option<Native>,
+}
+
+// the call of a native function that a frame belongs to, which is shared with
+// the running function. Continuations get a copy of it when they copy the frame.
+struct Native(Rc<NativeCall>);
+
+impl Clone for Native {
+    fn clone(&self) -> Self {
+        Self(Rc::new(NativeCall::clone(&self.0)))
+    }
+}
+
+#[derive(Clone)]
+struct NativeCall {
+    // identifies the call
+    id: u64,
+    function: NativeFn,
+    args: Vec<Value>,
+    // what receives the result of the call
+    caller: Caller,
+    // the height of the value stack when the call was made
+    height: usize,
+    // the results of the calls the function made back into the program, so
+    // that it can run again up to where a continuation returns into it after
+    // it has finished
+    results: RefCell<Vec<std::result::Result<Value, Box<RuntimeError>>>>,
+    // the number of calls the function has made back into the program
+    position: Cell<usize>,
+    // set while the function is `dynamic-wind` running its thunk
+    wind: RefCell<Option<Rc<Wind>>>,
+}
+
+#[derive(Clone)]
+enum Caller {
+    // another native function
+    Native,
+    // a call instruction of the program
+    Call {
+        name: Option<String>,
+        tail: bool,
+        span: Span,
+    },
+    // a match instruction whose pattern calls the predicates of `?` patterns,
+    // which jumps to `next` if the pattern doesn't match
+    Match {
+        next: u32,
+        span: Span,
+    },
+}
+
+// the `before` and `after` thunks of a call to `dynamic-wind`
+struct Wind {
+    before: Function,
+    after: Function,
 }
 
 pub struct ExecutionStack {
     pub stack: Vec<Value>,
-    env: Rc<RefCell<Env>>,
-    ops: IntoIter<Operation>,
-    op_count: usize,
-    current_op: usize,
-    call_stack_depth: usize,
+    frames: Vec<Frame>,
+    memory_limit: usize,
+    // bytes taken up by the scopes of the frames
+    scope_memory: usize,
+    // identifies the next call to a native function
+    next_call: u64,
+    // continuations captured by this stack can resume in it
+    id: u64,
+}
+
+/// A transfer of control that unwinds the rust stack like an error, through
+/// natives that called back into the program, until it reaches the execution
+/// stack that can carry it out.
+#[derive(Debug, Clone)]
+pub(crate) enum Control {
+    /// Asks the caller of `call/cc` to call a function with the continuation
+    /// of the call.
+    Capture(Value),
+    /// Resumes a continuation with a value.
+    Resume(Rc<Continuation>, Value),
+}
+
+/// The rest of a computation: the frames and values of an execution stack
+/// when the continuation was captured.
+pub(crate) struct Continuation {
+    owner: u64,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+}
+
+impl fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<continuation of stack {}>", self.owner)
+    }
 }
 
 impl ExecutionStack {
-    pub fn new(env: Rc<RefCell<Env>>, body: Block) -> Self {
-        let op_count = body.len();
-        // if block has only one operation and it is a value, push it to the stack
-        if op_count == 1 && matches!(body[0].kind, OperationKind::Value { .. }) {
-            let OperationKind::Value { value } = body.into_iter().next().unwrap().kind else {
-                panic!("Invalid block")
-            };
-            Self {
-                stack: vec![value],
-                env,
-                ops: Vec::new().into_iter(),
-                op_count: 0,
-                current_op: 0,
-                call_stack_depth: 0,
-            }
-        } else {
-            Self {
-                env,
-                stack: Vec::new(),
-                ops: body.into_iter(),
-                op_count,
-                current_op: 0,
-                call_stack_depth: 0,
-            }
+    pub fn new(
+        globals: Rc<RefCell<Globals>>,
+        scope: Option<Rc<RefCell<Scope>>>,
+        code: Rc<Code>,
+    ) -> Self {
+        Self {
+            stack: Vec::new(),
+            frames: vec![Frame {
+                code,
+                pc: 0,
+                scope,
+                globals,
+                call: None,
+                memory: 0,
+                native: None,
+            }],
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            scope_memory: 0,
+            next_call: 0,
+            id: NEXT_STACK_ID.with(|id| id.replace(id.get() + 1)),
+        }
+    }
+
+    // stands in for a stack while it is lent to a native function
+    fn empty() -> Self {
+        Self {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            memory_limit: 0,
+            scope_memory: 0,
+            next_call: 0,
+            id: u64::MAX,
         }
     }
 
+    /// Creates a stack that runs the rest of a continuation whose own stack
+    /// has finished, e.g. one captured by an earlier statement.
+    pub(crate) fn resume(continuation: &Continuation, value: Value) -> Self {
+        let mut stack = Self::empty();
+        stack.id = continuation.owner;
+        stack.memory_limit = DEFAULT_MEMORY_LIMIT;
+        stack.restore(continuation, value);
+        stack
+    }
+
+    /// Starts running the code at the instruction `pc` instead of its first.
+    pub fn with_entry(mut self, pc: usize) -> Self {
+        self.frames[0].pc = pc;
+        self
+    }
+
+    /// Sets the number of bytes that frames and values may take up before
+    /// execution fails with a stack overflow.
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
     pub fn is_done(&self) -> bool {
-        self.current_op >= self.op_count
+        self.frames.is_empty()
     }
 
     pub fn run(&mut self) -> Result<()> {
-        while !self.is_done() {
-            self.step()?;
-        }
-        Ok(())
+        self.run_frames(0)
     }
 
     pub fn pop(&mut self) -> Option<Value> {
@@ -75,440 +223,978 @@ impl ExecutionStack {
 }
 
 impl ExecutionStack {
-    fn dump_and_switch(
-        &mut self,
-        new_env: Option<Rc<RefCell<Env>>>,
-        new_ops: Vec<Operation>,
-        span: Option<Span>,
-    ) -> Result<()> {
-        self.call_stack_depth += 1;
+    // runs until only the bottom `base` frames are left. The frame below
+    // `base`, if any, belongs to a native function that is waiting for the
+    // call it made back into the program to finish.
+    fn run_frames(&mut self, base: usize) -> Result<()> {
+        while self.frames.len() > base {
+            let frame = self.frames.last_mut().unwrap();
+            let result = if frame.native.is_some() {
+                // natives below `base` are still running, so a continuation
+                // returned into this one after it had finished
+                match self.stack.pop() {
+                    Some(value) => self.resume_native(Ok(value)),
+                    None => Err(RuntimeError::new("missing stack value".to_string(), None)),
+                }
+            } else {
+                let Some(&instruction) = frame.code.instructions.get(frame.pc) else {
+                    // compiled code always ends by returning
+                    self.pop_frame();
+                    continue;
+                };
+                let span = frame.code.spans[frame.pc];
+                frame.pc += 1;
+                self.step(instruction, span)
+            };
+            if let Err(err) = result {
+                self.recover(err, base)?;
+            }
+        }
+        Ok(())
+    }
+
+    // resumes the continuation an error carries, or hands the error to a
+    // native function that had finished before a continuation returned into
+    // it, or else returns the error
+    fn recover(&mut self, mut err: RuntimeError, base: usize) -> Result<()> {
+        loop {
+            if let Some(Control::Resume(continuation, value)) = err.control.as_deref() {
+                if self.can_restore(continuation, base) {
+                    self.restore(continuation, value.clone());
+                    return Ok(());
+                }
+            }
+            let native = self.frames[base..].iter().rposition(|f| f.native.is_some());
+            let start = match native {
+                Some(index) if err.control.is_none() => base + index + 1,
+                _ => base,
+            };
+            let calls = self.frames[start..].iter().rev();
+            err.stack_trace.extend(calls.filter_map(|f| f.call.clone()));
+            if start == base {
+                return Err(err);
+            }
+            self.truncate_frames(start);
+            match self.resume_native(Err(err)) {
+                Ok(()) => return Ok(()),
+                Err(next) => err = next,
+            }
+        }
+    }
+
+    // whether a run of the frames above `base` can resume `continuation` in
+    // place, because it was captured during the call back into the program
+    // that the native function waiting for the run is making
+    fn can_restore(&self, continuation: &Continuation, base: usize) -> bool {
+        if continuation.owner != self.id {
+            return false;
+        }
+        if base == 0 {
+            return true;
+        }
+        let captured = continuation
+            .frames
+            .get(base - 1)
+            .and_then(|f| f.native.as_ref());
+        match (captured, self.frames[base - 1].native.as_ref()) {
+            (Some(Native(captured)), Some(Native(running))) => {
+                captured.id == running.id && captured.position == running.position
+            }
+            _ => false,
+        }
+    }
+
+    // replaces the frames and values of the stack with those of a
+    // continuation, which receives `value`
+    fn restore(&mut self, continuation: &Continuation, value: Value) {
+        self.stack = continuation.stack.clone();
+        self.stack.push(value);
+        self.frames = continuation.frames.clone();
+        self.scope_memory = self.frames.iter().map(|f| f.memory).sum();
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("no frame to execute in")
+    }
+
+    fn scope(&self) -> &Option<Rc<RefCell<Scope>>> {
+        &self.frames.last().expect("no frame to execute in").scope
+    }
+
+    fn memory_used(&self) -> usize {
+        self.frames.len() * std::mem::size_of::<Frame>()
+            + self.stack.len() * std::mem::size_of::<Value>()
+            + self.scope_memory
+    }
 
-        if self.call_stack_depth >= MAX_CALL_STACK_DEPTH {
+    fn push_frame(&mut self, frame: Frame, span: Option<Span>) -> Result<()> {
+        if self.memory_used() + std::mem::size_of::<Frame>() + frame.memory > self.memory_limit {
             return Err(RuntimeError::new(
                 "Maximum call stack size exceeded".to_string(),
                 span,
             ));
         }
+        self.scope_memory += frame.memory;
+        self.frames.push(frame);
+        Ok(())
+    }
 
-        let new_env = new_env.unwrap_or_else(|| self.env.clone());
-        let saved_state = (
-            std::mem::replace(&mut self.op_count, new_ops.len()),
-            std::mem::replace(&mut self.ops, new_ops.into_iter()),
-            std::mem::replace(&mut self.current_op, 0),
-            std::mem::replace(&mut self.env, new_env),
-        );
+    fn pop_frame(&mut self) -> Option<Frame> {
+        let frame = self.frames.pop()?;
+        self.scope_memory -= frame.memory;
+        Some(frame)
+    }
 
-        let result = self.run();
+    fn truncate_frames(&mut self, len: usize) {
+        while self.frames.len() > len {
+            self.pop_frame();
+        }
+    }
 
-        self.op_count = saved_state.0;
-        self.ops = saved_state.1;
-        self.current_op = saved_state.2;
-        self.env = saved_state.3;
+    fn jump(&mut self, target: u32) {
+        self.frame().pc = target as usize;
+    }
 
-        self.call_stack_depth -= 1;
+    fn pop_value(&mut self, what: &str, span: Span) -> Result<Value> {
+        self.stack
+            .pop()
+            .ok_or_else(|| RuntimeError::new(format!("Empty stack in {what}"), Some(span)))
+    }
 
-        result
+    // pops the guard of a conditional and jumps to `target` if it is false
+    fn jump_unless(&mut self, target: u32, what: &str, message: &str, span: Span) -> Result<()> {
+        match self.pop_value(what, span)? {
+            Value::Boolean(true) => {}
+            Value::Boolean(false) => self.jump(target),
+            _ => return Err(RuntimeError::new(message.to_string(), Some(span))),
+        }
+        Ok(())
     }
 
-    fn jump_to(&mut self, label: String) -> Result<()> {
-        self.current_op += 1;
-        let mut cur = self.ops.next();
+    // pops an operand of `and` or `or`, and jumps to `target` with it on the
+    // stack if it is `last`
+    fn short_circuit(
+        &mut self,
+        target: u32,
+        last: bool,
+        what: &str,
+        message: &str,
+        span: Span,
+    ) -> Result<()> {
+        match self.pop_value(what, span)? {
+            Value::Boolean(b) if b == last => {
+                self.stack.push(Value::Boolean(b));
+                self.jump(target);
+            }
+            Value::Boolean(_) => {}
+            _ => return Err(RuntimeError::new(message.to_string(), Some(span))),
+        }
+        Ok(())
+    }
 
-        while let Some(op) = cur {
-            match op.kind {
-                OperationKind::Label { name } if name == label => {
-                    return Ok(());
-                }
-                _ => {
-                    self.current_op += 1;
-                    cur = self.ops.next();
-                }
+    // natives that call back into the program recurse through here, so every
+    // instruction is run by a method of its own to keep the frame on the rust
+    // stack small
+    fn step(&mut self, instruction: Instruction, span: Span) -> Result<()> {
+        match instruction {
+            Instruction::Constant(index) => self.constant(index),
+            Instruction::CopyConstant(index) => self.copy_constant(index),
+            Instruction::Local { depth, index } => self.local(depth, index, span),
+            Instruction::Global(index) => self.global(index, span),
+            Instruction::SetLocal { depth, index } => self.set_local(depth, index, span),
+            Instruction::SetGlobal(index) => self.set_global(index, span),
+            Instruction::Closure(index) => self.closure(index),
+            Instruction::Call(arity) => self.call(arity, false, span),
+            Instruction::TailCall(arity) => self.call(arity, true, span),
+            Instruction::Return => {
+                self.pop_frame();
+                Ok(())
+            }
+            Instruction::Pop => self.pop_value("sequence", span).map(drop),
+            Instruction::Jump(target) => {
+                self.jump(target);
+                Ok(())
+            }
+            Instruction::If(target) => self.jump_unless(
+                target,
+                "if expression",
+                "Boolean expected in conditional",
+                span,
+            ),
+            Instruction::Cond(target) => self.jump_unless(
+                target,
+                "cond guard",
+                "boolean expected in conditional",
+                span,
+            ),
+            Instruction::And(target) => self.short_circuit(
+                target,
+                false,
+                "and expression",
+                "\"and\" expects a boolean value",
+                span,
+            ),
+            Instruction::Or(target) => self.short_circuit(
+                target,
+                true,
+                "or expression",
+                "\"or\" expects a boolean value",
+                span,
+            ),
+            Instruction::EnterScope(count) => self.bind(count, span),
+            Instruction::ExitScope => {
+                self.exit_scope();
+                Ok(())
             }
+            Instruction::Match { pattern, next } => self.match_pattern(pattern, next, span),
+            Instruction::NoMatch => Err(self.no_match(span)),
+            Instruction::Fail(message) => Err(self.fail(message, span)),
         }
+    }
+
+    fn constant(&mut self, index: u32) -> Result<()> {
+        let value = self.frame().code.constants[index as usize].clone();
+        self.stack.push(value);
+        Ok(())
+    }
 
-        Err(RuntimeError::new(
-            format!("Label `{}` not found", label),
+    fn copy_constant(&mut self, index: u32) -> Result<()> {
+        let value = self.frame().code.constants[index as usize].snapshot();
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn closure(&mut self, index: u32) -> Result<()> {
+        let frame = self.frame();
+        let value = Value::Closure(
+            Closure {
+                code: Rc::clone(&frame.code.functions[index as usize]),
+                env: frame.scope.clone(),
+                globals: Rc::clone(&frame.globals),
+            },
             None,
-        ))
+        );
+        self.stack.push(value);
+        Ok(())
     }
 
-    fn jump_past(&mut self, label: String) -> Result<()> {
-        self.jump_to(label)?;
-        self.current_op += 1;
+    // pops the values of the bindings of a `let` into a new scope
+    fn bind(&mut self, count: u32, span: Span) -> Result<()> {
+        if self.stack.len() < count as usize {
+            return Err(RuntimeError::new(
+                "Not enough values on stack for let binding".to_string(),
+                Some(span),
+            ));
+        }
+        let values = self.stack.split_off(self.stack.len() - count as usize);
+        self.enter_scope(values);
         Ok(())
     }
 
-    fn step(&mut self) -> Result<()> {
-        self.current_op += 1;
-        let Some(op) = self.ops.next() else {
-            return Ok(());
+    fn fail(&mut self, message: u32, span: Span) -> RuntimeError {
+        let Value::String(message) = &self.frame().code.constants[message as usize] else {
+            unreachable!("failure messages are strings");
         };
-        match op.kind {
-            OperationKind::Value { value } => {
-                self.stack.push(value);
-            }
-            OperationKind::Variable { name } => {
-                assert_not_reserved(&name)?;
-                if let Some(value) = self.env.borrow().get(&name) {
-                    self.stack.push(value.clone());
-                } else {
-                    return Err(RuntimeError::new(
-                        format!("Referenced unbound identifier `{name}`"),
-                        Some(op.span),
-                    ));
-                }
-            }
-            OperationKind::Application { arity } => {
-                if self.stack.len() < arity as usize {
-                    return Err(RuntimeError::new(
-                        format!("Not enough arguments on stack"),
-                        Some(op.span),
-                    ));
-                }
-                let args = self.stack.split_off(self.stack.len() - arity as usize);
-                let func = self.stack.pop().unwrap();
-
-                match func {
-                    Value::Closure(closure, _) => self.eval_closure(closure, args, op.span)?,
-                    Value::Function(function, name) => {
-                        let result = function.0(&args).map_err(|err| RuntimeError {
-                            message: err.message,
-                            namespace: err.namespace.or(name),
-                            span: Some(op.span),
-                        })?;
-                        self.stack.push(result);
-                    }
-                    _ => {
-                        return Err(RuntimeError::new(
-                            format!("Non-function value in function application"),
-                            Some(op.span),
-                        ))
-                    }
-                }
-            }
-            OperationKind::Closure { params, body } => {
-                let value = Value::Closure(
-                    Closure {
-                        params,
-                        body,
-                        env: Some(self.env.clone()),
-                    },
-                    None,
-                );
-                self.stack.push(value);
-            }
-            OperationKind::If {
-                if_block,
-                else_block,
-            } => {
-                if self.stack.is_empty() {
-                    return Err(RuntimeError::new(
-                        format!("Empty stack in if expression"),
-                        Some(op.span),
-                    ));
-                }
-                let cond = self.stack.pop().unwrap();
-                match cond {
-                    Value::Boolean(b) => {
-                        if b {
-                            self.dump_and_switch(None, if_block, Some(op.span))?;
-                        } else {
-                            self.dump_and_switch(None, else_block, Some(op.span))?;
-                        }
-                    }
-                    _ => {
-                        return Err(RuntimeError::new(
-                            format!("Boolean expected in conditional"),
-                            Some(op.span),
-                        ));
-                    }
-                };
-            }
-            OperationKind::Let { names, body } => {
-                for name in &names {
-                    assert_not_reserved(&name)?;
-                }
-                if self.stack.len() < names.len() {
-                    return Err(RuntimeError::new(
-                        format!("Not enough values on stack for let binding"),
-                        Some(op.span),
-                    ));
-                }
-
-                let values = self.stack.split_off(self.stack.len() - names.len());
+        RuntimeError::new(message.clone(), Some(span))
+    }
 
-                let new_env = self.extend_env(names.into_iter().zip(values.into_iter()));
-                self.dump_and_switch(Some(new_env), body, Some(op.span))?;
-            }
-            OperationKind::Sequence { subexpr_count } => {
-                self.stack = self
-                    .stack
-                    .split_off(self.stack.len() - subexpr_count as usize);
+    fn local(&mut self, depth: u32, index: u32, span: Span) -> Result<()> {
+        let value = self
+            .scope()
+            .as_ref()
+            .and_then(|scope| scope.borrow().get(depth as usize, index as usize));
+        let Some(value) = value else {
+            return Err(RuntimeError::new(
+                "Local variable missing from its frame".to_string(),
+                Some(span),
+            ));
+        };
+        self.stack.push(value);
+        Ok(())
+    }
 
-                if self.stack.len() < subexpr_count as usize {
-                    return Err(RuntimeError::new(
-                        format!(
-                            "Not enough values on stack for sequence: {} expected, {} found",
-                            subexpr_count,
-                            self.stack.len()
-                        ),
-                        Some(op.span),
-                    ));
-                }
+    fn global(&mut self, index: u32, span: Span) -> Result<()> {
+        let globals = self.frame().globals.borrow();
+        let Some(value) = globals.get(index as usize) else {
+            return Err(RuntimeError::new(
+                format!(
+                    "Referenced identifier `{}` before its definition",
+                    globals.name(index as usize)
+                ),
+                Some(span),
+            ));
+        };
+        drop(globals);
+        self.stack.push(value);
+        Ok(())
+    }
 
-                let ret = self.stack.pop().unwrap();
-                for _ in 1..subexpr_count {
-                    self.stack.pop();
-                }
-                self.stack.push(ret);
-            }
-            OperationKind::Match { branches } => {
-                if self.stack.is_empty() {
-                    return Err(RuntimeError::new(
-                        format!("Scrutinee missing from stack for match"),
-                        Some(op.span),
-                    ));
-                }
-                let scutinee = self.stack.pop().unwrap();
-                let mut found_match = false;
-                for branch in branches {
-                    let bindings = self.try_match(&branch.0, &scutinee)?;
-                    if let Some(bindings) = bindings {
-                        let new_env = self.extend_env(bindings);
-                        self.dump_and_switch(Some(new_env), branch.1, Some(op.span))?;
-                        found_match = true;
-                        break;
-                    }
-                }
-                if !found_match {
-                    return Err(RuntimeError::new(
-                        format!("No pattern matches for {}", scutinee),
-                        Some(op.span),
-                    ));
-                }
-            }
-            OperationKind::And { jump_to } => {
-                if self.stack.is_empty() {
-                    return Err(RuntimeError::new(
-                        format!("Missing argument to \"and\" instruction"),
-                        Some(op.span),
-                    ));
-                }
-                let cond = self.stack.pop().unwrap();
-                match cond {
-                    Value::Boolean(b) => {
-                        if !b {
-                            self.stack.push(Value::Boolean(false));
-                            self.jump_to(jump_to)?;
-                        }
-                    }
-                    _ => {
-                        return Err(RuntimeError::new(
-                            format!("\"and\" expects a boolean value"),
-                            Some(op.span),
-                        ));
-                    }
-                };
-            }
-            OperationKind::Or { jump_to } => {
-                if self.stack.is_empty() {
-                    return Err(RuntimeError::new(
-                        format!("Missing argument to \"or\" instruction"),
-                        Some(op.span),
-                    ));
-                }
-                let cond = self.stack.pop().unwrap();
-                match cond {
-                    Value::Boolean(b) => {
-                        if b {
-                            self.stack.push(Value::Boolean(true));
-                            self.jump_to(jump_to)?;
-                        }
-                    }
-                    _ => {
-                        return Err(RuntimeError::new(
-                            format!("\"or\" expects a boolean value"),
-                            Some(op.span),
-                        ));
-                    }
-                };
-            }
-            OperationKind::Cond { body, end } => {
-                if self.stack.is_empty() {
-                    return Err(RuntimeError::new(
-                        "missing guard to \"cond\" instruction".to_string(),
-                        Some(op.span),
-                    ));
-                }
-                let cond = self.stack.pop().unwrap();
-                match cond {
-                    Value::Boolean(b) => {
-                        if b {
-                            self.jump_past(end)?;
-                            self.dump_and_switch(None, body, Some(op.span))?;
-                        }
-                    }
-                    _ => {
-                        return Err(RuntimeError::new(
-                            "boolean expected in conditional".to_string(),
-                            Some(op.span),
-                        ));
-                    }
-                };
-            }
-            OperationKind::Label { .. } => {
-                // do nothing
-            }
-            OperationKind::Exception {
-                message,
-                mod_name,
-                span,
-                source,
-            } => {
-                return Err(RuntimeError::new(
-                    format!(
-                        "{}{}{}",
-                        if let Some(mod_name) = mod_name {
-                            format!("[{mod_name}] ")
-                        } else {
-                            String::default()
-                        },
-                        if let Some(source) = source {
-                            format!("{}: ", source)
-                        } else {
-                            String::default()
-                        },
-                        message,
-                    ),
-                    span,
-                ));
-            }
+    fn set_local(&mut self, depth: u32, index: u32, span: Span) -> Result<()> {
+        let value = self.pop_value("set! expression", span)?;
+        let assigned = self.scope().as_ref().is_some_and(|scope| {
+            scope
+                .borrow_mut()
+                .set(depth as usize, index as usize, value)
+        });
+        if !assigned {
+            return Err(RuntimeError::new(
+                "Local variable missing from its frame".to_string(),
+                Some(span),
+            ));
         }
+        self.stack.push(Value::Void);
         Ok(())
     }
 
-    fn eval_closure(&mut self, closure: Closure, args: Vec<Value>, span: Span) -> Result<()> {
-        if closure.params.len() != args.len() {
+    fn set_global(&mut self, index: u32, span: Span) -> Result<()> {
+        let value = self.pop_value("set! expression", span)?;
+        let mut globals = self.frame().globals.borrow_mut();
+        if !globals.set(index as usize, value) {
             return Err(RuntimeError::new(
                 format!(
-                    "Function expected {} arguments, passed {} instead",
-                    closure.params.len(),
-                    args.len()
+                    "Assigned to identifier `{}` before its definition",
+                    globals.name(index as usize)
                 ),
                 Some(span),
             ));
         }
-
-        let new_env = self.extend_env(closure.params.into_iter().zip(args.into_iter()));
-        self.dump_and_switch(Some(new_env), closure.body, Some(span))?;
-
+        drop(globals);
+        self.stack.push(Value::Void);
         Ok(())
     }
 
-    // returns None if the pattern does not match the value
-    fn try_match(&self, pattern: &Value, value: &Value) -> Result<Option<Vec<(String, Value)>>> {
-        let matches = Ok(Some(Vec::new()));
-        match (pattern, value) {
-            (Value::Symbol(s), v) => {
-                if s == "_" {
-                    matches
+    fn exit_scope(&mut self) {
+        let frame = self.frames.last_mut().expect("no frame to execute in");
+        let Some(scope) = frame.scope.take() else {
+            return;
+        };
+        let scope = scope.borrow();
+        let memory = scope.len() * std::mem::size_of::<Value>();
+        frame.scope = scope.parent();
+        frame.memory -= memory;
+        self.scope_memory -= memory;
+    }
+
+    fn match_pattern(&mut self, pattern: u32, next: u32, span: Span) -> Result<()> {
+        let code = Rc::clone(&self.frame().code);
+        let branch = &code.patterns[pattern as usize];
+        if self.stack.len() < branch.predicates + 1 {
+            return Err(RuntimeError::new(
+                "Scrutinee missing from stack for match".to_string(),
+                Some(span),
+            ));
+        }
+        if branch.predicates > 0 {
+            // the predicates of `?` patterns are called like natives call
+            // functions, so the match runs as a native function
+            let args = self
+                .stack
+                .split_off(self.stack.len() - branch.predicates - 1);
+            let matcher = NativeFn::new(move |args: &[Value]| {
+                let branch = &code.patterns[pattern as usize];
+                let mut slots = vec![Value::Void; branch.slots];
+                if try_match(&branch.pattern, &args[0], &args[1..], &mut slots)? {
+                    Ok(Value::List(slots))
                 } else {
-                    assert_not_reserved(&s)?;
-                    Ok(Some(vec![(s.clone(), v.clone())]))
+                    Ok(Value::Boolean(false))
                 }
+            });
+            self.push_native(matcher, args, Caller::Match { next, span }, Some(span))?;
+            let result = self.run_native();
+            return self.finish_native(result);
+        }
+        let scrutinee = self.stack.pop().unwrap();
+        let mut slots = vec![Value::Void; branch.slots];
+        if try_match(&branch.pattern, &scrutinee, &[], &mut slots)? {
+            self.enter_scope(slots);
+        } else {
+            self.stack.push(scrutinee);
+            self.jump(next);
+        }
+        Ok(())
+    }
+
+    fn no_match(&mut self, span: Span) -> RuntimeError {
+        match self.pop_value("match expression", span) {
+            Ok(scrutinee) => {
+                RuntimeError::new(format!("No pattern matches for {}", scrutinee), Some(span))
             }
-            (Value::List(p), Value::List(v)) => self.match_lists(p, v),
-            (Value::List(p), Value::Pair(vx, vy)) => {
-                self.match_lists(p, &vec![*vx.clone(), *vy.clone()])
+            Err(err) => err,
+        }
+    }
+
+    // calls the function below `arity` arguments; tail calls replace the
+    // current frame so that they execute in constant space
+    fn call(&mut self, arity: u32, tail: bool, span: Span) -> Result<()> {
+        if self.stack.len() < arity as usize + 1 {
+            return Err(RuntimeError::new(
+                "Not enough arguments on stack".to_string(),
+                Some(span),
+            ));
+        }
+        let args = self.stack.split_off(self.stack.len() - arity as usize);
+        let func = self.stack.pop().unwrap();
+
+        match func {
+            Value::Closure(closure, name) => {
+                self.call_closure(closure, name, args, tail, Some(span))
             }
-            (Value::List(p), Value::Struct(v)) => {
-                if p.is_empty() {
-                    return Ok(None);
-                }
+            Value::Function(function, name) => {
+                self.call_primitive(function, name, args, tail, span)
+            }
+            _ => Err(RuntimeError::new(
+                "Non-function value in function application".to_string(),
+                Some(span),
+            )),
+        }
+    }
 
-                let Value::Symbol(head) = p.first().unwrap() else {
-                    return Ok(None);
-                };
+    fn call_closure(
+        &mut self,
+        closure: Closure,
+        name: Option<String>,
+        args: Vec<Value>,
+        tail: bool,
+        span: Option<Span>,
+    ) -> Result<()> {
+        let frame = closure_frame(closure, name, args, span)?;
+        if tail {
+            self.pop_frame();
+        }
+        self.push_frame(frame, span)
+    }
+
+    // calls a native function for the program
+    fn call_primitive(
+        &mut self,
+        function: NativeFn,
+        name: Option<String>,
+        args: Vec<Value>,
+        tail: bool,
+        span: Span,
+    ) -> Result<()> {
+        let caller = Caller::Call { name, tail, span };
+        self.push_native(function, args, caller, Some(span))?;
+        let result = self.run_native();
+        self.finish_native(result)
+    }
 
-                if v.kind != *head {
-                    return Ok(None);
+    // pops the frame of a native function that returned to the program, and
+    // continues the program with its result
+    fn finish_native(&mut self, result: Result<Value>) -> Result<()> {
+        let frame = self.pop_frame().expect("no frame to execute in");
+        let Some(Native(call)) = frame.native else {
+            unreachable!("the frame belongs to a native function");
+        };
+        // the function has given back its share of the call
+        let call = Rc::try_unwrap(call).unwrap_or_else(|call| NativeCall::clone(&call));
+        match (call.caller, result) {
+            (Caller::Call { tail, .. }, Ok(value)) => {
+                self.stack.push(value);
+                if tail {
+                    self.pop_frame();
                 }
+                Ok(())
+            }
+            (Caller::Call { name, tail, span }, Err(err)) => {
+                self.native_failed(err, name, tail, span)
+            }
+            (Caller::Match { .. }, Ok(Value::List(slots))) => {
+                self.enter_scope(slots);
+                Ok(())
+            }
+            (Caller::Match { next, .. }, Ok(_)) => {
+                self.stack.push(call.args[0].clone());
+                self.jump(next);
+                Ok(())
+            }
+            (Caller::Match { span, .. }, Err(err)) => Err(RuntimeError {
+                span: err.span.or(Some(span)),
+                ..err
+            }),
+            (Caller::Native, _) => unreachable!("natives return to the native that called them"),
+        }
+    }
 
-                let args = p.iter().skip(1).collect::<Vec<&Value>>();
+    // carries out the call/cc a native function asked for, or else reports
+    // the native function's error
+    fn native_failed(
+        &mut self,
+        mut err: RuntimeError,
+        name: Option<String>,
+        tail: bool,
+        span: Span,
+    ) -> Result<()> {
+        if let Some(Control::Capture(receiver)) = err.control.as_deref() {
+            return self.call_with_continuation(receiver.clone(), tail, span);
+        }
+        err.stack_trace.push(StackFrame {
+            name: name.clone(),
+            span: Some(span),
+        });
+        Err(RuntimeError {
+            namespace: err.namespace.or(name),
+            // errors from closures called by the function keep their location
+            span: err.span.or(Some(span)),
+            ..err
+        })
+    }
 
-                if v.fields.len() == args.len() {
-                    let mut env = Vec::new();
-                    for i in 0..v.fields.len() {
-                        let env2 = self.try_match(args[i], &v.values[i])?;
-                        if env2.is_none() {
-                            return Ok(None);
-                        }
-                        env.extend(env2.unwrap());
-                    }
-                    Ok(Some(env))
-                } else {
-                    Ok(None)
-                }
+    fn push_native(
+        &mut self,
+        function: NativeFn,
+        args: Vec<Value>,
+        caller: Caller,
+        span: Option<Span>,
+    ) -> Result<()> {
+        let call = NativeCall {
+            id: self.next_call,
+            function,
+            args,
+            caller,
+            height: self.stack.len(),
+            results: RefCell::default(),
+            position: Cell::new(0),
+            wind: RefCell::new(None),
+        };
+        self.next_call += 1;
+        let below = self.frames.last().expect("no frame to call from");
+        let frame = Frame {
+            code: Rc::clone(&below.code),
+            pc: 0,
+            scope: None,
+            globals: Rc::clone(&below.globals),
+            call: None,
+            memory: 0,
+            native: Some(Native(Rc::new(call))),
+        };
+        self.push_frame(frame, span)
+    }
+
+    // the call of the native function in the top frame
+    fn native_call(&self) -> &Rc<NativeCall> {
+        match self.frames.last().and_then(|f| f.native.as_ref()) {
+            Some(Native(call)) => call,
+            None => unreachable!("the frame belongs to a native function"),
+        }
+    }
+
+    // runs the native function of the top frame, lending it the stack so that
+    // the calls it makes back into the program run on top of that frame
+    fn run_native(&mut self) -> Result<Value> {
+        let call = Rc::clone(self.native_call());
+        let depth = self.frames.len();
+
+        CURRENT.with(|current| current.set(Some(std::mem::replace(self, Self::empty()))));
+        let result = call.function.0(&call.args);
+        *self = CURRENT
+            .with(|current| current.take())
+            .expect("native functions give back the stack they were lent");
+
+        // the calls made back into the program have finished
+        debug_assert_eq!(self.frames.len(), depth);
+        result
+    }
+
+    // runs the native function of the top frame again after a continuation
+    // returned `result` into it from a call it made back into the program.
+    // The calls before that one return what they did the first time.
+    fn resume_native(&mut self, mut result: Result<Value>) -> Result<()> {
+        loop {
+            let call = self.native_call();
+            let mut results = call.results.borrow_mut();
+            results.truncate(call.position.get());
+            results.push(result.map_err(Box::new));
+            drop(results);
+            call.position.set(0);
+            let (height, native) = (call.height, matches!(call.caller, Caller::Native));
+            self.stack.truncate(height);
+            result = self.run_native();
+            if !native {
+                return self.finish_native(result);
             }
-            _ => {
-                if pattern == value {
-                    matches
-                } else {
-                    Ok(None)
+            // the native function that called it had finished too
+            self.pop_frame();
+            result = result.map_err(uncaptured);
+        }
+    }
+
+    // calls `function` for the native function running in the top frame, and
+    // runs until the call returns
+    fn call_back(&mut self, function: Value, args: &[Value]) -> Result<Value> {
+        let call = self.native_call();
+        let position = call.position.get();
+        let replayed = call.results.borrow().get(position).cloned();
+        if let Some(result) = replayed {
+            // the native function is running again after a continuation
+            // returned into it
+            call.position.set(position + 1);
+            return result.map_err(|err| *err);
+        }
+        let result = self.call_back_value(function, args);
+        if !result.as_ref().is_err_and(|err| err.control.is_some()) {
+            let call = self.native_call();
+            call.results
+                .borrow_mut()
+                .push(result.clone().map_err(Box::new));
+            call.position.set(position + 1);
+        }
+        result
+    }
+
+    fn call_back_value(&mut self, function: Value, args: &[Value]) -> Result<Value> {
+        let (depth, height) = (self.frames.len(), self.stack.len());
+        let result = match function {
+            Value::Closure(closure, name) => self.call_back_closure(closure, name, args),
+            Value::Function(function, _) => self.call_back_native(function, args),
+            _ => unreachable!("only procedures are called back"),
+        };
+        if result.is_err() {
+            // the frames and values the call left behind are abandoned
+            self.truncate_frames(depth);
+            self.stack.truncate(height);
+        }
+        match result {
+            Err(err) => match err.control.as_deref() {
+                // call/cc called by the native function captures the
+                // continuation of its call
+                Some(Control::Capture(receiver)) => {
+                    let continuation = self.continuation();
+                    self.call_back_value(receiver.clone(), &[continuation])
                 }
-            }
+                _ => Err(err),
+            },
+            result => result,
         }
     }
 
-    fn match_lists(&self, p: &Vec<Value>, v: &Vec<Value>) -> Result<Option<Vec<(String, Value)>>> {
-        if p.is_empty() || v.is_empty() {
-            return Ok(None);
+    fn call_back_closure(
+        &mut self,
+        closure: Closure,
+        name: Option<String>,
+        args: &[Value],
+    ) -> Result<Value> {
+        let depth = self.frames.len();
+        self.call_closure(closure, name, args.to_vec(), false, None)?;
+        self.run_nested(depth)?;
+        self.stack
+            .pop()
+            .ok_or_else(|| RuntimeError::new("missing stack value".to_string(), None))
+    }
+
+    fn call_back_native(&mut self, function: NativeFn, args: &[Value]) -> Result<Value> {
+        self.push_native(function, args.to_vec(), Caller::Native, None)?;
+        let result = self.run_native();
+        self.pop_frame();
+        result
+    }
+
+    // runs the frames above the bottom `base` while a native function waits for
+    // them, which recurses on the rust stack
+    fn run_nested(&mut self, base: usize) -> Result<()> {
+        let depth = RUN_DEPTH.with(|depth| depth.get());
+        if depth >= MAX_CALL_STACK_DEPTH {
+            return Err(RuntimeError::new(
+                "Maximum call stack size exceeded".to_string(),
+                None,
+            ));
         }
+        RUN_DEPTH.with(|d| d.set(depth + 1));
+        let result = self.run_frames(base);
+        RUN_DEPTH.with(|d| d.set(depth));
+        result
+    }
 
-        let head = p.first().unwrap();
-        let args = p.iter().skip(1).collect::<Vec<&Value>>();
+    // calls `receiver` with the continuation of the call to call/cc that asked
+    // for it, which returns to the caller of the current frame in tail position
+    fn call_with_continuation(&mut self, receiver: Value, tail: bool, span: Span) -> Result<()> {
+        if tail {
+            self.pop_frame();
+        }
+        let continuation = self.continuation();
+        self.stack.push(receiver);
+        self.stack.push(continuation);
+        self.call(1, false, span)
+    }
 
-        if let Value::Symbol(sym) = head {
-            if sym == "pair" || sym == "cons" && args.len() == 2 {
-                let env1 = self.try_match(args[0], v.first().unwrap())?;
-                let v_values = v.iter().skip(1).collect::<Vec<&Value>>();
-                let env2 = if v_values.len() == 1 {
-                    self.try_match(args[1], v_values.first().unwrap())?
-                } else {
-                    self.try_match(
-                        args[1],
-                        &Value::List(
-                            v_values
-                                .into_iter()
-                                .map(|i| i.clone())
-                                .collect::<Vec<Value>>(),
-                        ),
-                    )?
-                };
-                if let (Some(env1), Some(env2)) = (env1, env2) {
-                    let mut env = env1;
-                    env.extend(env2);
-                    Ok(Some(env))
-                } else {
-                    Ok(None)
+    // captures the frames and values of the stack as a procedure that returns
+    // its argument to the top frame
+    fn continuation(&self) -> Value {
+        let continuation = Rc::new(Continuation {
+            owner: self.id,
+            stack: self.stack.clone(),
+            frames: self.frames.clone(),
+        });
+        let resume = move |args: &[Value]| {
+            let value = match args {
+                [] => Value::Void,
+                [value] => value.clone(),
+                _ => {
+                    return Err(RuntimeError::new(
+                        format!("expected 0 to 1 arguments, found {}", args.len()),
+                        None,
+                    ))
                 }
-            } else {
-                Ok(None)
-            }
-        } else {
-            Ok(None)
+            };
+            rewind(&continuation.frames)?;
+            Err(RuntimeError::control(Control::Resume(
+                Rc::clone(&continuation),
+                value,
+            )))
+        };
+        Value::Function(NativeFn::new(resume), Some("continuation".to_string()))
+    }
+
+    // binds `values` in a new scope of the current frame
+    fn enter_scope(&mut self, values: Vec<Value>) {
+        let memory = values.len() * std::mem::size_of::<Value>();
+        let scope = Scope::new(values, self.scope().clone());
+        let frame = self.frame();
+        frame.scope = Some(Rc::new(RefCell::new(scope)));
+        frame.memory += memory;
+        self.scope_memory += memory;
+    }
+}
+
+// creates the frame a call to `closure` runs in
+fn closure_frame(
+    closure: Closure,
+    name: Option<String>,
+    args: Vec<Value>,
+    span: Option<Span>,
+) -> Result<Frame> {
+    let (args, pc) = closure
+        .code
+        .bind(args, name.as_deref())
+        .map_err(|message| RuntimeError::new(message, span))?;
+    let memory = args.len() * std::mem::size_of::<Value>();
+    // the body runs in the scope the closure was created in
+    let scope = Rc::new(RefCell::new(Scope::new(args, closure.env)));
+    Ok(Frame {
+        code: closure.code,
+        pc,
+        scope: Some(scope),
+        globals: closure.globals,
+        call: Some(StackFrame { name, span }),
+        memory,
+        native: None,
+    })
+}
+
+/// Returns the number of bytes the running program may take up, which native
+/// functions also hold the values they build to.
+pub(crate) fn memory_limit() -> usize {
+    CURRENT.with(|current| {
+        let stack = current.take();
+        let limit = stack
+            .as_ref()
+            .map_or(DEFAULT_MEMORY_LIMIT, |stack| stack.memory_limit);
+        current.set(stack);
+        limit
+    })
+}
+
+/// Calls a function on behalf of a native function. Calls made while the
+/// program is running continue on its execution stack, so that they share
+/// its memory limit and can capture its continuations.
+pub(crate) fn call_function(function: Value, args: &[Value]) -> Result<Value> {
+    let Some(mut stack) = CURRENT.with(|current| current.take()) else {
+        return call_outside(function, args);
+    };
+    let result = stack.call_back(function, args);
+    CURRENT.with(|current| current.set(Some(stack)));
+    result
+}
+
+// calls a function from outside of any running program, e.g. from an event
+// handler of the web interface
+fn call_outside(function: Value, args: &[Value]) -> Result<Value> {
+    match function {
+        Value::Closure(closure, name) => {
+            // the bottom frame only holds the globals natives are called with
+            let globals = Rc::clone(&closure.globals);
+            let mut stack = ExecutionStack::new(globals, None, Rc::default());
+            stack.call_back_value(Value::Closure(closure, name), args)
         }
+        Value::Function(function, _) => function.0(args).map_err(uncaptured),
+        _ => unreachable!("only procedures are called back"),
+    }
+}
+
+/// Calls `thunk` between calls to `before` and `after`. Continuations that
+/// leave the call to `thunk` call `after` on their way out, and ones that
+/// return into it call `before` again.
+pub(crate) fn dynamic_wind(before: &Function, thunk: &Function, after: &Function) -> Result<Value> {
+    before.call(&[])?;
+    let wind = Rc::new(Wind {
+        before: before.clone(),
+        after: after.clone(),
+    });
+    // running again after a continuation returned into the thunk keeps the
+    // wind the continuation entered
+    with_native_call(|call| {
+        call.wind.borrow_mut().get_or_insert(wind);
+    });
+    let result = thunk.call(&[]);
+    if result.as_ref().is_err_and(|err| err.control.is_some()) {
+        // the continuation being resumed has called `after`
+        return result;
+    }
+    with_native_call(|call| call.wind.replace(None));
+    after.call(&[])?;
+    result
+}
+
+// applies `f` to the call of the native function running on the stack, if any
+fn with_native_call<T>(f: impl FnOnce(&NativeCall) -> T) -> Option<T> {
+    let stack = CURRENT.with(|current| current.take())?;
+    let native = stack.frames.last().and_then(|frame| frame.native.as_ref());
+    let result = native.map(|Native(call)| f(call));
+    CURRENT.with(|current| current.set(Some(stack)));
+    result
+}
+
+// the calls to `dynamic-wind` running their thunk in `frames`, outermost first
+fn winds(frames: &[Frame]) -> Vec<Rc<Wind>> {
+    frames
+        .iter()
+        .filter_map(|f| f.native.as_ref()?.0.wind.borrow().clone())
+        .collect()
+}
+
+// calls the `after` thunks of the calls to `dynamic-wind` that resuming a
+// continuation with `frames` leaves, innermost first, and then the `before`
+// thunks of those it enters, outermost first
+fn rewind(frames: &[Frame]) -> Result<()> {
+    let current = CURRENT.with(|current| {
+        let stack = current.take();
+        let winds = stack.as_ref().map(|stack| winds(&stack.frames));
+        current.set(stack);
+        winds.unwrap_or_default()
+    });
+    let target = winds(frames);
+    let common = current
+        .iter()
+        .zip(&target)
+        .take_while(|(a, b)| Rc::ptr_eq(a, b))
+        .count();
+    for wind in current[common..].iter().rev() {
+        wind.after.call(&[])?;
     }
+    for wind in &target[common..] {
+        wind.before.call(&[])?;
+    }
+    Ok(())
+}
+
+// natives called from outside of a running program have no stack to capture
+// the continuation of, so call/cc called by them fails
+fn uncaptured(err: RuntimeError) -> RuntimeError {
+    match err.control.as_deref() {
+        Some(Control::Capture(_)) => RuntimeError {
+            control: None,
+            ..err
+        },
+        _ => err,
+    }
+}
+
+// matches `value` against `pattern`, storing the values of the variables it
+// binds in `slots`
+fn try_match(
+    pattern: &Pattern,
+    value: &Value,
+    predicates: &[Value],
+    slots: &mut [Value],
+) -> Result<bool> {
+    let all = |patterns: &[Pattern], values: &[Value], slots: &mut [Value]| {
+        if patterns.len() != values.len() {
+            return Ok(false);
+        }
+        for (pattern, value) in patterns.iter().zip(values) {
+            if !try_match(pattern, value, predicates, slots)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    };
 
-    fn extend_env(&self, bindings: impl IntoIterator<Item = (String, Value)>) -> Rc<RefCell<Env>> {
-        let new_env = Rc::new((*self.env).clone());
-        {
-            let mut new_env = new_env.borrow_mut();
-            for (name, value) in bindings.into_iter() {
-                new_env.set(name.clone(), value.clone());
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => Ok(true),
+        (Pattern::Variable(slot), _) => {
+            slots[*slot] = value.clone();
+            Ok(true)
+        }
+        (Pattern::Literal(literal), _) => Ok(literal == value),
+        (Pattern::Cons(head, tail), Value::Pair(x, y)) => {
+            Ok(try_match(head, x, predicates, slots)? && try_match(tail, y, predicates, slots)?)
+        }
+        (Pattern::Cons(head, tail), Value::List(values)) if !values.is_empty() => {
+            let rest = if values.len() == 1 {
+                Value::Null
+            } else {
+                Value::List(values[1..].to_vec())
+            };
+            Ok(try_match(head, &values[0], predicates, slots)?
+                && try_match(tail, &rest, predicates, slots)?)
+        }
+        (Pattern::List(patterns), Value::Null) => Ok(patterns.is_empty()),
+        (Pattern::List(patterns), Value::List(values)) => all(patterns, values, slots),
+        (Pattern::Vector(patterns), Value::Vector(values)) => {
+            // predicates may mutate the vector, so it isn't borrowed while matching
+            let values = values.borrow().clone();
+            all(patterns, &values, slots)
+        }
+        (Pattern::Struct { kind, fields }, Value::Struct(s)) => {
+            Ok(s.kind == *kind && all(fields, &s.values, slots)?)
+        }
+        (
+            Pattern::Predicate {
+                predicate,
+                patterns,
+            },
+            _,
+        ) => {
+            let predicate = &predicates[*predicate];
+            let Some(function) = Function::from_value(predicate) else {
+                return Err(RuntimeError::new(
+                    format!(
+                        "? pattern expects a procedure, received {}",
+                        predicate.name()
+                    ),
+                    None,
+                ));
+            };
+            if matches!(
+                function.call(std::slice::from_ref(value))?,
+                Value::Boolean(false)
+            ) {
+                return Ok(false);
             }
+            for pattern in patterns {
+                if !try_match(pattern, value, predicates, slots)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
         }
-        new_env
+        (Pattern::And(patterns), _) => {
+            for pattern in patterns {
+                if !try_match(pattern, value, predicates, slots)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        (Pattern::Or(patterns), _) => {
+            for pattern in patterns {
+                if try_match(pattern, value, predicates, slots)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        _ => Ok(false),
     }
 }
 
@@ -517,23 +1203,39 @@ pub struct Runner {
     stmts: IntoIter<Statement>,
     stmt_count: usize,
     output: Vec<Output>,
-    env: Rc<RefCell<Env>>,
+    globals: Rc<RefCell<Globals>>,
     current_stmt: usize,
+    memory_limit: usize,
+    files: Rc<Files>,
 }
 
 impl Runner {
-    pub fn new(program: Ast, env: Option<Rc<RefCell<Env>>>) -> Self {
+    /// Creates a runner for a program that has been resolved against `globals`.
+    pub fn new(program: Ast, globals: Option<Rc<RefCell<Globals>>>) -> Self {
         let stmt_count = program.statements.len();
         let stmts = program.statements.into_iter();
         Self {
             stmts,
             stmt_count,
             output: Vec::new(),
-            env: env.unwrap_or(Rc::new(RefCell::new(Env::new(None)))),
+            globals: globals.unwrap_or_default(),
             current_stmt: 0,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            files: Rc::default(),
         }
     }
 
+    /// Sets the files the program's file imports were prepared by.
+    pub fn with_files(mut self, files: Rc<Files>) -> Self {
+        self.files = files;
+        self
+    }
+
+    pub fn with_memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
     pub fn get_output(self) -> Vec<Output> {
         self.output
     }
@@ -548,6 +1250,7 @@ impl Runner {
         };
 
         self.current_stmt += 1;
+        let _files = self.files.enter();
 
         match stmt.kind {
             StatementKind::Binding { name, body } => {
@@ -556,9 +1259,10 @@ impl Runner {
             StatementKind::Expression { body } => {
                 self.step_expr(body);
             }
-            StatementKind::Import { mod_name } => {
-                self.step_import(mod_name, stmt.span)?;
+            StatementKind::Import { source, filter } => {
+                self.step_import(source, filter, stmt.span)?;
             }
+            StatementKind::Provide { .. } => {}
             StatementKind::Display { body } => {
                 self.step_expr(body);
             }
@@ -584,9 +1288,10 @@ impl Runner {
 
 impl Runner {
     fn step_define(&self, name: String, body: Block) -> Result<()> {
-        assert_not_reserved(&name)?;
-        let mut interpreter = ExecutionStack::new(Rc::clone(&self.env), body);
-        _ = interpreter.run()?;
+        let code = compile(body, Params::default())?;
+        let mut interpreter = ExecutionStack::new(Rc::clone(&self.globals), None, code)
+            .with_memory_limit(self.memory_limit);
+        self.run_stack(&mut interpreter)?;
 
         let value = interpreter.stack.pop();
         match value {
@@ -597,7 +1302,7 @@ impl Runner {
                 } else {
                     value
                 };
-                self.env.borrow_mut().set(name, value);
+                self.globals.borrow_mut().define(&name, value);
             }
             None => {
                 todo!();
@@ -607,52 +1312,82 @@ impl Runner {
         Ok(())
     }
 
-    fn step_import(&mut self, mod_name: String, span: Span) -> Result<()> {
-        match mod_name.as_str() {
-            "image" => {
-                crate::modules::image::add_to(&mut self.env.borrow_mut());
-            }
-            "lab" => {
-                crate::modules::lab::add_to(&mut self.env.borrow_mut());
-            }
-            "music" => {
-                crate::modules::music::add_to(&mut self.env.borrow_mut());
-            }
-            _ => {
-                return Err(RuntimeError::new(
-                    format!("Module {} not found", mod_name),
-                    Some(span),
-                ));
+    // runs the code of a statement. Continuations captured by earlier
+    // statements resume in place of the rest of the statement.
+    fn run_stack(&self, interpreter: &mut ExecutionStack) -> Result<()> {
+        loop {
+            let Err(err) = interpreter.run() else {
+                return Ok(());
+            };
+            let Some(Control::Resume(continuation, value)) = err.control.as_deref() else {
+                return Err(err);
+            };
+            *interpreter = ExecutionStack::resume(continuation, value.clone())
+                .with_memory_limit(self.memory_limit);
+        }
+    }
+
+    fn step_import(
+        &mut self,
+        source: ImportSource,
+        filter: ImportFilter,
+        span: Span,
+    ) -> Result<()> {
+        let module = match &source {
+            ImportSource::Module(mod_name) => {
+                let Some(module) = crate::modules::load(mod_name) else {
+                    return Err(RuntimeError::new(
+                        format!("Module {} not found", mod_name),
+                        Some(span),
+                    ));
+                };
+                module
             }
+            ImportSource::File(path) => self.files.evaluate(path, span, self.memory_limit)?,
         };
+        let mut imported = Env::new();
+        for (name, value) in module {
+            if let Some(name) = filter.rename(&name) {
+                imported.set(name, value);
+            }
+        }
+        let mut globals = self.globals.borrow_mut();
+        globals.define_all(imported);
+        if let ImportSource::File(path) = &source {
+            // the file's macros may refer to any of its bindings
+            globals.define_all(self.files.hidden_bindings(path));
+        }
         Ok(())
     }
 
     fn step_struct(&mut self, id: String, fields: Vec<String>) -> Result<()> {
-        assert_not_reserved(&id)?;
-        for field in &fields {
-            assert_not_reserved(field)?;
-        }
-
         let s = Struct {
             kind: id,
             fields,
             values: vec![],
         };
 
-        s.add_to(&mut self.env.borrow_mut(), None, None);
+        let mut env = Env::new();
+        s.add_to(&mut env, None, None);
+        self.globals.borrow_mut().define_all(env);
 
         Ok(())
     }
 
     fn step_expr(&mut self, body: Block) {
-        let mut interpreter = ExecutionStack::new(Rc::clone(&self.env), body);
-        match interpreter.run() {
+        let code = match compile(body, Params::default()) {
+            Ok(code) => code,
+            Err(e) => return self.output.push(Output::Error(e)),
+        };
+        let mut interpreter = ExecutionStack::new(Rc::clone(&self.globals), None, code)
+            .with_memory_limit(self.memory_limit);
+        match self.run_stack(&mut interpreter) {
             Ok(_) => {
                 let value = interpreter.stack.pop();
                 match value {
                     Some(value) => {
-                        self.output.push(Output::Value(value));
+                        // vectors and boxes may be mutated by later statements
+                        self.output.push(Output::Value(value.snapshot()));
                     }
                     None => {
                         todo!();
@@ -663,17 +1398,3 @@ impl Runner {
         }
     }
 }
-
-fn assert_not_reserved(identifier: &str) -> Result<()> {
-    if RESERVED_WORDS.contains(&identifier) {
-        Err(RuntimeError::new(
-            format!(
-                "\"{}\" is a reserved word and cannot be used as an identifier",
-                identifier
-            ),
-            None,
-        ))
-    } else {
-        Ok(())
-    }
-}
@@ -1,9 +1,17 @@
+mod bytecode;
+mod compiler;
 mod engine;
 mod env;
 mod error;
 mod eval;
 
+#[cfg(test)]
+mod tests;
+
+pub use bytecode::{BranchPattern, Code, Instruction};
+pub use compiler::compile;
 pub use engine::Engine;
-pub use env::Env;
-pub use error::RuntimeError;
-pub use eval::{ExecutionStack, Output};
+pub use env::{Env, Globals, Scope};
+pub use error::{ErrorObject, RuntimeError, StackFrame};
+pub(crate) use eval::{call_function, dynamic_wind, memory_limit, Control, Runner};
+pub use eval::{ExecutionStack, Output, DEFAULT_MEMORY_LIMIT};
@@ -0,0 +1,1177 @@
+use super::*;
+
+// runs a program and renders each output the way the repl would
+fn run(src: &str) -> Vec<String> {
+    run_with(&Engine::new(), src)
+}
+
+fn run_with(engine: &Engine, src: &str) -> Vec<String> {
+    engine
+        .run(src)
+        .expect("parse failed")
+        .into_iter()
+        .map(|output| match output {
+            Output::Value(value) => value.to_string(),
+            Output::Error(err) => err.message,
+            Output::Warning(warning) => format!("warning: {}", warning.message),
+        })
+        .collect()
+}
+
+fn parse_error(src: &str) -> String {
+    parse_error_with(&Engine::new(), src)
+}
+
+fn parse_error_with(engine: &Engine, src: &str) -> String {
+    match engine.run(src) {
+        Ok(_) => panic!("expected a parse error"),
+        Err(err) => err.message,
+    }
+}
+
+#[test]
+fn tail_calls() {
+    let src = "(define count-down (lambda (n) (if (= n 0) \"done\" (count-down (- n 1)))))
+        (count-down 10000)";
+    assert_eq!(run(src), vec!["\"done\""]);
+
+    // branches of cond, let, and, or and begin are all in tail position
+    let src = "(define loop
+            (lambda (n acc)
+                (cond
+                    [(= n 0) acc]
+                    [else (let ([m (- n 1)])
+                        (and #t (or #f (begin acc (loop m (+ acc 1))))))])))
+        (loop 10000 0)";
+    assert_eq!(run(src), vec!["10000"]);
+}
+
+#[test]
+fn deep_recursion() {
+    let src = "(define sum (lambda (n) (if (= n 0) 0 (+ n (sum (- n 1))))))
+        (sum 20000)";
+    assert_eq!(run(src), vec!["200010000"]);
+
+    let engine = Engine::new().with_memory_limit(64 * 1024);
+    assert_eq!(
+        run_with(&engine, src),
+        vec!["Maximum call stack size exceeded"]
+    );
+
+    // closures called by natives run on the program's stack, under its limit
+    let src = "(define (tree n) (if (= n 0) 1 (list (tree (- n 1)) 1)))
+        (define count-leaves (lambda (t) (if (list? t) (apply + (map count-leaves t)) 1)))
+        (count-leaves (tree 30))";
+    assert_eq!(run(src), vec!["31"]);
+    let src = "(define sum (lambda (n) (if (= n 0) 0 (+ n (sum (- n 1))))))
+        (map sum (list 10 20000))";
+    assert_eq!(run(src), vec!["(list 55 200010000)"]);
+    assert_eq!(
+        run_with(&engine, src),
+        vec!["Maximum call stack size exceeded"]
+    );
+}
+
+#[test]
+fn nested_sequences() {
+    assert_eq!(run("(+ 1 (begin 2 3))"), vec!["4"]);
+    assert_eq!(run("(+ (cond [#f 1] [#t 2]) 3)"), vec!["5"]);
+    assert_eq!(
+        run("(and) (or) (and #t 2) (or #f 3) (and #f 3)"),
+        vec!["#t", "#f", "2", "3", "#f"]
+    );
+}
+
+#[test]
+fn stack_traces() {
+    let src = "(define helper (lambda (x) (car x)))
+        (define middle (lambda (x) (+ 1 (helper x))))
+        (map middle (list 1))";
+    let output = Engine::new().run(src).expect("parse failed");
+    let Some(Output::Error(err)) = output.into_iter().next() else {
+        panic!("expected an error");
+    };
+    let names = err
+        .stack_trace
+        .iter()
+        .map(|frame| frame.name.clone().unwrap_or_default())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["car", "helper", "middle", "map"]);
+    assert!(err
+        .emit_to_string(src)
+        .ends_with("context...:\n   1:27 car\n   2:41 helper\n   middle\n   3:9 map"));
+}
+
+#[test]
+fn exceptions() {
+    assert_eq!(
+        run("(with-handler (lambda (e) (list 'caught e)) (lambda () (+ 1 (raise 'oops))))"),
+        vec!["(list caught oops)"]
+    );
+    assert_eq!(
+        run("(with-handler (lambda (e) (error-object-message e)) error \"bad\")"),
+        vec!["\"bad\""]
+    );
+    assert_eq!(
+        run("(with-handler error-object? car 1) (with-handler error-object? + 1 2)"),
+        vec!["#t", "3"]
+    );
+    assert_eq!(
+        run("(with-handler (lambda (e) (raise e)) error \"again\")"),
+        vec!["again"]
+    );
+    assert_eq!(run("(raise 5)"), vec!["uncaught exception: 5"]);
+}
+
+#[test]
+fn mutable_vectors() {
+    assert_eq!(
+        run("(define v (vector 1 2 3))
+            (define w v)
+            (vector-set! w 0 10)
+            v
+            (vector-map! (lambda (x) (* x 2)) v)
+            w
+            (vector-fill! v 0)
+            w"),
+        vec![
+            "void",
+            "(vector 10 2 3)",
+            "void",
+            "(vector 20 4 6)",
+            "void",
+            "(vector 0 0 0)"
+        ]
+    );
+    assert_eq!(
+        run("(define v (vector 1 2))
+            (eq? v v) (eq? v (vector 1 2)) (equal? v (vector 1 2))"),
+        vec!["#t", "#f", "#t"]
+    );
+    // each evaluation of a vector literal makes a new vector
+    assert_eq!(
+        run("(define (pair-of-numbers) [1 2])
+            (define a (pair-of-numbers))
+            (vector-set! a 0 10)
+            (list a (pair-of-numbers))
+            (define (nested) '(1 [2]))
+            (vector-set! (list-ref (nested) 1) 0 20)
+            (nested)"),
+        vec![
+            "void",
+            "(list (vector 10 2) (vector 1 2))",
+            "void",
+            "(list 1 (vector 2))"
+        ]
+    );
+    assert_eq!(
+        run("(vector-map + (vector 1 2) (vector 10 20))"),
+        vec!["(vector 11 22)"]
+    );
+    assert_eq!(
+        run("(vector-set! (vector 1) 1 0)"),
+        vec!["vector-set!: index 1 out of bounds"]
+    );
+
+    // vectors that contain themselves are printed with datum labels, and
+    // compared without following the cycle forever
+    assert_eq!(
+        run("(define v (vector 1 2))
+            (vector-set! v 0 v)
+            v
+            (define w (vector 1 2))
+            (vector-set! w 0 w)
+            (equal? v w)
+            (list v v)"),
+        vec![
+            "void",
+            "#0=(vector #0# 2)",
+            "void",
+            "#t",
+            "(list #0=(vector #0# 2) #0#)"
+        ]
+    );
+}
+
+#[test]
+fn assignment() {
+    assert_eq!(
+        run("(define count 0)
+            (define increment (lambda () (begin (set! count (+ count 1)) count)))
+            (increment) (increment) count"),
+        vec!["1", "2", "2"]
+    );
+    assert_eq!(
+        run("(define make-counter
+               (lambda () (let ([n 0]) (lambda () (begin (set! n (+ n 1)) n)))))
+            (define c1 (make-counter))
+            (define c2 (make-counter))
+            (c1) (c1) (c2)"),
+        vec!["1", "2", "1"]
+    );
+    assert_eq!(
+        run("(define b (box 1)) (set-box! b (+ (unbox b) 1)) b (unbox b)"),
+        vec!["void", "(box 2)", "2"]
+    );
+    // boxes that contain themselves can be printed, compared and used as
+    // keys of hash tables
+    assert_eq!(
+        run("(define b (box 0))
+            (set-box! b b)
+            b
+            (equal? b (box b))
+            (define h (make-hash))
+            (hash-set! h b 'found)
+            (hash-ref h (box (box b)))"),
+        vec!["void", "#0=(box #0#)", "#t", "void", "found"]
+    );
+    assert_eq!(
+        parse_error("(set! x 1)"),
+        "Referenced unbound identifier `x`"
+    );
+}
+
+#[test]
+fn shared_environments() {
+    assert_eq!(
+        run("(define make-adder (lambda (n) (lambda (x) (+ x n))))
+            ((make-adder 1) 2)
+            (define n 10)
+            ((make-adder 1) 2)"),
+        vec!["3", "3"]
+    );
+    assert_eq!(
+        parse_error("(set! car 1)"),
+        "Cannot assign to built-in identifier `car`"
+    );
+    assert_eq!(run("(define car 2) (set! car 3) car"), vec!["void", "3"]);
+    // definitions don't leak into the prelude other programs see
+    assert_eq!(run("car"), vec!["car"]);
+}
+
+#[test]
+fn resolution() {
+    // top-level definitions are visible before they run
+    assert_eq!(
+        run("(define f (lambda () (g))) (define g (lambda () 1)) (f)"),
+        vec!["1"]
+    );
+    assert_eq!(
+        run("x (define x 1) x"),
+        vec!["Referenced identifier `x` before its definition", "1"]
+    );
+    // inner bindings shadow outer ones
+    assert_eq!(
+        run("(define x 1)
+            (let ([x 2] [y x]) (let ([x (+ x 10)]) (list x y)))
+            (match (pair 1 2) [(pair x y) (+ x y)])"),
+        vec!["(list 12 1)", "3"]
+    );
+
+    // unbound identifiers are reported before anything runs
+    let engine = Engine::new();
+    let err = engine.run("(define y 1) (+ y z)").unwrap_err();
+    assert_eq!(err.message, "Referenced unbound identifier `z`");
+    assert_eq!(err.span.map(|span| span.loc), Some(18));
+    assert_eq!(
+        parse_error("(let ([if 1]) if)"),
+        "\"if\" is a reserved word and cannot be used as an identifier"
+    );
+
+    // definitions carry over between runs of the same engine, unless the run failed to resolve
+    assert!(engine.run("y").is_err());
+    assert_eq!(run_with(&engine, "(define y 2)"), Vec::<String>::new());
+    assert_eq!(run_with(&engine, "(+ y 1)"), vec!["3"]);
+
+    // only names the program itself defines can be referred to before their definition
+    assert_eq!(
+        parse_error("(define (f) (lenght 1)) (f)"),
+        "Referenced unbound identifier `lenght`"
+    );
+    assert_eq!(
+        parse_error_with(&engine, "(define f (lambda (n) (g n)))"),
+        "Referenced unbound identifier `g`"
+    );
+    assert_eq!(
+        parse_error("(define h (let ([n 1]) (k n)))"),
+        "Referenced unbound identifier `k`"
+    );
+}
+
+#[test]
+fn bytecode() {
+    let mut globals = Globals::new();
+    let src = "(define f (lambda (n) (if (= n 0) (and #t 1) (f (- n 1)))))";
+    let ast = crate::parser::resolve(
+        crate::parser::parse(src).unwrap(),
+        &mut globals,
+        &crate::modules::Files::default(),
+    )
+    .unwrap();
+    let Some(crate::ast::StatementKind::Binding { body, .. }) =
+        ast.statements.into_iter().next().map(|stmt| stmt.kind)
+    else {
+        panic!("expected a binding");
+    };
+    let code = compile(body, crate::ast::Params::default()).unwrap();
+    let lambda = &code.functions[0];
+    assert_eq!(lambda.params, vec!["n"]);
+    let eq = globals.index_of("=").unwrap() as u32;
+    let sub = globals.index_of("-").unwrap() as u32;
+    let f = globals.index_of("f").unwrap() as u32;
+    use Instruction::*;
+    assert_eq!(
+        lambda.instructions,
+        vec![
+            Global(eq),
+            Local { depth: 0, index: 0 },
+            Constant(0),
+            Call(2),
+            If(9),
+            Constant(1),
+            And(8),
+            Constant(2),
+            // the label after `and` is the end of the branch
+            Return,
+            Global(f),
+            Global(sub),
+            Local { depth: 0, index: 0 },
+            Constant(3),
+            Call(2),
+            TailCall(1),
+            Return,
+            Return,
+        ]
+    );
+}
+
+#[test]
+fn scoped_blocks() {
+    // let and match bodies outside tail position restore the enclosing frame
+    assert_eq!(
+        run("(define x 1)
+            (let ([y 2]) (+ (let ([x 10]) x) (match (pair 3 4) [(pair a b) (+ a b)] [_ 0]) x y))
+            (+ 1 (match 5 [1 1] [n (* n 2)]))"),
+        vec!["20", "11"]
+    );
+    assert_eq!(
+        run("(+ 1 (match 5 [1 1]))"),
+        vec!["No pattern matches for 5"]
+    );
+    assert_eq!(
+        run("(+ 1 (cond [#f 1]))"),
+        vec!["No branches of \"cond\" expression matched"]
+    );
+}
+
+#[test]
+fn numeric_tower() {
+    assert_eq!(
+        run("(/ 1 3) (+ 1/3 2/3) (expt 2 100) (- (expt 2 64) (expt 2 64) 1)"),
+        vec!["1/3", "1", "1267650600228229401496703205376", "-1"]
+    );
+    // inexactness is contagious
+    assert_eq!(
+        run("(* 2.0 3) (+ 1/2 0.5) (max 1 2.0) (range 0 1 0.5) (sqrt 16) (sqrt 2)"),
+        vec![
+            "6.0",
+            "1.0",
+            "2.0",
+            "(list 0.0 0.5)",
+            "4",
+            "1.4142135623730951"
+        ]
+    );
+    assert_eq!(
+        run(
+            "(exact->inexact 1/4) (inexact->exact 0.25) (exact? 1/2) (inexact? 1)
+            (numerator 6/4) (denominator 6/4) (denominator 0.5)"
+        ),
+        vec!["0.25", "1/4", "#t", "#f", "3", "2", "2.0"]
+    );
+    assert_eq!(
+        run("(quotient (expt 10 20) 3) (modulo -7 2) (floor 7/2) (= 1/2 0.5) (< 1/3 0.33)"),
+        vec!["33333333333333333333", "1", "3", "#t", "#f"]
+    );
+    assert_eq!(
+        run("(/ 1 0) (quotient 1 1/2) (string->number \"-2/6\")"),
+        vec!["Division by zero", "expected integer arguments", "-1/3"]
+    );
+    // exact numbers compare with floats by the float's exact value
+    assert_eq!(
+        run("(define big (expt 2 53))
+            (define float (exact->inexact big))
+            (list (= big float) (= (+ big 1) float) (< float (+ big 1)) (> 1/3 0.3333333333333333))
+            (define inf (exp 1000.0))
+            (list (< (expt 10 400) inf) (> (expt 10 400) (- inf)) (= 1 (- inf inf)) (< 1 (- inf inf)))"),
+        vec!["(list #t #f #t #t)", "(list #t #t #f #f)"]
+    );
+    // exact powers too large to store are refused rather than computed
+    assert_eq!(
+        run("(expt 2 1000000000) (expt -1 1000000001) (expt 1/2 -3) (expt 0 10000000000)"),
+        vec!["Result is too large to compute exactly", "-1", "8", "0"]
+    );
+    // halfway cases round to even, and transcendental functions are exact
+    // where their result is
+    assert_eq!(
+        run("(round 2.5) (round -3.5) (round 5/2) (round 7/2) (exp 0) (sin 0) (cos 0) (exp 0.0)"),
+        vec!["2.0", "-4.0", "2", "4", "1", "0", "1", "1.0"]
+    );
+    assert_eq!(parse_error("(+ 1/2.5 1)"), "invalid number literal");
+    assert_eq!(parse_error("(+ 1/2/3 1)"), "invalid number literal");
+}
+
+#[test]
+fn match_patterns() {
+    assert_eq!(
+        run("(struct point (x y))
+            (match (point 3 4) [(point x y) (+ x y)])
+            (define sum (lambda (l) (match l [null 0] [(cons h t) (+ h (sum t))])))
+            (sum (list 1 2 3))
+            (match (list 1) [(cons h t) t])
+            (match (vector 1 2) [(vector a b) (list b a)])"),
+        vec!["7", "6", "null", "(list 2 1)"]
+    );
+    assert_eq!(
+        run("(struct point (x y))
+            (match 5 [(? even?) \"even\"] [(? odd? n) (* n 10)])
+            (match (list 1 2) [(and l (list a _)) (list a l)])
+            (match 4 [(or 1 2) \"small\"] [(or (? odd? n) (? even? n)) n])
+            (match 'b ['a 1] ['b 2])
+            (match (point 1 2) [(point (? even?) y) y] [_ \"other\"])"),
+        vec!["50", "(list 1 (list 1 2))", "4", "2", "\"other\""]
+    );
+
+    // a value no branch matches is reported at the scrutinee
+    let src = "(define f (lambda (v) (match v [1 \"one\"])))\n(f 2)";
+    let output = Engine::new().run(src).expect("parse failed");
+    let Some(Output::Error(err)) = output.into_iter().next() else {
+        panic!("expected an error");
+    };
+    assert_eq!(err.message, "No pattern matches for 2");
+    assert_eq!(err.span.map(|span| span.loc), Some(29));
+
+    assert_eq!(
+        parse_error("(match 1 [(or a 2) 1])"),
+        "every alternative of an or pattern must bind the same variables"
+    );
+    assert_eq!(
+        parse_error("(match (list 1 2) [(list x x) x])"),
+        "pattern binds `x` more than once"
+    );
+    assert_eq!(
+        parse_error("(match 1 [(and x (? odd? x)) x])"),
+        "pattern binds `x` more than once"
+    );
+    assert_eq!(
+        run("(match (list 2 1) [(or (list x 1) (list 1 x)) x])"),
+        vec!["2"]
+    );
+    assert_eq!(
+        parse_error("(struct point (x y)) (match (point 1 2) [(pont x y) x])"),
+        "Unknown struct `pont` in pattern"
+    );
+}
+
+#[test]
+fn file_imports() {
+    use std::collections::HashMap;
+
+    let files = HashMap::from([
+        (
+            "helpers.scm".to_string(),
+            "(import \"counter.scm\")
+            (define double (lambda (x) (* 2 (next x))))
+            (struct point (x y))"
+                .to_string(),
+        ),
+        (
+            "counter.scm".to_string(),
+            "(define count (vector 0))
+            (vector-set! count 0 (+ (vector-ref count 0) 1))
+            (define next (lambda (x) (+ x (vector-ref count 0))))"
+                .to_string(),
+        ),
+        ("a.scm".to_string(), "(import \"b.scm\")".to_string()),
+        ("b.scm".to_string(), "(import \"a.scm\")".to_string()),
+        ("bad.scm".to_string(), "(define x (car null))".to_string()),
+        (
+            "lib/shapes.scm".to_string(),
+            "(import \"../counter.scm\") (import \"units.scm\")".to_string(),
+        ),
+        ("lib/units.scm".to_string(), "(define unit 10)".to_string()),
+    ]);
+    let engine = Engine::new().with_loader(files.clone());
+
+    // definitions are visible and each file only runs once, however often it's imported
+    assert_eq!(
+        run_with(
+            &engine,
+            "(import \"helpers.scm\") (import \"counter.scm\") (import \"helpers.scm\")
+            (double 1) (point-y (point 1 2)) (vector-ref count 0)"
+        ),
+        vec!["4", "2", "1"]
+    );
+    // paths are relative to the importing file, and name the same file
+    // however they're written
+    assert_eq!(
+        run_with(
+            &engine,
+            "(import \"./lib/../lib/shapes.scm\") (import \"lib/units.scm\")
+            (list unit (vector-ref count 0))"
+        ),
+        vec!["(list 10 1)"]
+    );
+    // names a file imports itself aren't brought along
+    assert_eq!(
+        Engine::new()
+            .with_loader(files.clone())
+            .run("(import \"helpers.scm\") count")
+            .unwrap_err()
+            .message,
+        "Referenced unbound identifier `count`"
+    );
+    assert_eq!(
+        engine.run("(import \"x.scm\")").unwrap_err().message,
+        "Cannot import \"x.scm\": file not found"
+    );
+
+    assert_eq!(
+        engine.run("(import \"a.scm\")").unwrap_err().message,
+        "Import cycle: a.scm -> b.scm -> a.scm"
+    );
+    assert_eq!(
+        run_with(&engine, "(import \"bad.scm\") 1"),
+        vec!["Expected a pair (in bad.scm)", "1"]
+    );
+    assert_eq!(
+        parse_error("(import \"helpers.scm\")"),
+        "Cannot import \"helpers.scm\": file imports are not available here"
+    );
+}
+
+#[test]
+fn import_filters() {
+    use std::collections::HashMap;
+
+    let files = HashMap::from([(
+        "shapes.scm".to_string(),
+        "(provide area unit)
+        (define factor 3)
+        (define area (lambda (r) (* factor r r)))
+        (define unit 1)"
+            .to_string(),
+    )]);
+    let engine = Engine::new().with_loader(files.clone());
+
+    // only provided names are visible to importers
+    assert_eq!(
+        run_with(&engine, "(import \"shapes.scm\") (area 2)"),
+        vec!["12"]
+    );
+    assert_eq!(
+        parse_error_with(&engine, "factor"),
+        "Referenced unbound identifier `factor`"
+    );
+    assert_eq!(
+        run_with(
+            &Engine::new().with_loader(files.clone()),
+            "(import \"shapes.scm\" :prefix s/ :only (area)) (s/area 1)"
+        ),
+        vec!["3"]
+    );
+    assert_eq!(
+        parse_error_with(
+            &Engine::new().with_loader(files),
+            "(import \"shapes.scm\" :prefix s/) area"
+        ),
+        "Referenced unbound identifier `area`"
+    );
+    assert_eq!(
+        parse_error("(import image :only (circle pi))"),
+        "`pi` is not provided by module image"
+    );
+    assert_eq!(
+        parse_error("(define x 1) (provide x y)"),
+        "Cannot provide unbound identifier `y`"
+    );
+    assert_eq!(
+        parse_error("(import image :depth 2)"),
+        "unknown import option :depth, expected :prefix or :only"
+    );
+
+    // imports that replace existing bindings are reported, unless they import the same ones again
+    let engine = Engine::new();
+    assert_eq!(
+        run_with(&engine, "(import image) (import image)"),
+        vec!["warning: Importing module image shadows existing bindings: `square`"]
+    );
+    assert_eq!(run_with(&engine, "(import image)"), Vec::<String>::new());
+    assert_eq!(
+        run_with(
+            &Engine::new(),
+            "(import image :prefix img/) (img/square 1 \"solid\" \"red\") (square 3)"
+        )[1..],
+        ["9"]
+    );
+}
+
+#[test]
+fn macros() {
+    let engine = Engine::new();
+    assert_eq!(
+        run_with(
+            &engine,
+            "(define-syntax swap!
+                (syntax-rules ()
+                    [(_ a b) (let ([tmp a]) (begin (set! a b) (set! b tmp)))]))
+            (define-syntax my-or
+                (syntax-rules ()
+                    [(_) #f]
+                    [(_ e) e]
+                    [(_ e r ...) (let ([t e]) (if t t (my-or r ...)))]))
+            (define-syntax for
+                (syntax-rules (in)
+                    [(_ x in lst body) (map (lambda (x) body) lst)]))
+            (define tmp 1)
+            (define other 2)
+            (swap! tmp other)
+            (list tmp other)
+            (let ([t 5]) (my-or #f t))
+            (for y in (list 1 2 3) (* y y))"
+        ),
+        vec!["void", "(list 2 1)", "5", "(list 1 4 9)"]
+    );
+
+    // free identifiers in a template refer to the top level, not to locals at the use site
+    assert_eq!(
+        run_with(
+            &engine,
+            "(define-syntax unless (syntax-rules () [(_ c body) (if c void body)]))
+            (let ([void 3]) (unless #t 7))
+            '(unless x)"
+        ),
+        vec!["void", "(list unless x)"]
+    );
+
+    // macros carry over between runs, and errors inside expansions point at the use site
+    let src = "(define-syntax add-a (syntax-rules () [(_ x) (+ x \"a\")]))\n(add-a (unless #f 1))";
+    let output = engine.run(src).expect("parse failed");
+    let Some(Output::Error(err)) = output.into_iter().next() else {
+        panic!("expected an error");
+    };
+    assert_eq!(err.span.map(|span| span.loc), Some(58));
+    assert_eq!(
+        parse_error_with(&engine, "(unless 1 2 3)"),
+        "no syntax rule of macro `unless` matches this use"
+    );
+    assert_eq!(
+        parse_error("(define-syntax loop (syntax-rules () [(_) (loop)])) (loop)"),
+        "macro expansion is too deeply nested"
+    );
+}
+
+#[test]
+fn imported_macros() {
+    use std::collections::HashMap;
+
+    let files = HashMap::from([(
+        "loops.scm".to_string(),
+        "(provide while)
+        (define-syntax while
+            (syntax-rules ()
+                [(_ c body ...)
+                 (let ([loop (lambda (loop) (if c (begin body ... (loop loop)) void))])
+                     (loop loop))]))
+        (define-syntax hidden (syntax-rules () [(_) 1]))"
+            .to_string(),
+    )]);
+    assert_eq!(
+        run_with(
+            &Engine::new().with_loader(files.clone()),
+            "(import \"loops.scm\" :only (while))
+            (define i (vector 0))
+            (define loop 0)
+            (while (< (vector-ref i 0) 3)
+                (vector-set! i 0 (+ (vector-ref i 0) 1))
+                (set! loop (+ loop 1)))
+            (list (vector-ref i 0) loop)"
+        ),
+        vec!["void", "(list 3 3)"]
+    );
+    assert_eq!(
+        parse_error_with(
+            &Engine::new().with_loader(files),
+            "(import \"loops.scm\") (hidden)"
+        ),
+        "Referenced unbound identifier `hidden`"
+    );
+
+    // identifiers a macro introduces refer to the bindings of its own file,
+    // even ones the file doesn't provide or the importer also defines
+    let files = HashMap::from([(
+        "scale.scm".to_string(),
+        "(provide scale)
+        (define (helper x) (* x 100))
+        (define-syntax scale (syntax-rules () [(_ x) (helper x)]))"
+            .to_string(),
+    )]);
+    assert_eq!(
+        run_with(
+            &Engine::new().with_loader(files.clone()),
+            "(import \"scale.scm\") (scale 1)"
+        ),
+        vec!["100"]
+    );
+    let engine = Engine::new().with_loader(files);
+    assert_eq!(
+        run_with(
+            &engine,
+            "(define (helper x) x) (import \"scale.scm\") (list (scale 2) (helper 2))"
+        ),
+        vec!["(list 200 2)"]
+    );
+    assert_eq!(run_with(&engine, "(scale 3)"), vec!["300"]);
+}
+
+#[test]
+fn internal_definitions() {
+    assert_eq!(
+        run("(define (square x) (* x x))
+            (square 5)
+            (define (parity n)
+                (define (even? n) (if (= n 0) #t (odd? (- n 1))))
+                (define (odd? n) (if (= n 0) #f (even? (- n 1))))
+                (if (even? n) \"even\" \"odd\"))
+            (parity 7)
+            (let ([x 2])
+                (define y (* x 3))
+                (+ x y))
+            (letrec ([even? (lambda (n) (if (= n 0) #t (odd? (- n 1))))]
+                     [odd? (lambda (n) (if (= n 0) #f (even? (- n 1))))])
+                (even? 10))
+            (letrec ([a 1] [b (+ a 1)]) b)"),
+        vec!["25", "\"odd\"", "8", "#t", "2"]
+    );
+
+    // macros used in a body can expand into definitions
+    assert_eq!(
+        run(
+            "(define-syntax define-one (syntax-rules () [(_ x) (define x 1)]))
+            (define (f) (define-one a) (+ a 1))
+            (f)"
+        ),
+        vec!["2"]
+    );
+
+    assert_eq!(
+        parse_error("(lambda (x) (define y x))"),
+        "a body must end with an expression"
+    );
+    assert_eq!(
+        parse_error("(define (f) 1 (define a 2) a)"),
+        "definitions must come before the expressions of a body"
+    );
+    assert_eq!(
+        parse_error("(define (f) (define a 1) (define a 2) a)"),
+        "`a` is defined more than once in the same body"
+    );
+}
+
+#[test]
+fn bodies_and_named_let() {
+    assert_eq!(
+        run("(define log (vector null))
+            (define (note! x) (vector-set! log 0 (cons x (vector-ref log 0))))
+            (define (twice x) (note! x) (* x 2))
+            (twice 3)
+            (let ([y 1]) (note! y) (set! y 2) y)
+            (cond [#f 0] [else (note! \"cond\") 1])
+            (match 5 [n (note! n) (+ n 1)])
+            (vector-ref log 0)"),
+        vec!["6", "2", "1", "6", "(list 5 \"cond\" 1 3)"]
+    );
+
+    // the initial values are evaluated outside the scope of the loop's name
+    assert_eq!(
+        run("(let loop ([i 0] [acc null])
+                (if (= i 3) (reverse acc) (loop (+ i 1) (cons i acc))))
+            (let count-down ([n 100000]) (if (= n 0) \"done\" (count-down (- n 1))))
+            (define loop 7)
+            (let loop ([i loop]) i)"),
+        vec!["(list 0 1 2)", "\"done\"", "7"]
+    );
+}
+
+#[test]
+fn variadic_functions() {
+    assert_eq!(
+        run("(define (range-of a [b (+ a 1)] [c (* b 2)]) (list a b c))
+            (range-of 1)
+            (range-of 1 5)
+            (range-of 1 5 7)
+            (define (tagged tag . items) (cons tag items))
+            (tagged 'a)
+            (tagged 'a 1 2)
+            ((lambda args args) 1 2 3)
+            (map (lambda (x [y 10]) (+ x y)) (list 1 2))
+            (lambda (a [b 1] . rest) a)"),
+        vec![
+            "(list 1 2 4)",
+            "(list 1 5 10)",
+            "(list 1 5 7)",
+            "(list a)",
+            "(list a 1 2)",
+            "(list 1 2 3)",
+            "(list 11 12)",
+            "(lambda (a b . rest) <body>)",
+        ]
+    );
+
+    assert_eq!(
+        run("(define (range-of a [b 1] [c 2]) a)
+            (range-of)
+            (range-of 1 2 3 4)
+            (define (tagged tag . items) tag)
+            (tagged)
+            ((lambda (x) x))"),
+        vec![
+            "Function `range-of` expected 1 to 3 arguments, passed 0 instead",
+            "Function `range-of` expected 1 to 3 arguments, passed 4 instead",
+            "Function `tagged` expected at least 1 argument, passed 0 instead",
+            "Function expected 1 argument, passed 0 instead",
+        ]
+    );
+    assert_eq!(
+        parse_error("(lambda ([a 1] b) a)"),
+        "required parameters must come before optional ones"
+    );
+}
+
+#[test]
+fn quasiquotes() {
+    assert_eq!(
+        run("(define x 5)
+            (define xs (list 1 2))
+            `(a ,x (b ,(* x 2)) [c ,x])
+            `(start ,@xs ,@null end)
+            (let ([cons 0] [append 0]) `(,x ,@xs))
+            `(1 `(2 ,(3 ,x)))
+            (define (deriv e)
+                (match e
+                    [(list '+ a b) `(+ ,(deriv a) ,(deriv b))]
+                    [(list '* a b) `(+ (* ,(deriv a) ,b) (* ,a ,(deriv b)))]
+                    ['x 1]
+                    [_ 0]))
+            (deriv '(* x (+ x 3)))"),
+        vec![
+            "(list a 5 (list b 10) (vector c 5))",
+            "(list start 1 2 end)",
+            "(list 5 1 2)",
+            "(list 1 (list quasiquote (list 2 (list unquote (list 3 5)))))",
+            "(list + (list * 1 (list + x 3)) (list * x (list + 1 0)))",
+        ]
+    );
+
+    assert_eq!(
+        parse_error("(list ,x)"),
+        "unquote must be used inside of a quasiquote"
+    );
+    assert_eq!(
+        parse_error("`,@x"),
+        "unquote-splicing must be used inside of a list"
+    );
+}
+
+#[test]
+fn continuations() {
+    assert_eq!(
+        run("(+ 1 (call/cc (lambda (k) (+ 10 (k 5)))))
+            (define (find-first pred lst)
+                (call/cc (lambda (return)
+                    (map (lambda (x) (if (pred x) (return x) void)) lst)
+                    #f)))
+            (find-first even? (list 1 3 4 5))
+            (find-first even? (list 1 3))
+            (call-with-current-continuation (lambda (k) (for-range 0 10 (lambda (i) (if (= i 3) (k i) void))) -1))"),
+        vec!["6", "4", "#f", "3"]
+    );
+
+    // continuations can be resumed after call/cc returns, even from a later statement
+    assert_eq!(
+        run("(define saved (vector #f))
+            (define count (vector 0))
+            (let ([n (call/cc (lambda (k) (vector-set! saved 0 k) 0))])
+                (vector-set! count 0 (+ (vector-ref count 0) 1))
+                (if (< n 3) ((vector-ref saved 0) (+ n 1)) (list n (vector-ref count 0))))
+            (+ 100 (call/cc (lambda (k) (vector-set! saved 0 k) 1)))
+            ((vector-ref saved 0) 5)"),
+        vec!["(list 3 4)", "101", "105"]
+    );
+
+    // leaving a dynamic-wind early still runs its cleanup, and handlers don't
+    // catch jumps to continuations
+    assert_eq!(
+        run("(define log (vector null))
+            (define (note! x) (vector-set! log 0 (cons x (vector-ref log 0))))
+            (call/cc (lambda (k)
+                (dynamic-wind (lambda () (note! 'in)) (lambda () (k 1)) (lambda () (note! 'out)))))
+            (with-handler
+                (lambda (e) (error-object-message e))
+                (lambda () (dynamic-wind (lambda () (note! 'in)) (lambda () (error \"oops\")) (lambda () (note! 'out)))))
+            (with-handler (lambda (e) 'handled) (lambda () (call/cc (lambda (k) (k 'escaped)))))
+            (vector-ref log 0)
+            (map call/cc (list (lambda (k) 1)))"),
+        vec![
+            "1",
+            "\"oops\"",
+            "escaped",
+            "(list out in out in)",
+            "(list 1)"
+        ]
+    );
+
+    // continuations captured under natives that call back into the program
+    // can leave them, and return into them after they've finished
+    assert_eq!(
+        run("(define saved (vector #f))
+            (define (gen) (map (lambda (x) (call/cc (lambda (k) (vector-set! saved 0 k) x))) (list 1 2 3)))
+            (let ([r (gen)]) (if (= (car (cdr (cdr r))) 3) ((vector-ref saved 0) 30) r))
+            (call/cc (lambda (return) (hash-for-each (hash 'a 1) (lambda (k v) (return v))) 0))
+            (define log (vector null))
+            (define (note! x) (vector-set! log 0 (cons x (vector-ref log 0))))
+            (let ([n (vector 0)])
+                (dynamic-wind
+                    (lambda () (note! 'before))
+                    (lambda () (call/cc (lambda (k) (vector-set! saved 0 k))) (note! 'during))
+                    (lambda () (note! 'after)))
+                (vector-set! n 0 (+ (vector-ref n 0) 1))
+                (if (< (vector-ref n 0) 2) ((vector-ref saved 0) #f) (reverse (vector-ref log 0))))"),
+        vec![
+            "(list 1 2 30)",
+            "1",
+            "(list before during after before during after)"
+        ]
+    );
+}
+
+#[test]
+fn hash_tables() {
+    assert_eq!(
+        run("(define counts (make-hash))
+            (define (count! word) (hash-set! counts word (+ 1 (hash-ref counts word 0))))
+            (map count! (list \"a\" \"b\" \"a\" \"c\" \"a\"))
+            counts
+            (hash-keys counts)
+            (hash-ref counts \"z\" (lambda () 'none))
+            (hash-has-key? counts \"b\")
+            (struct point (x y))
+            (define keys (make-hash (list (pair 1 'int) (pair (list 1 2) 'list) (pair (point 0 0) 'point))))
+            (list (hash-ref keys 1.0) (hash-ref keys (list 1 2)) (hash-ref keys (point 0 0)))
+            (define h (hash 'x 1))
+            (hash-set h 'y 2)
+            h
+            (equal? (hash 'x 1 'y 2) (hash-set h 'y 2))"),
+        vec![
+            "(list void void void void void)",
+            "(make-hash (list (pair \"a\" 3) (pair \"b\" 1) (pair \"c\" 1)))",
+            "(list \"a\" \"b\" \"c\")",
+            "none",
+            "#t",
+            "(list int list point)",
+            "(hash x 1 y 2)",
+            "(hash x 1)",
+            "#t",
+        ]
+    );
+
+    assert_eq!(
+        run("(hash-ref (make-hash) 'missing)
+            (hash-set! (hash) 'x 1)"),
+        vec![
+            "no value found for key missing",
+            "Expected a mutable hash table, use hash-set to extend an immutable one",
+        ]
+    );
+
+    // mutable tables are only eq? to themselves
+    assert_eq!(
+        run("(define h (make-hash))
+            (list (eq? h h) (eq? (make-hash) (make-hash)) (equal? (make-hash) (make-hash)))"),
+        vec!["(list #t #f #t)"]
+    );
+
+    // tables are built up functionally without copying their entries, and
+    // equal tables are the same key whatever order their entries are in
+    assert_eq!(
+        run(
+            "(define (build n t) (if (= n 0) t (build (- n 1) (hash-set t n (* n n)))))
+            (define t (build 20000 (hash)))
+            (list (hash-count t) (hash-ref t 777))
+            (define h (make-hash))
+            (hash-set! h (hash 1 2 3 4) 'found)
+            (list (hash-ref h (hash 3 4 1 2)) (hash-ref h (hash 3 4 1 5) 'none))"
+        ),
+        vec!["(list 20000 603729)", "void", "(list found none)"]
+    );
+}
+
+#[test]
+fn string_ports() {
+    assert_eq!(
+        run("(define out (open-output-string))
+            (write-string \"hello\" out)
+            (write-char #\\, out)
+            (write-string \" world\" out)
+            (get-output-string out)
+            (output-port? out)
+            (input-port? out)"),
+        vec!["void", "void", "void", "\"hello, world\"", "#t", "#f"]
+    );
+
+    assert_eq!(
+        run("(define nl (string (integer->char 10)))
+            (define in (open-input-string (string-append \"ab\" nl nl \"cd\")))
+            (peek-char in)
+            (read-char in)
+            (read-line in)
+            (read-line in)
+            (read-line in)
+            (eof-object? (read-line in))
+            (eof-object? (read-char in))"),
+        vec!["\\#a", "\\#a", "\"b\"", "\"\"", "\"cd\"", "#t", "#t"]
+    );
+}
+
+#[test]
+fn rasterize_drawings() {
+    use crate::ast::FromValue;
+    use crate::modules::image::Drawing;
+
+    let src = "(import image :only (beside overlay circle rectangle))
+        (beside
+            (rectangle 10 10 \"solid\" \"red\")
+            (overlay (circle 2 \"solid\" \"white\") (rectangle 10 20 \"solid\" \"blue\")))";
+    let outputs = Engine::new().run(src).expect("parse failed");
+    let Some(Output::Value(value)) = outputs.last() else {
+        panic!("expected a drawing");
+    };
+    let raster = Drawing::from_value(value)
+        .expect("expected a drawing")
+        .rasterize();
+
+    assert_eq!((raster.width(), raster.height()), (20, 20));
+    assert_eq!(raster.pixel(5, 10), Some([255, 0, 0, 255]));
+    // the shorter drawing is centered vertically, leaving the corners empty
+    assert_eq!(raster.pixel(5, 1), Some([0, 0, 0, 0]));
+    assert_eq!(raster.pixel(15, 1), Some([0, 0, 255, 255]));
+    assert_eq!(raster.pixel(15, 10), Some([255, 255, 255, 255]));
+    assert_eq!(raster.pixel(20, 0), None);
+
+    let png = raster.to_png().expect("encoding failed");
+    assert!(png.starts_with(b"\x89PNG"));
+}
+
+#[test]
+fn drawings_to_svg() {
+    let src = "(import image :only (drawing->svg beside/align with-dash circle rectangle))
+        (drawing->svg
+            (beside/align \"bottom\"
+                (circle 5 \"solid\" \"red\")
+                (with-dash (list 2 1) (rectangle 4 2 \"outline\" \"blue\"))))";
+    assert_eq!(
+        run(src),
+        vec![
+            "\"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"14\" height=\"10\" viewBox=\"0 0 14 10\">\
+            <g><ellipse cx=\"5\" cy=\"5\" rx=\"5\" ry=\"5\" fill=\"rgb(255, 0, 0)\"/>\
+            <g transform=\"translate(10 8)\"><g stroke-dasharray=\"2 1\">\
+            <rect width=\"4\" height=\"2\" fill=\"none\" stroke=\"rgb(0, 0, 255)\" stroke-width=\"1\"/>\
+            </g></g></g></svg>\"",
+        ]
+    );
+
+    // rotations are done by a transform around the corner of the rotated drawing
+    let svg = &run("(import image :only (drawing->svg rotate rectangle rgb))
+        (drawing->svg (rotate 180 (rectangle 4 2 \"solid\" (rgb 0 0 0 51))))")[0];
+    assert!(svg.contains(
+        "<g transform=\"translate(4 2) rotate(180)\">\
+        <rect width=\"4\" height=\"2\" fill=\"rgb(0, 0, 0)\" fill-opacity=\"0.2\"/></g>"
+    ));
+}
+
+#[test]
+fn bitmap_images() {
+    let src = "(import image :only (beside rectangle rgb rgb-red image-get-pixel image->pixels
+                pixels->image pixel-map pixel-filter image-width))
+        (define pic (beside (rectangle 1 1 \"solid\" \"red\") (rectangle 1 1 \"solid\" \"blue\")))
+        (image-get-pixel pic 1 0)
+        (image->pixels (pixel-map (lambda (c) (rgb 0 (rgb-red c) 0)) pic))
+        (image->pixels (pixel-filter (lambda (c) (> (rgb-red c) 0)) pic))
+        (define small (pixels->image (list \"white\" (rgb 1 2 3 4)) 1 2))
+        (list (image-width small) (image-get-pixel small 0 1))
+        (image-get-pixel small 1 0)
+        (image-get-pixel small 4294967296 0)
+        (pixels->image (vector) 4294967296 4294967296)
+        (pixels->image (vector) 2 3)";
+    assert_eq!(
+        run(src),
+        vec![
+            "(rgb 0 0 255 255)",
+            "(vector (rgb 0 255 0 255) (rgb 0 0 0 255))",
+            "(vector (rgb 255 0 0 255) (rgb 0 0 0 0))",
+            "(list 1 (rgb 1 2 3 4))",
+            "(1, 0) is outside of the 1x2 image",
+            "(4294967296, 0) is outside of the 1x2 image",
+            "a 4294967296x4294967296 image is too large",
+            "a 2x3 image needs 6 pixels, given 0",
+        ]
+    );
+
+    // images are loaded through the program's file loader
+    let dir = std::env::temp_dir().join(format!("scamper-images-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let raster = crate::modules::image::Raster::from_rgba(1, 1, vec![10, 20, 30, 255]).unwrap();
+    std::fs::write(dir.join("dot.png"), raster.to_png().unwrap()).unwrap();
+    let engine = Engine::new().with_loader(crate::modules::DiskLoader::new(&dir));
+    assert_eq!(
+        run_with(
+            &engine,
+            "(import image :only (load-image image-get-pixel))
+            (image-get-pixel (load-image \"dot.png\") 0 0)"
+        ),
+        vec!["(rgb 10 20 30 255)"]
+    );
+    assert!(
+        run("(import image :only (load-image)) (load-image \"dot.png\")")[0]
+            .starts_with("Cannot load image \"dot.png\"")
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn text_dimensions() {
+    // text is measured with the bundled face, styled like the font asked for
+    let src = "(import image :only (text font image-width image-height drawing->svg))
+        (define hello (text \"Hello\" 20 \"black\"))
+        (list (image-width hello) (image-height hello))
+        (image-width (text \"Hello\" 20 \"black\" (font \"Arial\" \"sans-serif\" #t #f)))
+        (image-width (text \"Hello\" 20 \"black\" (font \"Arial\" \"sans-serif\" #f #t)))
+        (image-width (text \"Hello\" 20 \"black\" (font \"Courier\" \"monospace\" #t #f)))
+        (image-width (text \"iiii\" 20 \"black\" (font \"Menlo\")))
+        (image-width (text \"\" 20 \"black\"))
+        (drawing->svg (text \"-\" 10 \"red\"))";
+    assert_eq!(
+        run(src),
+        vec![
+            "(list 50.693359375 23.28125)",
+            "54.86002604166667",
+            "50.693359375",
+            "60.205078125",
+            "48.1640625",
+            "0",
+            "\"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"3.6083984375\" height=\"11.640625\" \
+            viewBox=\"0 0 3.6083984375 11.640625\"><path d=\"M0.48828125 6.142578125L3.1201171875 6.142578125\
+            L3.1201171875 6.943359375L0.48828125 6.943359375L0.48828125 6.142578125Z\" fill=\"rgb(255, 0, 0)\"/></svg>\"",
+        ]
+    );
+}
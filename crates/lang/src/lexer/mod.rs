@@ -123,6 +123,15 @@ impl<'a> Lexer<'a> {
                 ']' => TokenKind::CloseDelimiter(Delimiter::Bracket),
 
                 '\'' => TokenKind::Quote,
+                '`' => TokenKind::Quasiquote,
+                ',' => {
+                    if self.peek() == '@' {
+                        self.next();
+                        TokenKind::UnquoteSplicing
+                    } else {
+                        TokenKind::Unquote
+                    }
+                }
 
                 '"' => {
                     self.eat_while(|c2| c2 != '\n' && c2 != '"');
@@ -144,14 +153,32 @@ impl<'a> Lexer<'a> {
                         TokenKind::Sequence
                     } else {
                         let mut kind = LiteralKind::Integer;
+                        // digits must come before a decimal point or slash and
+                        // after a slash, and rationals can't have decimal points
+                        let mut digits = true;
+                        let mut valid = true;
                         loop {
                             match self.peek() {
-                                c if c.is_numeric() => (),
-                                '.' => kind = LiteralKind::Float,
+                                c if c.is_numeric() => digits = true,
+                                '.' => {
+                                    valid &= kind == LiteralKind::Integer && digits;
+                                    kind = LiteralKind::Float;
+                                }
+                                '/' => {
+                                    valid &= kind == LiteralKind::Integer && digits;
+                                    kind = LiteralKind::Rational;
+                                    digits = false;
+                                }
                                 _ => break,
                             };
                             self.next();
                         }
+                        if !valid || !digits {
+                            return Err(ParseError::new(
+                                "invalid number literal",
+                                Some(self.span()),
+                            ));
+                        }
                         TokenKind::Literal(kind)
                     }
                 }
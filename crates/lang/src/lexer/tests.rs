@@ -84,6 +84,19 @@ fn number_literals() {
             Token::new(TokenKind::Eof, 3, 0)
         ]
     );
+    // rational
+    assert_eq!(
+        tokenize("-1/3").expect("lex failed"),
+        vec![
+            Token::new(TokenKind::Literal(LiteralKind::Rational), 0, 4),
+            Token::new(TokenKind::Eof, 4, 0)
+        ]
+    );
+    // malformed numbers
+    assert!(tokenize("1/2.5").is_err());
+    assert!(tokenize("1/2/3").is_err());
+    assert!(tokenize("1.2.3").is_err());
+    assert!(tokenize("1/").is_err());
 }
 
 #[test]
@@ -110,4 +123,20 @@ fn quotes() {
             Token::new(TokenKind::Eof, 8, 0)
         ]
     );
+
+    // quasiquote
+    assert_eq!(
+        tokenize("`(a ,b ,@c)").expect("lex failed"),
+        vec![
+            Token::new(TokenKind::Quasiquote, 0, 1),
+            Token::new(TokenKind::OpenDelimiter(Delimiter::Parenthesis), 1, 1),
+            Token::new(TokenKind::Sequence, 2, 1),
+            Token::new(TokenKind::Unquote, 4, 1),
+            Token::new(TokenKind::Sequence, 5, 1),
+            Token::new(TokenKind::UnquoteSplicing, 7, 2),
+            Token::new(TokenKind::Sequence, 9, 1),
+            Token::new(TokenKind::CloseDelimiter(Delimiter::Parenthesis), 10, 1),
+            Token::new(TokenKind::Eof, 11, 0)
+        ]
+    );
 }
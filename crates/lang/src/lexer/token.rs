@@ -33,6 +33,7 @@ impl Delimiter {
 pub enum LiteralKind {
     String,
     Integer,
+    Rational,
     Float,
     Boolean,
     Char,
@@ -44,8 +45,11 @@ pub enum TokenKind {
     CloseDelimiter(Delimiter),
     LineComment, // ;
     Literal(LiteralKind),
-    Quote,    // '
-    Sequence, // any other sequence of non-whitespace, non-delimiting characters
+    Quote,           // '
+    Quasiquote,      // `
+    Unquote,         // ,
+    UnquoteSplicing, // ,@
+    Sequence,        // any other sequence of non-whitespace, non-delimiting characters
     Eof,
 }
 
@@ -0,0 +1,368 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::{Ast, Span, StatementKind};
+use crate::diagnostics::ParseError;
+use crate::interpreter::{Env, Globals, Output, Runner, RuntimeError};
+use crate::parser::{base_name, imported_from, parse_with, resolve_module, Macros, Parsed};
+
+/// Reads the source of the files a program imports with `(import "path")`.
+pub trait FileLoader {
+    /// Returns the contents of the file at `path`, or a message explaining why
+    /// it couldn't be read.
+    fn read(&self, path: &str) -> Result<String, String>;
+
+    /// Returns the raw contents of the file at `path`, for files that aren't
+    /// source code, such as images.
+    fn read_bytes(&self, _path: &str) -> Result<Vec<u8>, String> {
+        Err("binary files are not available here".to_string())
+    }
+}
+
+/// A loader for environments without files, where every import fails.
+pub struct NoFiles;
+
+impl FileLoader for NoFiles {
+    fn read(&self, _path: &str) -> Result<String, String> {
+        Err("file imports are not available here".to_string())
+    }
+}
+
+/// Serves files from a map of paths to sources.
+impl FileLoader for HashMap<String, String> {
+    fn read(&self, path: &str) -> Result<String, String> {
+        self.get(path)
+            .cloned()
+            .ok_or_else(|| "file not found".to_string())
+    }
+}
+
+/// Reads files from disk, relative to a root directory.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct DiskLoader {
+    root: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DiskLoader {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileLoader for DiskLoader {
+    fn read(&self, path: &str) -> Result<String, String> {
+        std::fs::read_to_string(self.root.join(path)).map_err(|e| e.to_string())
+    }
+
+    fn read_bytes(&self, path: &str) -> Result<Vec<u8>, String> {
+        std::fs::read(self.root.join(path)).map_err(|e| e.to_string())
+    }
+}
+
+thread_local! {
+    // the files of the programs running on this thread, innermost last
+    static RUNNING: RefCell<Vec<Rc<Files>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Reads a file for a built-in function, such as `load-image`, with the loader
+/// of the program that is running.
+pub(crate) fn read_bytes(path: &str) -> Result<Vec<u8>, String> {
+    let files = RUNNING.with(|running| running.borrow().last().cloned());
+    match files {
+        Some(files) => files.loader.read_bytes(path),
+        None => NoFiles.read_bytes(path),
+    }
+}
+
+/// Keeps a program's files available to built-in functions until dropped.
+pub(crate) struct RunningFiles(());
+
+impl Drop for RunningFiles {
+    fn drop(&mut self) {
+        RUNNING.with(|running| running.borrow_mut().pop());
+    }
+}
+
+struct FileModule {
+    // the bindings the file provides to importers
+    names: Vec<String>,
+    macros: Macros,
+    globals: Rc<RefCell<Globals>>,
+    // the resolved program, until it has run
+    program: Option<Ast>,
+    result: Option<Result<Env, RuntimeError>>,
+}
+
+/// The files imported by a program. Each file is parsed and resolved the first
+/// time it is imported and runs the first time an import of it executes; later
+/// imports reuse its definitions.
+pub struct Files {
+    loader: Box<dyn FileLoader>,
+    modules: RefCell<HashMap<String, FileModule>>,
+    // files being resolved, outermost first
+    loading: RefCell<Vec<String>>,
+}
+
+impl Files {
+    pub fn new(loader: impl FileLoader + 'static) -> Self {
+        Self {
+            loader: Box::new(loader),
+            modules: RefCell::new(HashMap::new()),
+            loading: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The path of the file being prepared, if any.
+    pub fn current(&self) -> Option<String> {
+        self.loading.borrow().last().cloned()
+    }
+
+    /// The path of the file that an import of `path` refers to, in the file
+    /// being prepared or in the program if there is none.
+    pub fn locate(&self, path: &str) -> String {
+        import_path(self.loading.borrow().last().map(String::as_str), path)
+    }
+
+    /// Parses and resolves the file at `path` unless that already happened,
+    /// returning the names of the bindings it provides. `path` is a path
+    /// returned by [`Files::locate`], so that each file is prepared once.
+    pub fn prepare(&self, path: &str, span: Span) -> Result<Vec<String>, ParseError> {
+        if let Some(module) = self.modules.borrow().get(path) {
+            return Ok(module.names.clone());
+        }
+        if self.loading.borrow().iter().any(|p| p == path) {
+            let mut cycle = self.loading.borrow().clone();
+            cycle.push(path.to_string());
+            let start = cycle.iter().position(|p| p == path).unwrap_or(0);
+            return Err(ParseError::new(
+                format!("Import cycle: {}", cycle[start..].join(" -> ")),
+                Some(span),
+            ));
+        }
+
+        let src = self.loader.read(path).map_err(|message| {
+            ParseError::new(format!("Cannot import \"{path}\": {message}"), Some(span))
+        })?;
+        let in_file = |e: ParseError| ParseError {
+            span: Some(span),
+            note: Some(match e.span {
+                Some(inner) => format!("in {path}:{}", position(&src, inner)),
+                None => format!("in {path}"),
+            }),
+            ..e
+        };
+
+        let mut globals = Globals::new();
+        self.loading.borrow_mut().push(path.to_string());
+        let resolved = parse_with(&src, Macros::new(), self).and_then(|mut parsed| {
+            let macros = provided_macros(&mut parsed);
+            resolve_module(parsed.ast, &mut globals, self).map(|resolution| (resolution, macros))
+        });
+        self.loading.borrow_mut().pop();
+        let (resolution, macros) = resolved.map_err(in_file)?;
+        let names = resolution.provided;
+        let program = resolution.ast;
+
+        self.modules.borrow_mut().insert(
+            path.to_string(),
+            FileModule {
+                names: names.clone(),
+                macros,
+                globals: Rc::new(RefCell::new(globals)),
+                program: Some(program),
+                result: None,
+            },
+        );
+        Ok(names)
+    }
+
+    /// Makes these the files that built-in functions read from, until the
+    /// returned guard is dropped.
+    pub(crate) fn enter(self: &Rc<Self>) -> RunningFiles {
+        RUNNING.with(|running| running.borrow_mut().push(Rc::clone(self)));
+        RunningFiles(())
+    }
+
+    /// The macros the prepared file at `path` provides.
+    pub fn macros(&self, path: &str) -> Macros {
+        self.modules
+            .borrow()
+            .get(path)
+            .map(|module| module.macros.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether the prepared file at `path` binds `name` itself or imports it,
+    /// rather than getting it from the prelude.
+    pub(crate) fn binds(&self, path: &str, name: &str) -> bool {
+        self.modules.borrow().get(path).is_some_and(|module| {
+            let globals = module.globals.borrow();
+            globals
+                .index_of(name)
+                .is_some_and(|index| !globals.is_builtin(index))
+        })
+    }
+
+    /// The top-level bindings of the file at `path` under the hidden names its
+    /// macros refer to them by in importers, once it has run.
+    pub(crate) fn hidden_bindings(&self, path: &str) -> Env {
+        let mut env = Env::new();
+        if let Some(module) = self.modules.borrow().get(path) {
+            for (name, value) in module.globals.borrow().defined() {
+                // bindings of macros the file itself imports are hidden already
+                if base_name(name) == name {
+                    env.set(imported_from(name, path), value);
+                }
+            }
+        }
+        env
+    }
+
+    /// The bindings the file at `path` provides, if it has already run.
+    pub fn values(&self, path: &str) -> Option<Env> {
+        match &self.modules.borrow().get(path)?.result {
+            Some(Ok(env)) => Some(env.clone()),
+            _ => None,
+        }
+    }
+
+    /// Runs the prepared file at `path` if it hasn't run yet and returns the
+    /// bindings it provides.
+    pub(crate) fn evaluate(
+        self: &Rc<Self>,
+        path: &str,
+        span: Span,
+        memory_limit: usize,
+    ) -> Result<Env, RuntimeError> {
+        let (program, globals) = {
+            let mut modules = self.modules.borrow_mut();
+            let Some(module) = modules.get_mut(path) else {
+                return Err(RuntimeError::new(
+                    format!("File \"{path}\" was not resolved before execution"),
+                    Some(span),
+                ));
+            };
+            if let Some(result) = &module.result {
+                return result.clone();
+            }
+            let Some(program) = module.program.take() else {
+                return Err(RuntimeError::new(
+                    format!("File \"{path}\" imports itself while running"),
+                    Some(span),
+                ));
+            };
+            (program, Rc::clone(&module.globals))
+        };
+
+        let mut runner = Runner::new(program, Some(Rc::clone(&globals)))
+            .with_files(Rc::clone(self))
+            .with_memory_limit(memory_limit);
+        runner.execute();
+        let error = runner
+            .get_output()
+            .into_iter()
+            .find_map(|output| match output {
+                Output::Error(e) => Some(e),
+                _ => None,
+            });
+
+        let mut modules = self.modules.borrow_mut();
+        let module = modules.get_mut(path).expect("file module disappeared");
+        let result = match error {
+            Some(e) => Err(RuntimeError::new(
+                format!("{} (in {path})", e.message),
+                Some(span),
+            )),
+            None => {
+                let globals = globals.borrow();
+                let mut env = Env::new();
+                for name in &module.names {
+                    if let Some(value) = globals.index_of(name).and_then(|i| globals.get(i)) {
+                        env.set(name.clone(), value);
+                    }
+                }
+                Ok(env)
+            }
+        };
+        module.result = Some(result.clone());
+        result
+    }
+}
+
+impl Default for Files {
+    fn default() -> Self {
+        Self::new(NoFiles)
+    }
+}
+
+/// Returns the path of the file that `path` refers to when the file at
+/// `importer` imports it, or the program if `importer` is `None`. Paths are
+/// relative to the directory of the file that imports them, and `.` and `..`
+/// are removed so that each file has a single path.
+pub fn import_path(importer: Option<&str>, path: &str) -> String {
+    let directory = match importer {
+        Some(importer) if !path.starts_with('/') => {
+            importer.rfind('/').map_or("", |end| &importer[..=end])
+        }
+        _ => "",
+    };
+    let full = format!("{directory}{path}");
+    let absolute = full.starts_with('/');
+    let mut components = Vec::new();
+    for component in full.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                // there is nothing above the root
+                _ if absolute => {}
+                _ => components.push(".."),
+            },
+            component => components.push(component),
+        }
+    }
+    let path = components.join("/");
+    if absolute {
+        format!("/{path}")
+    } else {
+        path
+    }
+}
+
+// takes the macros a parsed file provides out of its provide statements; a
+// file without provide statements provides the macros it defines
+fn provided_macros(parsed: &mut Parsed) -> Macros {
+    let mut provided = None;
+    for stmt in &mut parsed.ast.statements {
+        if let StatementKind::Provide { names } = &mut stmt.kind {
+            let provided = provided.get_or_insert_with(Macros::new);
+            names.retain(|name| match parsed.macros.get(name) {
+                Some(mac) => {
+                    provided.insert(name.clone(), Rc::clone(mac));
+                    false
+                }
+                None => true,
+            });
+        }
+    }
+    provided.unwrap_or_else(|| {
+        parsed
+            .defined_macros
+            .iter()
+            .filter_map(|name| Some((name.clone(), Rc::clone(parsed.macros.get(name)?))))
+            .collect()
+    })
+}
+
+// line:column of a span within `src`
+fn position(src: &str, span: Span) -> String {
+    let before = &src[..(span.loc as usize).min(src.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    format!("{line}:{column}")
+}
@@ -0,0 +1,279 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    rc::{Rc, Weak},
+};
+
+use scamper_macros::function;
+
+use super::{raster::rgba, Color, Drawing, Raster, Rgb};
+use crate::{
+    ast::{Contract, FromValue, Function, IntoValue, List, Natural, Value, Vector},
+    interpreter::{Env, RuntimeError},
+    modules::files,
+};
+
+pub fn add_to(env: &mut Env) {
+    env.register("bitmap?", bitmap_q);
+    env.register("image->bitmap", image_to_bitmap);
+    env.register("load-image", load_image);
+    env.register("image-get-pixel", image_get_pixel);
+    env.register("image->pixels", image_to_pixels);
+    env.register("pixels->image", pixels_to_image);
+    env.register("pixel-map", pixel_map);
+    env.register("pixel-filter", pixel_filter);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        env.register("save-image", save_image);
+        env.register("image->png", save_image);
+    }
+}
+
+/// An image made of pixels, such as a loaded PNG or a rendered drawing.
+/// Functions that expect a bitmap also accept drawings, which they render
+/// first. Only the drawing rendered last is kept, so code that looks at the
+/// pixels of several drawings in turn should convert each with
+/// `image->bitmap` once. Clones share the same pixels.
+#[derive(Debug, Clone)]
+pub struct Bitmap(Rc<Raster>);
+
+impl Bitmap {
+    pub fn new(raster: Raster) -> Self {
+        Bitmap(Rc::new(raster))
+    }
+
+    pub fn raster(&self) -> &Raster {
+        &self.0
+    }
+}
+
+thread_local! {
+    // the drawing converted to a bitmap last, so that a loop over its pixels
+    // renders it once instead of once per pixel
+    static RASTERIZED: RefCell<Option<(Weak<dyn Any>, Bitmap)>> = const { RefCell::new(None) };
+}
+
+impl FromValue for Bitmap {
+    fn from_value(value: &Value) -> Option<Self> {
+        let Value::Foreign(f) = value else {
+            return None;
+        };
+        if let Some(bitmap) = f.downcast_ref::<Bitmap>() {
+            Some(bitmap.clone())
+        } else {
+            let drawing = f.downcast_ref::<Drawing>()?;
+            Some(RASTERIZED.with(|rasterized| {
+                let mut rasterized = rasterized.borrow_mut();
+                match &*rasterized {
+                    Some((last, bitmap)) if Weak::ptr_eq(last, &Rc::downgrade(f)) => bitmap.clone(),
+                    _ => {
+                        let bitmap = Bitmap::new(drawing.rasterize());
+                        *rasterized = Some((Rc::downgrade(f), bitmap.clone()));
+                        bitmap
+                    }
+                }
+            }))
+        }
+    }
+
+    fn name() -> &'static str {
+        "image"
+    }
+}
+
+impl IntoValue for Bitmap {
+    fn into_value(self) -> Option<Value> {
+        Some(Value::Foreign(Rc::new(self)))
+    }
+}
+
+/// A drawing or a bitmap, for functions that work on either without drawing
+/// the drawing first.
+pub(super) enum Image {
+    Drawing(Drawing),
+    Bitmap(Bitmap),
+}
+
+impl Image {
+    pub fn width(&self) -> f64 {
+        match self {
+            Image::Drawing(drawing) => drawing.width(),
+            Image::Bitmap(bitmap) => bitmap.raster().width() as f64,
+        }
+    }
+
+    pub fn height(&self) -> f64 {
+        match self {
+            Image::Drawing(drawing) => drawing.height(),
+            Image::Bitmap(bitmap) => bitmap.raster().height() as f64,
+        }
+    }
+}
+
+impl FromValue for Image {
+    fn from_value(value: &Value) -> Option<Self> {
+        let Value::Foreign(f) = value else {
+            return None;
+        };
+        if let Some(drawing) = f.downcast_ref::<Drawing>() {
+            Some(Image::Drawing(drawing.clone()))
+        } else {
+            f.downcast_ref::<Bitmap>()
+                .map(|bitmap| Image::Bitmap(bitmap.clone()))
+        }
+    }
+
+    fn name() -> &'static str {
+        "image"
+    }
+}
+
+// maps each pixel of `image` to the color `f` returns for it
+fn map_pixels(
+    image: &Bitmap,
+    mut f: impl FnMut(Rgb) -> Result<[u8; 4], RuntimeError>,
+) -> Result<Bitmap, RuntimeError> {
+    let raster = image.raster();
+    let mut data = Vec::with_capacity(raster.data().len());
+    for color in raster.colors() {
+        data.extend(f(color)?);
+    }
+    let raster = Raster::from_rgba(raster.width(), raster.height(), data)
+        .expect("a mapped image has as many pixels as the original");
+    Ok(Bitmap::new(raster))
+}
+
+#[function]
+fn bitmap_q(v: Value) -> bool {
+    matches!(&v, Value::Foreign(f) if f.is::<Bitmap>())
+}
+
+#[function]
+fn image_to_bitmap(image: Bitmap) -> Bitmap {
+    image
+}
+
+#[function]
+fn load_image(path: String) -> Result<Bitmap, RuntimeError> {
+    let raster = files::read_bytes(&path)
+        .and_then(|bytes| Raster::from_png(&bytes))
+        .map_err(|e| RuntimeError::new(format!("Cannot load image \"{path}\": {e}"), None))?;
+    Ok(Bitmap::new(raster))
+}
+
+#[function(contract(1, Natural), contract(2, Natural))]
+fn image_get_pixel(image: Bitmap, x: i64, y: i64) -> Result<Rgb, RuntimeError> {
+    let raster = image.raster();
+    let pixel = u32::try_from(x)
+        .ok()
+        .zip(u32::try_from(y).ok())
+        .and_then(|(x, y)| raster.pixel(x, y));
+    match pixel {
+        Some([red, green, blue, alpha]) => Ok(Rgb {
+            red: red as f64,
+            green: green as f64,
+            blue: blue as f64,
+            alpha: alpha as f64,
+        }),
+        None => Err(RuntimeError::new(
+            format!(
+                "({x}, {y}) is outside of the {}x{} image",
+                raster.width(),
+                raster.height()
+            ),
+            None,
+        )),
+    }
+}
+
+#[function]
+fn image_to_pixels(image: Bitmap) -> Vector {
+    image
+        .raster()
+        .colors()
+        .filter_map(|color| color.into_value())
+        .collect::<Vec<_>>()
+        .into()
+}
+
+#[function(contract(1, Natural), contract(2, Natural))]
+fn pixels_to_image(pixels: Value, width: i64, height: i64) -> Result<Bitmap, RuntimeError> {
+    let pixels = if let Some(v) = Vector::from_value(&pixels) {
+        v.values_vec()
+    } else if let Some(l) = List::from_value(&pixels) {
+        l.values_vec()
+    } else {
+        return Err(RuntimeError::new(
+            "pixels must be given as a vector or list of colors".to_string(),
+            None,
+        ));
+    };
+    let count = width.checked_mul(height);
+    let size = u32::try_from(width).ok().zip(u32::try_from(height).ok());
+    let (Some(count), Some((columns, rows))) = (count, size) else {
+        return Err(RuntimeError::new(
+            format!("a {width}x{height} image is too large"),
+            None,
+        ));
+    };
+    if count != pixels.len() as i64 {
+        return Err(RuntimeError::new(
+            format!(
+                "a {width}x{height} image needs {count} pixels, given {}",
+                pixels.len()
+            ),
+            None,
+        ));
+    }
+    let mut data = Vec::with_capacity(pixels.len() * 4);
+    for pixel in &pixels {
+        let Some(color) = Color::from_value(pixel) else {
+            return Err(RuntimeError::new(
+                format!("expected a color, received {}", pixel),
+                None,
+            ));
+        };
+        data.extend(rgba(&color));
+    }
+    let raster = Raster::from_rgba(columns, rows, data).expect("the number of pixels was checked");
+    Ok(Bitmap::new(raster))
+}
+
+#[function]
+fn pixel_map(f: Function, image: Bitmap) -> Result<Bitmap, RuntimeError> {
+    map_pixels(&image, |color| {
+        let result = f.call(&[color.into_value().unwrap()])?;
+        match Color::from_value(&result) {
+            Some(color) => Ok(rgba(&color)),
+            None => Err(RuntimeError::new(
+                format!("function must return a color, returned {}", result),
+                None,
+            )),
+        }
+    })
+}
+
+#[function]
+fn pixel_filter(pred: Function, image: Bitmap) -> Result<Bitmap, RuntimeError> {
+    map_pixels(&image, |color| {
+        let keep = pred.call(&[color.into_value().unwrap()])?.truthy();
+        Ok(if keep {
+            rgba(&Color::from_rgb(color))
+        } else {
+            [0, 0, 0, 0]
+        })
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[function]
+fn save_image(image: Bitmap, path: String) -> Result<Value, RuntimeError> {
+    let png = image
+        .raster()
+        .to_png()
+        .map_err(|e| RuntimeError::new(e, None))?;
+    std::fs::write(&path, png)
+        .map_err(|e| RuntimeError::new(format!("Failed to save image to {}: {}", path, e), None))?;
+    Ok(Value::Void)
+}
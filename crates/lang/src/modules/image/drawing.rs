@@ -1,6 +1,6 @@
 use scamper_macros::{function, ForeignValue};
 
-use super::{color::Color, Rgb};
+use super::{bitmap::Image, color::Color, font::measure_text, Rgb};
 use crate::{
     ast::{Contract, FromValue, List, NonNegative, Value},
     interpreter::{Env, RuntimeError},
@@ -189,6 +189,9 @@ pub struct WithDash {
     pub drawing: Box<Drawing>,
 }
 
+/// A font to set text in. Text is measured and drawn with the bundled face,
+/// made monospace, bold or italic like the font, so its size doesn't depend
+/// on the fonts that are installed.
 #[derive(Debug, Clone, ForeignValue)]
 pub struct Font {
     pub face: String,
@@ -468,7 +471,7 @@ impl Drawing {
 
 #[function]
 fn drawing_q(x: Value) -> bool {
-    Drawing::from_value(&x).is_some()
+    Image::from_value(&x).is_some()
 }
 
 #[function(contract(0, NonNegative), contract(1, NonNegative))]
@@ -755,128 +758,18 @@ fn font(name: String, system: Option<String>, bold: Option<bool>, italic: Option
     }
 }
 
-#[cfg(target_arch = "wasm32")]
-use wasm_bindgen::prelude::*;
-
-#[cfg(not(target_arch = "wasm32"))]
-use font_kit::{
-    properties::{Properties, Style, Weight},
-    source::SystemSource,
-};
-
-pub struct TextDimensions {
-    pub width: f64,
-    pub height: f64,
-}
-
-#[cfg(target_arch = "wasm32")]
-pub fn measure_text(font: &Font, text: &str, size: f64) -> Result<TextDimensions, String> {
-    use web_sys;
-
-    let document = web_sys::window()
-        .ok_or("No window found")?
-        .document()
-        .ok_or("No document found")?;
-
-    let canvas = document
-        .create_element("canvas")
-        .ok()
-        .and_then(|e| e.dyn_into::<web_sys::HtmlCanvasElement>().ok())
-        .ok_or("Failed to create canvas")?;
-
-    let context = canvas
-        .get_context("2d")
-        .ok()
-        .and_then(|e| e?.dyn_into::<web_sys::CanvasRenderingContext2d>().ok())
-        .ok_or("Failed to get 2d context")?;
-
-    let font_string = font.to_string(size);
-    context.set_font(&font_string);
-
-    let metrics = context
-        .measure_text(text)
-        .map_err(|_| "Failed to measure text")?;
-    let width = metrics.width() as f64;
-
-    let ascent = metrics.actual_bounding_box_ascent() as f64;
-    let descent = metrics.actual_bounding_box_descent() as f64;
-    let height = ascent + descent + 1.0;
-
-    Ok(TextDimensions { width, height })
-}
-
-#[cfg(not(target_arch = "wasm32"))]
-pub fn measure_text(font: &Font, text: &str, size: f64) -> Result<TextDimensions, String> {
-    use font_kit::family_name::FamilyName;
-
-    let source = SystemSource::new();
-
-    let properties = Properties {
-        weight: if font.bold {
-            Weight::BOLD
-        } else {
-            Weight::NORMAL
-        },
-        style: if font.italic {
-            Style::Italic
-        } else {
-            Style::Normal
-        },
-        ..Properties::new()
-    };
-
-    let font = source
-        .select_best_match(
-            &[
-                FamilyName::Title(font.face.clone()),
-                font.system
-                    .as_ref()
-                    .map(|f| FamilyName::Title(f.clone()))
-                    .unwrap_or(FamilyName::SansSerif),
-            ],
-            &properties,
-        )
-        .map_err(|e| format!("Failed to select font: {}", e))?
-        .load()
-        .map_err(|e| format!("Failed to load font: {}", e))?;
-
-    let metrics = font.metrics();
-    let scale = size / metrics.units_per_em as f64;
-
-    let mut width = 0.0;
-    for c in text.chars() {
-        if let Some(glyph_id) = font.glyph_for_char(c) {
-            let advance = font
-                .advance(glyph_id)
-                .map_err(|e| format!("Failed to get glyph advance: {}", e))?;
-            width += advance.x() as f64 * scale;
-        }
-    }
-
-    let height = (metrics.ascent - metrics.descent) as f64 * scale;
-
-    Ok(TextDimensions { width, height })
-}
-
 #[function]
-fn text(
-    text: String,
-    size: f64,
-    color: Color,
-    font: Option<Font>,
-) -> Result<Drawing, RuntimeError> {
+fn text(text: String, size: f64, color: Color, font: Option<Font>) -> Drawing {
     let font = font.unwrap_or_else(|| Font::new(String::from("Arial")));
-    match measure_text(&font, &text, size) {
-        Ok(dimensions) => Ok(Drawing::Text(Text {
-            width: dimensions.width,
-            height: dimensions.height,
-            text,
-            size,
-            color,
-            font,
-        })),
-        Err(e) => Err(RuntimeError::new(e, None)),
-    }
+    let dimensions = measure_text(&text, size, &font);
+    Drawing::Text(Text {
+        width: dimensions.width,
+        height: dimensions.height,
+        text,
+        size,
+        color,
+        font,
+    })
 }
 
 #[function]
@@ -1002,13 +895,13 @@ fn outlined_isosceles_triangle(width: f64, height: f64, color: Color) -> Drawing
 }
 
 #[function]
-fn image_width(drawing: Drawing) -> f64 {
-    drawing.width()
+fn image_width(image: Image) -> f64 {
+    image.width()
 }
 
 #[function]
-fn image_height(drawing: Drawing) -> f64 {
-    drawing.height()
+fn image_height(image: Image) -> f64 {
+    image.height()
 }
 
 #[function]
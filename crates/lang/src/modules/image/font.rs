@@ -0,0 +1,218 @@
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+use super::drawing::{Font, Text};
+
+// names of fonts that are set as monospace text, in lowercase
+const MONOSPACE_FONTS: &[&str] = &[
+    "consolas",
+    "courier",
+    "courier new",
+    "dejavu sans mono",
+    "lucida console",
+    "menlo",
+    "monaco",
+    "monospace",
+];
+
+// text is measured and drawn with the same font on every platform, so that
+// text drawings have the same size wherever a program runs. Only its regular
+// face is bundled, and the bold, oblique and monospace styles of a font are
+// made from it.
+const FACE: &[u8] = include_bytes!("fonts/DejaVuSans.ttf");
+
+// how far oblique text leans to the right per unit of height (about 12°)
+const OBLIQUE_SLANT: f64 = 0.21;
+
+// how much wider bold text makes the stems of glyphs, in ems
+const BOLD_STRENGTH: f64 = 1.0 / 24.0;
+
+// the width of each character of monospace text, in ems, as in DejaVu Sans Mono
+const MONOSPACE_ADVANCE: f64 = 1233.0 / 2048.0;
+
+fn is_monospace(font: &Font) -> bool {
+    font.system.as_deref() == Some("monospace")
+        || MONOSPACE_FONTS.contains(&font.face.to_lowercase().as_str())
+}
+
+pub struct TextDimensions {
+    pub width: f64,
+    pub height: f64,
+}
+
+/// A step in drawing the outline of some text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathCommand {
+    MoveTo(f64, f64),
+    LineTo(f64, f64),
+    QuadTo(f64, f64, f64, f64),
+    CubicTo(f64, f64, f64, f64, f64, f64),
+    Close,
+}
+
+/// Measures `text` set in the bundled face, styled like `font`, at `size`
+/// pixels. The height spans from the highest ascent to the lowest descent of
+/// the face, so it doesn't depend on the text.
+pub fn measure_text(text: &str, size: f64, font: &Font) -> TextDimensions {
+    let layout = Layout::new(size, font);
+    let width = layout.glyphs(text).map(|glyph| layout.advance(glyph)).sum();
+    let height = (layout.face.ascender() as f64 - layout.face.descender() as f64) * layout.scale;
+    TextDimensions { width, height }
+}
+
+/// Returns the outlines of the glyphs of `text` set in the bundled face,
+/// styled like `font`, at `size` pixels, with the top left corner of the text
+/// at the origin and y going down.
+pub fn text_outline(text: &str, size: f64, font: &Font) -> Vec<PathCommand> {
+    let layout = Layout::new(size, font);
+    let mut outline = Outline {
+        commands: Vec::new(),
+        x: 0.0,
+        x_scale: layout.scale,
+        scale: layout.scale,
+        baseline: layout.face.ascender() as f64 * layout.scale,
+        slant: if font.italic { OBLIQUE_SLANT } else { 0.0 },
+    };
+    let mut x = 0.0;
+    for glyph in layout.glyphs(text) {
+        let width = layout.natural_advance(glyph);
+        // glyphs wider than a monospace cell are narrowed to fit it, and
+        // all of them are centered in it
+        if layout.monospace {
+            outline.x_scale = layout.scale * f64::min(1.0, layout.cell / width);
+        }
+        let offset = (layout.advance(glyph) - layout.ink_advance(glyph)) / 2.0;
+        // bold glyphs are drawn a second time a little to the right
+        let strokes: &[f64] = if layout.bold {
+            &[0.0, layout.strength]
+        } else {
+            &[0.0]
+        };
+        for stroke in strokes {
+            outline.x = x + offset.max(0.0) + stroke;
+            layout.face.outline_glyph(glyph, &mut outline);
+        }
+        x += layout.advance(glyph);
+    }
+    outline.commands
+}
+
+impl Text {
+    /// The outline of the text, with its top left corner at the origin.
+    pub fn outline(&self) -> Vec<PathCommand> {
+        text_outline(&self.text, self.size, &self.font)
+    }
+}
+
+// how the glyphs of text in some font are placed
+struct Layout {
+    face: Face<'static>,
+    // pixels per font unit
+    scale: f64,
+    monospace: bool,
+    bold: bool,
+    // how much further a bold glyph is drawn again, in pixels
+    strength: f64,
+    // the width of a monospace character, in pixels
+    cell: f64,
+}
+
+impl Layout {
+    fn new(size: f64, font: &Font) -> Self {
+        let face = Face::parse(FACE, 0).expect("the bundled font is a valid font");
+        Layout {
+            scale: size / face.units_per_em() as f64,
+            face,
+            monospace: is_monospace(font),
+            bold: font.bold,
+            strength: size * BOLD_STRENGTH,
+            cell: size * MONOSPACE_ADVANCE,
+        }
+    }
+
+    // characters the font has no glyph for are set as its missing glyph
+    fn glyphs<'a>(&'a self, text: &'a str) -> impl Iterator<Item = GlyphId> + 'a {
+        text.chars()
+            .map(|c| self.face.glyph_index(c).unwrap_or(GlyphId(0)))
+    }
+
+    // the width the face gives the glyph, in pixels
+    fn natural_advance(&self, glyph: GlyphId) -> f64 {
+        self.face.glyph_hor_advance(glyph).unwrap_or(0) as f64 * self.scale
+    }
+
+    // the width of the glyph as drawn, before it is centered in its cell
+    fn ink_advance(&self, glyph: GlyphId) -> f64 {
+        let width = self.natural_advance(glyph);
+        let width = if self.monospace {
+            width.min(self.cell)
+        } else {
+            width
+        };
+        if self.bold {
+            width + self.strength
+        } else {
+            width
+        }
+    }
+
+    // how far the glyph moves the next one along, in pixels. Bold glyphs are
+    // wider, except in monospace text where each one fills a cell.
+    fn advance(&self, glyph: GlyphId) -> f64 {
+        if self.monospace {
+            self.cell
+        } else {
+            self.ink_advance(glyph)
+        }
+    }
+}
+
+// collects the outline of each glyph, which is in font units with y going
+// up, at the position of the glyph in the text
+struct Outline {
+    commands: Vec<PathCommand>,
+    x: f64,
+    x_scale: f64,
+    scale: f64,
+    baseline: f64,
+    slant: f64,
+}
+
+impl Outline {
+    fn point(&self, x: f32, y: f32) -> (f64, f64) {
+        let (x, y) = (x as f64, y as f64);
+        (
+            self.x + x * self.x_scale + y * self.slant * self.scale,
+            self.baseline - y * self.scale,
+        )
+    }
+}
+
+impl OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.point(x, y);
+        self.commands.push(PathCommand::MoveTo(x, y));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let (x, y) = self.point(x, y);
+        self.commands.push(PathCommand::LineTo(x, y));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x1, y1) = self.point(x1, y1);
+        let (x, y) = self.point(x, y);
+        self.commands.push(PathCommand::QuadTo(x1, y1, x, y));
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (x1, y1) = self.point(x1, y1);
+        let (x2, y2) = self.point(x2, y2);
+        let (x, y) = self.point(x, y);
+        self.commands
+            .push(PathCommand::CubicTo(x1, y1, x2, y2, x, y));
+    }
+
+    fn close(&mut self) {
+        self.commands.push(PathCommand::Close);
+    }
+}
@@ -1,13 +1,22 @@
 use crate::interpreter::Env;
 
+mod bitmap;
 mod color;
 mod color_names;
 mod drawing;
+mod font;
+mod raster;
+mod svg;
 
+pub use bitmap::Bitmap;
 pub use color::{Color, Hsv, Rgb};
 pub use drawing::{Align, Drawing, Mode};
+pub use font::PathCommand;
+pub use raster::Raster;
 
 pub fn add_to(env: &mut Env) {
     color::add_to(env);
     drawing::add_to(env);
+    svg::add_to(env);
+    bitmap::add_to(env);
 }
@@ -0,0 +1,356 @@
+use tiny_skia::{FillRule, Paint, PathBuilder, Pixmap, Rect, Stroke, StrokeDash, Transform};
+
+use super::{drawing::Text, Align, Color, Drawing, Mode, PathCommand, Rgb};
+
+/// A grid of pixels, stored row by row from the top left corner as RGBA bytes
+/// that are not premultiplied by their alpha.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Raster {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Raster {
+    /// Creates a fully transparent raster.
+    pub fn new(width: u32, height: u32) -> Self {
+        Raster {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Creates a raster from RGBA bytes, if there are as many as it has pixels.
+    pub fn from_rgba(width: u32, height: u32, data: Vec<u8>) -> Option<Self> {
+        if data.len() != width as usize * height as usize * 4 {
+            return None;
+        }
+        Some(Raster {
+            width,
+            height,
+            data,
+        })
+    }
+
+    /// Decodes a PNG file of any color type and bit depth.
+    pub fn from_png(bytes: &[u8]) -> Result<Self, String> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| format!("Failed to decode image: {}", e))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|e| format!("Failed to decode image: {}", e))?;
+        buffer.truncate(info.buffer_size());
+
+        let data = match info.color_type {
+            png::ColorType::Rgba => buffer,
+            png::ColorType::Rgb => buffer
+                .chunks(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+            png::ColorType::Indexed => {
+                return Err("Failed to decode image: unexpanded palette".to_string())
+            }
+        };
+        Raster::from_rgba(info.width, info.height, data)
+            .ok_or_else(|| "Failed to decode image: truncated pixel data".to_string())
+    }
+
+    /// Returns the RGBA components of the pixel at (`x`, `y`).
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        Some([
+            self.data[i],
+            self.data[i + 1],
+            self.data[i + 2],
+            self.data[i + 3],
+        ])
+    }
+
+    /// Returns the pixels in order, as colors.
+    pub fn colors(&self) -> impl Iterator<Item = Rgb> + '_ {
+        self.data.chunks(4).map(|p| Rgb {
+            red: p[0] as f64,
+            green: p[1] as f64,
+            blue: p[2] as f64,
+            alpha: p[3] as f64,
+        })
+    }
+
+    /// Encodes the raster as a PNG file.
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.data))
+            .map_err(|e| format!("Failed to encode image: {}", e))?;
+        Ok(bytes)
+    }
+
+    fn from_pixmap(pixmap: &Pixmap) -> Self {
+        let data = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|p| {
+                let c = p.demultiply();
+                [c.red(), c.green(), c.blue(), c.alpha()]
+            })
+            .collect();
+        Raster {
+            width: pixmap.width(),
+            height: pixmap.height(),
+            data,
+        }
+    }
+}
+
+impl Drawing {
+    /// Draws the drawing onto a transparent raster of its size, the same way
+    /// the canvas renderer of the web interface does.
+    pub fn rasterize(&self) -> Raster {
+        let width = self.width().ceil() as u32;
+        let height = self.height().ceil() as u32;
+        let Some(pixmap) = Pixmap::new(width, height) else {
+            return Raster::new(width, height);
+        };
+        let mut renderer = Renderer {
+            pixmap,
+            transform: Transform::identity(),
+            dash: None,
+        };
+        renderer.render(0.0, 0.0, self);
+        Raster::from_pixmap(&renderer.pixmap)
+    }
+}
+
+struct Renderer {
+    pixmap: Pixmap,
+    transform: Transform,
+    // the dash pattern of the strokes, set by the closest `with-dash`
+    dash: Option<StrokeDash>,
+}
+
+impl Renderer {
+    fn render(&mut self, x: f64, y: f64, drawing: &Drawing) {
+        match drawing {
+            Drawing::Ellipse(ellipse) => {
+                if let Some(rect) = rect(x, y, ellipse.width, ellipse.height) {
+                    if let Some(path) = PathBuilder::from_oval(rect) {
+                        self.draw(path, &ellipse.mode, &ellipse.color);
+                    }
+                }
+            }
+            Drawing::Rectangle(rectangle) => {
+                if let Some(rect) = rect(x, y, rectangle.width, rectangle.height) {
+                    self.draw(
+                        PathBuilder::from_rect(rect),
+                        &rectangle.mode,
+                        &rectangle.color,
+                    );
+                }
+            }
+            Drawing::Triangle(triangle) => {
+                let mut builder = PathBuilder::new();
+                builder.move_to(x as f32, (y + triangle.height) as f32);
+                builder.line_to((x + triangle.width / 2.0) as f32, y as f32);
+                builder.line_to((x + triangle.width) as f32, (y + triangle.height) as f32);
+                builder.close();
+                if let Some(path) = builder.finish() {
+                    self.draw(path, &triangle.mode, &triangle.color);
+                }
+            }
+            Drawing::Path(path) => {
+                let mut builder = PathBuilder::new();
+                for (i, (px, py)) in path.points.iter().enumerate() {
+                    let (px, py) = ((x + px) as f32, (y + py) as f32);
+                    if i == 0 {
+                        builder.move_to(px, py);
+                    } else {
+                        builder.line_to(px, py);
+                    }
+                }
+                if let Some(p) = builder.finish() {
+                    self.draw(p, &path.mode, &path.color);
+                }
+            }
+            Drawing::Beside(beside) => {
+                let mut x_offset = 0.0;
+                for drawing in &beside.drawings {
+                    let y = match beside.align {
+                        Align::Top => y,
+                        Align::Bottom => y + beside.height - drawing.height(),
+                        _ => y + (beside.height - drawing.height()) / 2.0,
+                    };
+                    self.render(x + x_offset, y, drawing);
+                    x_offset += drawing.width();
+                }
+            }
+            Drawing::Above(above) => {
+                let mut y_offset = 0.0;
+                for drawing in &above.drawings {
+                    let x = match above.align {
+                        Align::Left => x,
+                        Align::Right => x + above.width - drawing.width(),
+                        _ => x + (above.width - drawing.width()) / 2.0,
+                    };
+                    self.render(x, y + y_offset, drawing);
+                    y_offset += drawing.height();
+                }
+            }
+            Drawing::Overlay(overlay) => {
+                // the first drawing is on top, so it is drawn last
+                for drawing in overlay.drawings.iter().rev() {
+                    let x = match overlay.x_align {
+                        Align::Left => x,
+                        Align::Right => x + overlay.width - drawing.width(),
+                        _ => x + (overlay.width - drawing.width()) / 2.0,
+                    };
+                    let y = match overlay.y_align {
+                        Align::Top => y,
+                        Align::Bottom => y + overlay.height - drawing.height(),
+                        _ => y + (overlay.height - drawing.height()) / 2.0,
+                    };
+                    self.render(x, y, drawing);
+                }
+            }
+            Drawing::OverlayOffset(overlay) => {
+                let x1 = if overlay.dx > 0.0 { x } else { x - overlay.dx };
+                let y1 = if overlay.dy > 0.0 { y } else { y - overlay.dy };
+                let x2 = if overlay.dx > 0.0 { x + overlay.dx } else { x };
+                let y2 = if overlay.dy > 0.0 { y + overlay.dy } else { y };
+                self.render(x2, y2, &overlay.drawing2);
+                self.render(x1, y1, &overlay.drawing1);
+            }
+            Drawing::Rotate(rotate) => {
+                let transform = self.transform;
+                self.transform = transform
+                    .pre_translate((x + rotate.x_offset) as f32, (y + rotate.y_offset) as f32)
+                    .pre_rotate(rotate.angle as f32);
+                self.render(0.0, 0.0, &rotate.drawing);
+                self.transform = transform;
+            }
+            Drawing::WithDash(dash) => {
+                let outer = self.dash.take();
+                self.dash = dash_pattern(&dash.dash_spec);
+                self.render(x, y, &dash.drawing);
+                self.dash = outer;
+            }
+            Drawing::Text(text) => self.draw_text(x, y, text),
+        }
+    }
+
+    fn draw(&mut self, path: tiny_skia::Path, mode: &Mode, color: &Color) {
+        let paint = paint(color);
+        match mode {
+            Mode::Solid => {
+                self.pixmap
+                    .fill_path(&path, &paint, FillRule::Winding, self.transform, None)
+            }
+            Mode::Outline => {
+                let stroke = Stroke {
+                    width: 1.0,
+                    dash: self.dash.clone(),
+                    ..Stroke::default()
+                };
+                self.pixmap
+                    .stroke_path(&path, &paint, &stroke, self.transform, None)
+            }
+        }
+    }
+
+    fn draw_text(&mut self, x: f64, y: f64, text: &Text) {
+        let mut builder = PathBuilder::new();
+        for command in text.outline() {
+            match command {
+                PathCommand::MoveTo(px, py) => builder.move_to((x + px) as f32, (y + py) as f32),
+                PathCommand::LineTo(px, py) => builder.line_to((x + px) as f32, (y + py) as f32),
+                PathCommand::QuadTo(x1, y1, px, py) => builder.quad_to(
+                    (x + x1) as f32,
+                    (y + y1) as f32,
+                    (x + px) as f32,
+                    (y + py) as f32,
+                ),
+                PathCommand::CubicTo(x1, y1, x2, y2, px, py) => builder.cubic_to(
+                    (x + x1) as f32,
+                    (y + y1) as f32,
+                    (x + x2) as f32,
+                    (y + y2) as f32,
+                    (x + px) as f32,
+                    (y + py) as f32,
+                ),
+                PathCommand::Close => builder.close(),
+            }
+        }
+        if let Some(path) = builder.finish() {
+            self.pixmap.fill_path(
+                &path,
+                &paint(&text.color),
+                FillRule::Winding,
+                self.transform,
+                None,
+            );
+        }
+    }
+}
+
+fn rect(x: f64, y: f64, width: f64, height: f64) -> Option<Rect> {
+    Rect::from_xywh(x as f32, y as f32, width as f32, height as f32)
+}
+
+fn paint(color: &Color) -> Paint<'static> {
+    let [red, green, blue, alpha] = rgba(color);
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(red, green, blue, alpha);
+    paint.anti_alias = true;
+    paint
+}
+
+/// The components of a color as the bytes of a pixel.
+pub(super) fn rgba(color: &Color) -> [u8; 4] {
+    let rgb = color.to_rgb();
+    let component = |c: f64| c.round().clamp(0.0, 255.0) as u8;
+    [
+        component(rgb.red),
+        component(rgb.green),
+        component(rgb.blue),
+        component(rgb.alpha),
+    ]
+}
+
+// like a canvas, repeats a dash spec with an odd number of lengths so that
+// it alternates between dashes and gaps, and draws solid lines for a spec it
+// cannot use
+fn dash_pattern(spec: &[f64]) -> Option<StrokeDash> {
+    let mut lengths = spec.iter().map(|&n| n as f32).collect::<Vec<_>>();
+    if lengths.len() % 2 == 1 {
+        lengths.extend_from_within(..);
+    }
+    StrokeDash::new(lengths, 0.0)
+}
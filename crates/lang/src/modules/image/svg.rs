@@ -0,0 +1,226 @@
+use std::fmt::Write;
+
+use scamper_macros::function;
+
+use super::{drawing::Text, Align, Color, Drawing, Mode, PathCommand};
+use crate::interpreter::Env;
+
+pub fn add_to(env: &mut Env) {
+    env.register("drawing->svg", drawing_to_svg);
+}
+
+impl Drawing {
+    /// Returns a standalone SVG document that looks like the drawing. Every
+    /// drawing is drawn with its top left corner at the origin and moved into
+    /// place by the transform of a group, like the canvas renderer does.
+    pub fn to_svg(&self) -> String {
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            w = self.width(),
+            h = self.height(),
+        );
+        write_drawing(&mut svg, self);
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+fn write_drawing(svg: &mut String, drawing: &Drawing) {
+    let _ = match drawing {
+        Drawing::Ellipse(ellipse) => write!(
+            svg,
+            r#"<ellipse cx="{rx}" cy="{ry}" rx="{rx}" ry="{ry}" {}/>"#,
+            paint(&ellipse.mode, &ellipse.color),
+            rx = ellipse.width / 2.0,
+            ry = ellipse.height / 2.0,
+        ),
+        Drawing::Rectangle(rectangle) => write!(
+            svg,
+            r#"<rect width="{}" height="{}" {}/>"#,
+            rectangle.width,
+            rectangle.height,
+            paint(&rectangle.mode, &rectangle.color),
+        ),
+        Drawing::Triangle(triangle) => write!(
+            svg,
+            r#"<polygon points="0,{h} {},0 {},{h}" {}/>"#,
+            triangle.width / 2.0,
+            triangle.width,
+            paint(&triangle.mode, &triangle.color),
+            h = triangle.height,
+        ),
+        Drawing::Path(path) => {
+            let points = path
+                .points
+                .iter()
+                .map(|(x, y)| format!("{x},{y}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            // a path is only closed when it is filled
+            let element = match path.mode {
+                Mode::Solid => "polygon",
+                Mode::Outline => "polyline",
+            };
+            write!(
+                svg,
+                r#"<{element} points="{points}" {}/>"#,
+                paint(&path.mode, &path.color)
+            )
+        }
+        Drawing::Beside(beside) => {
+            svg.push_str("<g>");
+            let mut x = 0.0;
+            for drawing in &beside.drawings {
+                let y = match beside.align {
+                    Align::Top => 0.0,
+                    Align::Bottom => beside.height - drawing.height(),
+                    _ => (beside.height - drawing.height()) / 2.0,
+                };
+                write_translated(svg, x, y, drawing);
+                x += drawing.width();
+            }
+            svg.push_str("</g>");
+            Ok(())
+        }
+        Drawing::Above(above) => {
+            svg.push_str("<g>");
+            let mut y = 0.0;
+            for drawing in &above.drawings {
+                let x = match above.align {
+                    Align::Left => 0.0,
+                    Align::Right => above.width - drawing.width(),
+                    _ => (above.width - drawing.width()) / 2.0,
+                };
+                write_translated(svg, x, y, drawing);
+                y += drawing.height();
+            }
+            svg.push_str("</g>");
+            Ok(())
+        }
+        Drawing::Overlay(overlay) => {
+            svg.push_str("<g>");
+            // later elements are drawn on top, so the first drawing comes last
+            for drawing in overlay.drawings.iter().rev() {
+                let x = match overlay.x_align {
+                    Align::Left => 0.0,
+                    Align::Right => overlay.width - drawing.width(),
+                    _ => (overlay.width - drawing.width()) / 2.0,
+                };
+                let y = match overlay.y_align {
+                    Align::Top => 0.0,
+                    Align::Bottom => overlay.height - drawing.height(),
+                    _ => (overlay.height - drawing.height()) / 2.0,
+                };
+                write_translated(svg, x, y, drawing);
+            }
+            svg.push_str("</g>");
+            Ok(())
+        }
+        Drawing::OverlayOffset(overlay) => {
+            svg.push_str("<g>");
+            let (dx, dy) = (overlay.dx, overlay.dy);
+            write_translated(svg, dx.max(0.0), dy.max(0.0), &overlay.drawing2);
+            write_translated(svg, (-dx).max(0.0), (-dy).max(0.0), &overlay.drawing1);
+            svg.push_str("</g>");
+            Ok(())
+        }
+        Drawing::Rotate(rotate) => {
+            let _ = write!(
+                svg,
+                r#"<g transform="translate({} {}) rotate({})">"#,
+                rotate.x_offset, rotate.y_offset, rotate.angle
+            );
+            write_drawing(svg, &rotate.drawing);
+            svg.push_str("</g>");
+            Ok(())
+        }
+        Drawing::WithDash(dash) => {
+            let spec = if dash.dash_spec.is_empty() {
+                String::from("none")
+            } else {
+                dash.dash_spec
+                    .iter()
+                    .map(|n| n.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            let _ = write!(svg, r#"<g stroke-dasharray="{spec}">"#);
+            write_drawing(svg, &dash.drawing);
+            svg.push_str("</g>");
+            Ok(())
+        }
+        Drawing::Text(text) => write_text(svg, text),
+    };
+}
+
+fn write_translated(svg: &mut String, x: f64, y: f64, drawing: &Drawing) {
+    if x == 0.0 && y == 0.0 {
+        write_drawing(svg, drawing);
+        return;
+    }
+    let _ = write!(svg, r#"<g transform="translate({x} {y})">"#);
+    write_drawing(svg, drawing);
+    svg.push_str("</g>");
+}
+
+// text is drawn from the outlines of its glyphs, like in the other renderers,
+// so that it looks the same without the fonts it was measured with
+fn write_text(svg: &mut String, text: &Text) -> std::fmt::Result {
+    let mut path = String::new();
+    for command in text.outline() {
+        let _ = match command {
+            PathCommand::MoveTo(x, y) => write!(path, "M{x} {y}"),
+            PathCommand::LineTo(x, y) => write!(path, "L{x} {y}"),
+            PathCommand::QuadTo(x1, y1, x, y) => write!(path, "Q{x1} {y1} {x} {y}"),
+            PathCommand::CubicTo(x1, y1, x2, y2, x, y) => {
+                write!(path, "C{x1} {y1} {x2} {y2} {x} {y}")
+            }
+            PathCommand::Close => write!(path, "Z"),
+        };
+    }
+    write!(
+        svg,
+        r#"<path d="{path}" fill="{}"{}/>"#,
+        color(&text.color),
+        opacity("fill-opacity", &text.color),
+    )
+}
+
+fn paint(mode: &Mode, c: &Color) -> String {
+    match mode {
+        Mode::Solid => format!(r#"fill="{}"{}"#, color(c), opacity("fill-opacity", c)),
+        Mode::Outline => format!(
+            r#"fill="none" stroke="{}"{} stroke-width="1""#,
+            color(c),
+            opacity("stroke-opacity", c)
+        ),
+    }
+}
+
+// uses the syntax of SVG 1.1 rather than that of `Rgb`'s display, which only
+// CSS understands
+fn color(color: &Color) -> String {
+    let rgb = color.to_rgb();
+    format!(
+        "rgb({}, {}, {})",
+        rgb.red.round(),
+        rgb.green.round(),
+        rgb.blue.round()
+    )
+}
+
+fn opacity(attribute: &str, color: &Color) -> String {
+    let alpha = color.to_rgb().alpha;
+    if alpha >= 255.0 {
+        String::new()
+    } else {
+        format!(r#" {attribute}="{}""#, alpha / 255.0)
+    }
+}
+
+#[function]
+fn drawing_to_svg(drawing: Drawing) -> String {
+    drawing.to_svg()
+}
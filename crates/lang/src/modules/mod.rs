@@ -1,4 +1,36 @@
+mod files;
 pub mod image;
 pub mod lab;
 pub mod music;
 pub mod prelude;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use files::DiskLoader;
+pub use files::{import_path, FileLoader, Files, NoFiles};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::interpreter::Env;
+
+thread_local! {
+    // built-in modules that have been loaded, so that every import of a module
+    // shares the same bindings
+    static LOADED: RefCell<HashMap<String, Env>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the bindings provided by the built-in module `name`, if it exists.
+pub fn load(name: &str) -> Option<Env> {
+    if let Some(env) = LOADED.with(|loaded| loaded.borrow().get(name).cloned()) {
+        return Some(env);
+    }
+    let mut env = Env::new();
+    match name {
+        "image" => image::add_to(&mut env),
+        "lab" => lab::add_to(&mut env),
+        "music" => music::add_to(&mut env),
+        _ => return None,
+    }
+    LOADED.with(|loaded| loaded.borrow_mut().insert(name.to_string(), env.clone()));
+    Some(env)
+}
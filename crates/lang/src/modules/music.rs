@@ -1,6 +1,6 @@
 use crate::{
-    ast::{Contract, Function, Natural, Value},
-    interpreter::Env,
+    ast::{Contract, FromValue, Function, Natural, Number, Value},
+    interpreter::{Env, RuntimeError},
     make_range_checker,
 };
 use scamper_macros::{function, ForeignValue};
@@ -125,14 +125,31 @@ fn dur(numerator: f64, denominator: f64) -> Duration {
     }
 }
 
+// these shadow the prelude's numerator and denominator, so they accept
+// numbers as well as durations
 #[function]
-fn numerator(duration: Duration) -> f64 {
-    duration.numerator
+fn numerator(value: Value) -> Result<Number, RuntimeError> {
+    let part = match Duration::from_value(&value) {
+        Some(duration) => Number::Float(duration.numerator).to_exact(),
+        None => Number::from_value(&value).and_then(|n| n.numerator()),
+    };
+    part.ok_or_else(|| expected_duration(&value))
 }
 
 #[function]
-fn denominator(duration: Duration) -> f64 {
-    duration.denominator
+fn denominator(value: Value) -> Result<Number, RuntimeError> {
+    let part = match Duration::from_value(&value) {
+        Some(duration) => Number::Float(duration.denominator).to_exact(),
+        None => Number::from_value(&value).and_then(|n| n.denominator()),
+    };
+    part.ok_or_else(|| expected_duration(&value))
+}
+
+fn expected_duration(value: &Value) -> RuntimeError {
+    RuntimeError::new(
+        format!("expected a duration or number, received {}", value.name()),
+        None,
+    )
 }
 
 #[function]
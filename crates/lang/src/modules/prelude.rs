@@ -1,13 +1,18 @@
 use crate::{
-    ast::{Closure, FromValue, Function, List, Number, Operation, Span, Value, Vector},
-    interpreter::{Env, RuntimeError},
+    ast::{
+        FromValue, Function, HashTable, IntoValue, List, NativeFn, Number, Table, Value, ValueBox,
+        Vector,
+    },
+    interpreter::{memory_limit, Control, Env, ErrorObject, RuntimeError},
 };
 use core::f64;
-use scamper_macros::{function, scamper_doc};
+use scamper_macros::{function, scamper_doc, ForeignValue};
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
 
 pub fn add_to(env: &mut Env) {
     // numbers (6.2)
     env.register("equal?", equal_q);
+    env.register("eq?", eq_q);
     env.register("number?", number_q);
     env.register("real?", real_q);
     env.register("integer?", integer_q);
@@ -43,6 +48,12 @@ pub fn add_to(env: &mut Env) {
     env.register("square", square);
     env.register("sqrt", sqrt);
     env.register("expt", expt);
+    env.register("exact?", exact_q);
+    env.register("inexact?", inexact_q);
+    env.register("exact->inexact", exact_to_inexact);
+    env.register("inexact->exact", inexact_to_exact);
+    env.register("numerator", numerator);
+    env.register("denominator", denominator);
     env.register("number->string", number_to_string);
     env.register("string->number", string_to_number);
     env.register("exp", exp);
@@ -146,13 +157,45 @@ pub fn add_to(env: &mut Env) {
     env.register("make-vector", make_vector);
     env.register("vector-length", vector_length);
     env.register("vector-ref", vector_ref);
-    // env.register("vector-set!", vector_set);
-    // env.register("vector-fill!", vector_fill);
+    env.register("vector-set!", vector_set);
+    env.register("vector-fill!", vector_fill);
     env.register("vector->list", vector_to_list);
     env.register("list->vector", list_to_vector);
     env.register("vector-range", vector_range);
     env.register("vector-append", vector_append);
 
+    // boxes
+    env.register("box", make_box);
+    env.register("box?", box_q);
+    env.register("unbox", unbox);
+    env.register("set-box!", set_box);
+
+    // hash tables
+    env.register("make-hash", make_hash);
+    env.register("hash", hash);
+    env.register("hash?", hash_q);
+    env.register("hash-ref", hash_ref);
+    env.register("hash-set!", hash_set_bang);
+    env.register("hash-set", hash_set);
+    env.register("hash-has-key?", hash_has_key);
+    env.register("hash-count", hash_count);
+    env.register("hash-keys", hash_keys);
+    env.register("hash-values", hash_values);
+    env.register("hash-for-each", hash_for_each);
+
+    // string ports (6.13)
+    env.register("open-output-string", open_output_string);
+    env.register("open-input-string", open_input_string);
+    env.register("output-port?", output_port_q);
+    env.register("input-port?", input_port_q);
+    env.register("write-string", write_string);
+    env.register("write-char", write_char);
+    env.register("get-output-string", get_output_string);
+    env.register("read-char", read_char);
+    env.register("peek-char", peek_char);
+    env.register("read-line", read_line);
+    env.register("eof-object?", eof_object_q);
+
     // control features (6.10)
     env.register("procedure?", procedure_q);
     env.register("apply", apply);
@@ -165,20 +208,26 @@ pub fn add_to(env: &mut Env) {
     env.register("reduce", reduce);
     env.register("reduce-right", reduce_right);
     env.register("vector-map", vector_map);
-    // env.register("vector-map!", vector_map_bang);
+    env.register("vector-map!", vector_map_bang);
     env.register("vector-for-each", vector_for_each);
     env.register("for-range", for_range);
     env.register("vector-filter", vector_filter);
 
     env.register("void?", void_q);
     env.register("error", error);
+    env.register("raise", raise);
+    env.register("with-handler", with_handler);
+    env.register("error-object?", error_object_q);
+    env.register("error-object-message", error_object_message);
+    env.register("call-with-current-continuation", call_cc);
+    env.register("call/cc", call_cc);
+    env.register("dynamic-wind", dynamic_wind);
     env.register("??", qq);
     env.register("compose", compose);
     env.register("o", compose);
     env.register("|>", pipe);
     env.register("range", range);
     env.register("random", random);
-    // env.register("with-handler", with_handler);
     // env.register("ignore", ignore); // needs custom type/renderer
 
     // additional constants
@@ -187,6 +236,7 @@ pub fn add_to(env: &mut Env) {
     env.register_value("pi", f64::consts::PI);
     env.register_value("π", f64::consts::PI);
     env.register_value("void", Value::Void);
+    env.register_value("eof", Eof);
 }
 
 #[scamper_doc(
@@ -201,6 +251,29 @@ fn equal_q(x: Value, y: Value) -> bool {
     x == y
 }
 
+#[scamper_doc(
+    name = "eq?",
+    description = "Returns `#t` if and only if `v1` and `v2` are the same value. Vectors and boxes are only `eq?` to themselves, even if another has the same contents.",
+    param = ["v1", "any"],
+    param = ["v2", "any"],
+    return_type = "boolean?"
+)]
+#[function]
+fn eq_q(x: Value, y: Value) -> bool {
+    if let (Some(a), Some(b)) = (Vector::from_value(&x), Vector::from_value(&y)) {
+        return a.ptr_eq(&b);
+    }
+    if let (Some(a), Some(b)) = (ValueBox::from_value(&x), ValueBox::from_value(&y)) {
+        return a.ptr_eq(&b);
+    }
+    if let (Some(a), Some(b)) = (HashTable::from_value(&x), HashTable::from_value(&y)) {
+        if a.borrow().is_mutable() || b.borrow().is_mutable() {
+            return a.ptr_eq(&b);
+        }
+    }
+    x == y
+}
+
 #[scamper_doc(
     name = "number?",
     description = "Returns `#t` if and only if `v` is a number.",
@@ -209,7 +282,7 @@ fn equal_q(x: Value, y: Value) -> bool {
 )]
 #[function]
 fn number_q(x: Value) -> bool {
-    matches!(x, Value::Integer(_) | Value::Float(_))
+    Number::from_value(&x).is_some()
 }
 
 #[scamper_doc(
@@ -221,7 +294,8 @@ fn number_q(x: Value) -> bool {
 #[function]
 fn real_q(x: Value) -> bool {
     match x {
-        Value::Integer(_) => false,
+        Value::Integer(_) | Value::BigInteger(_) => false,
+        Value::Rational(_) => true,
         Value::Float(f) => f.fract() != 0.0,
         _ => false,
     }
@@ -236,7 +310,7 @@ fn real_q(x: Value) -> bool {
 #[function]
 fn integer_q(x: Value) -> bool {
     match x {
-        Value::Integer(_) => true,
+        Value::Integer(_) | Value::BigInteger(_) => true,
         Value::Float(f) => f.fract() == 0.0,
         _ => false,
     }
@@ -264,7 +338,7 @@ fn nan_q(x: Value) -> bool {
 )]
 #[function]
 fn zero_q(x: Number) -> bool {
-    x == 0.0
+    x.is_zero()
 }
 
 #[scamper_doc(
@@ -275,7 +349,7 @@ fn zero_q(x: Number) -> bool {
 )]
 #[function]
 fn positive_q(x: Number) -> bool {
-    x > 0.0
+    x > Number::Integer(0)
 }
 
 #[scamper_doc(
@@ -286,7 +360,7 @@ fn positive_q(x: Number) -> bool {
 )]
 #[function]
 fn negative_q(x: Number) -> bool {
-    x < 0.0
+    x < Number::Integer(0)
 }
 
 #[scamper_doc(
@@ -296,8 +370,14 @@ fn negative_q(x: Number) -> bool {
     return_type = "boolean?"
 )]
 #[function]
-fn odd_q(x: i64) -> bool {
-    x % 2 != 0
+fn odd_q(x: Number) -> Result<bool, RuntimeError> {
+    match x.remainder(&Number::Integer(2)) {
+        Some(r) => Ok(!r.is_zero()),
+        None => Err(RuntimeError::new(
+            format!("expected an integer, got {x}"),
+            None,
+        )),
+    }
 }
 
 #[scamper_doc(
@@ -307,8 +387,14 @@ fn odd_q(x: i64) -> bool {
     return_type = "boolean?"
 )]
 #[function]
-fn even_q(x: i64) -> bool {
-    x % 2 == 0
+fn even_q(x: Number) -> Result<bool, RuntimeError> {
+    match x.remainder(&Number::Integer(2)) {
+        Some(r) => Ok(r.is_zero()),
+        None => Err(RuntimeError::new(
+            format!("expected an integer, got {x}"),
+            None,
+        )),
+    }
 }
 
 #[function]
@@ -338,9 +424,9 @@ fn equal(x: Number, y: Number) -> bool {
 
 #[function]
 fn plus(args: &[Number]) -> Number {
-    let mut sum: Number = 0.0.into();
+    let mut sum = Number::Integer(0);
     for arg in args {
-        sum = sum + *arg;
+        sum = sum + arg.clone();
     }
     sum
 }
@@ -354,20 +440,20 @@ fn minus(args: &[Number]) -> Result<Number, RuntimeError> {
         ));
     }
     if args.len() == 1 {
-        return Ok(0.0 - args[0]);
+        return Ok(-args[0].clone());
     }
-    let mut difference = args[0];
-    for i in 1..args.len() {
-        difference = difference - args[i];
+    let mut difference = args[0].clone();
+    for arg in &args[1..] {
+        difference = difference - arg.clone();
     }
     Ok(difference)
 }
 
 #[function]
 fn times(args: &[Number]) -> Number {
-    let mut product: Number = 1.0.into();
+    let mut product = Number::Integer(1);
     for arg in args {
-        product = product * *arg;
+        product = product * arg.clone();
     }
     product
 }
@@ -380,33 +466,48 @@ fn divide(args: &[Number]) -> Result<Number, RuntimeError> {
             None,
         ));
     }
-    let mut quotient = args[0];
-    for i in 1..args.len() {
-        quotient = quotient / args[i];
+    // a single argument is divided into 1
+    let (mut quotient, divisors) = match args {
+        [_] => (Number::Integer(1), args),
+        _ => (args[0].clone(), &args[1..]),
+    };
+    for arg in divisors {
+        quotient = quotient
+            .checked_div(arg)
+            .ok_or_else(|| RuntimeError::new("Division by zero".to_string(), None))?;
     }
     Ok(quotient)
 }
 
 #[function]
 fn max(args: &[Number]) -> Number {
-    let mut max = f64::NEG_INFINITY.into();
-    for arg in args {
-        if *arg > max {
-            max = *arg;
-        }
-    }
-    max
+    extremum(args, Ordering::Greater)
 }
 
 #[function]
 fn min(args: &[Number]) -> Number {
-    let mut min = f64::INFINITY.into();
-    for arg in args {
-        if *arg < min {
-            min = *arg;
+    extremum(args, Ordering::Less)
+}
+
+// the argument furthest in the direction of `order`, inexact if any argument is
+fn extremum(args: &[Number], order: Ordering) -> Number {
+    let Some(first) = args.first() else {
+        return Number::Float(match order {
+            Ordering::Greater => f64::NEG_INFINITY,
+            _ => f64::INFINITY,
+        });
+    };
+    let mut result = first.clone();
+    for arg in &args[1..] {
+        if arg.partial_cmp(&result) == Some(order) {
+            result = arg.clone();
         }
     }
-    min
+    if args.iter().all(Number::is_exact) {
+        result
+    } else {
+        result.to_inexact()
+    }
 }
 
 #[function]
@@ -415,56 +516,101 @@ fn abs(x: Number) -> Number {
 }
 
 #[function]
-fn quotient(x: Number, y: Number) -> Value {
-    let result: f64 = (x / y).into();
-    Value::Integer(result as i64)
+fn quotient(x: Number, y: Number) -> Result<Number, RuntimeError> {
+    x.quotient(&y).ok_or_else(|| integer_division_error(&y))
 }
 
 #[function]
-fn remainder(x: Number, y: Number) -> Value {
-    let result: f64 = (x % y).into();
-    Value::Integer(result as i64)
+fn remainder(x: Number, y: Number) -> Result<Number, RuntimeError> {
+    x.remainder(&y).ok_or_else(|| integer_division_error(&y))
 }
 
 #[function]
-fn modulo(x: Number, y: Number) -> Number {
-    x % y
+fn modulo(x: Number, y: Number) -> Result<Number, RuntimeError> {
+    x.modulo(&y).ok_or_else(|| integer_division_error(&y))
+}
+
+fn integer_division_error(divisor: &Number) -> RuntimeError {
+    if divisor.is_zero() {
+        RuntimeError::new("Division by zero".to_string(), None)
+    } else {
+        RuntimeError::new("expected integer arguments".to_string(), None)
+    }
 }
 
 #[function]
 fn floor(x: Number) -> Number {
-    f64::floor(x.into()).into()
+    x.floor()
 }
 
 #[function]
 fn ceiling(x: Number) -> Number {
-    f64::ceil(x.into()).into()
+    x.ceiling()
 }
 
 #[function]
 fn truncate(x: Number) -> Number {
-    f64::trunc(x.into()).into()
+    x.truncate()
 }
 
 #[function]
 fn round(x: Number) -> Number {
-    f64::round(x.into()).into()
+    x.round()
 }
 
 #[function]
 fn square(x: Number) -> Number {
-    x * x
+    x.clone() * x
 }
 
 #[function]
 fn sqrt(x: Number) -> Number {
-    f64::sqrt(x.into()).into()
+    x.sqrt()
+}
+
+#[function]
+fn expt(x: Number, y: Number) -> Result<Number, RuntimeError> {
+    if x.expt_bits(&y) / 8 > memory_limit() as u64 {
+        return Err(RuntimeError::new(
+            "Result is too large to compute exactly".to_string(),
+            None,
+        ));
+    }
+    x.expt(&y)
+        .ok_or_else(|| RuntimeError::new("Division by zero".to_string(), None))
+}
+
+#[function]
+fn exact_q(x: Number) -> bool {
+    x.is_exact()
+}
+
+#[function]
+fn inexact_q(x: Number) -> bool {
+    !x.is_exact()
+}
+
+#[function]
+fn exact_to_inexact(x: Number) -> Number {
+    x.to_inexact()
+}
+
+#[function]
+fn inexact_to_exact(x: Number) -> Result<Number, RuntimeError> {
+    x.to_exact()
+        .ok_or_else(|| RuntimeError::new(format!("{x} has no exact representation"), None))
+}
+
+#[function]
+fn numerator(x: Number) -> Result<Number, RuntimeError> {
+    x.numerator()
+        .ok_or_else(|| RuntimeError::new(format!("{x} has no exact representation"), None))
 }
 
 #[function]
-fn expt(x: Number, y: Number) -> Number {
-    let x_float: f64 = x.into();
-    x_float.powf(y.into()).into()
+fn denominator(x: Number) -> Result<Number, RuntimeError> {
+    x.denominator()
+        .ok_or_else(|| RuntimeError::new(format!("{x} has no exact representation"), None))
 }
 
 #[function]
@@ -474,55 +620,60 @@ fn number_to_string(x: Number) -> String {
 
 #[function]
 fn string_to_number(x: String) -> Result<Value, RuntimeError> {
-    // check if number is integer or float
-    if x.contains('.') {
-        if let Ok(n) = x.parse::<f64>() {
-            return Ok(Value::Float(n));
-        }
-    } else if let Ok(n) = x.parse::<i64>() {
-        return Ok(Value::Integer(n));
+    if let Some(n) = Number::parse(&x) {
+        return Ok(n.into_value().unwrap());
     }
     Err(RuntimeError::new(format!("invalid string: {}", x), None))
 }
 
+// applies `f` to `x` inexactly, except at the exact argument `at`, where the
+// result is the exact `result`
+fn exact_at(x: Number, at: i64, result: i64, f: fn(f64) -> f64) -> Number {
+    if x.is_exact() && x == Number::Integer(at) {
+        Number::Integer(result)
+    } else {
+        Number::Float(f(x.into()))
+    }
+}
+
 #[function]
 fn exp(x: Number) -> Number {
-    f64::exp(x.into()).into()
+    exact_at(x, 0, 1, f64::exp)
 }
 
 #[function]
 fn log(x: Number) -> Number {
-    f64::log10(x.into()).into()
+    exact_at(x, 1, 0, f64::log10)
 }
 
 #[function]
 fn sin(x: Number) -> Number {
-    f64::sin(x.into()).into()
+    exact_at(x, 0, 0, f64::sin)
 }
 
 #[function]
 fn cos(x: Number) -> Number {
-    f64::cos(x.into()).into()
+    exact_at(x, 0, 1, f64::cos)
 }
 
 #[function]
 fn tan(x: Number) -> Number {
-    f64::tan(x.into()).into()
+    exact_at(x, 0, 0, f64::tan)
 }
 
 #[function]
 fn asin(x: Number) -> Number {
-    f64::asin(x.into()).into()
+    exact_at(x, 0, 0, f64::asin)
 }
 
 #[function]
 fn acos(x: Number) -> Number {
-    f64::acos(x.into()).into()
+    exact_at(x, 1, 0, f64::acos)
 }
 
 #[function]
 fn atan(x: Number) -> Number {
-    f64::atan(x.into()).into()
+    exact_at(x, 0, 0, f64::atan)
 }
 
 #[function]
@@ -1174,15 +1325,23 @@ fn vector_ref(v: Vector, i: i64) -> Result<Value, RuntimeError> {
     }
 }
 
-// #[function]
-// fn vector_set(v: Vector, i: i64, x: Value) -> Result<Value, RuntimeError> {
-// 	Ok(Value::Void)
-// }
+#[function]
+fn vector_set(v: Vector, i: i64, x: Value) -> Result<Value, RuntimeError> {
+    if i >= 0 && v.set(i as usize, x) {
+        Ok(Value::Void)
+    } else {
+        Err(RuntimeError::new(
+            format!("vector-set!: index {i} out of bounds"),
+            None,
+        ))
+    }
+}
 
-// #[function]
-// fn vector_fill(v: Vector, x: Value) -> Value {
-//     Ok(Value::Void)
-// }
+#[function]
+fn vector_fill(v: Vector, x: Value) -> Value {
+    v.fill(x);
+    Value::Void
+}
 
 #[function]
 fn vector_to_list(v: Vector) -> List {
@@ -1203,31 +1362,37 @@ fn vector_range(args: &[Number]) -> Result<Vector, RuntimeError> {
         ));
     }
     let m = if args.len() == 1 {
-        0.0
+        Number::Integer(0)
     } else {
-        args[0].as_f64()
+        args[0].clone()
     };
     let n = if args.len() == 1 {
-        args[0].as_f64()
+        args[0].clone()
     } else {
-        args[1].as_f64()
+        args[1].clone()
     };
     let step = if args.len() == 3 {
-        args[2].as_f64()
+        args[2].clone()
     } else {
-        1.0
+        Number::Integer(1)
     };
-    if step == 0.0 {
+    if step.is_zero() {
         return Err(RuntimeError::new(
             format!("\"step\" argument must be non-zero"),
             None,
         ));
     }
     let mut v = Vec::new();
-    let mut i = m;
-    while (step > 0.0 && i < n) || (step < 0.0 && i > n) {
-        v.push(Value::Float(i));
-        i += step;
+    // the elements are inexact if any of the arguments are
+    let mut i = if args.iter().all(Number::is_exact) {
+        m
+    } else {
+        m.to_inexact()
+    };
+    let zero = Number::Integer(0);
+    while (step > zero && i < n) || (step < zero && i > n) {
+        v.push(i.clone().into_value().unwrap());
+        i = i + step.clone();
     }
     Ok(v.into())
 }
@@ -1241,6 +1406,366 @@ fn vector_append(vectors: &[Vector]) -> Vector {
     v.into()
 }
 
+#[function]
+fn make_box(value: Value) -> ValueBox {
+    ValueBox::new(value)
+}
+
+#[function]
+fn box_q(value: Value) -> bool {
+    matches!(value, Value::Box(_))
+}
+
+#[function]
+fn unbox(b: ValueBox) -> Value {
+    b.get()
+}
+
+#[function]
+fn set_box(b: ValueBox, value: Value) -> Value {
+    b.set(value);
+    Value::Void
+}
+
+#[scamper_doc(
+    name = "make-hash",
+    description = "Returns a new mutable hash table, holding the key-value pairs of `assocs` if it is given. Keys are compared with `equal?`.",
+    param = ["assocs", "list?"],
+    return_type = "hash?"
+)]
+#[function]
+fn make_hash(assocs: &[Value]) -> Result<HashTable, RuntimeError> {
+    let mut table = Table::new(true);
+    match assocs {
+        [] => {}
+        [assocs] => {
+            let Some(assocs) = List::from_value(assocs) else {
+                return Err(RuntimeError::new(
+                    "Expected a list of pairs".to_string(),
+                    None,
+                ));
+            };
+            for assoc in assocs.values_vec() {
+                let (key, value) = match assoc {
+                    Value::Pair(key, value) => (*key, *value),
+                    // pairs whose second value is a list are lists
+                    Value::List(mut values) if !values.is_empty() => {
+                        let key = values.remove(0);
+                        (key, List::from(values).into_value().unwrap())
+                    }
+                    _ => {
+                        return Err(RuntimeError::new(
+                            "Expected a list of pairs".to_string(),
+                            None,
+                        ))
+                    }
+                };
+                table.insert(key, value);
+            }
+        }
+        _ => {
+            return Err(RuntimeError::new(
+                format!("expected 0 to 1 arguments, found {}", assocs.len()),
+                None,
+            ))
+        }
+    }
+    Ok(HashTable::new(table))
+}
+
+#[scamper_doc(
+    name = "hash",
+    description = "Returns an immutable hash table that maps each key `k` to the value `v` after it.",
+    param = ["k", "any"],
+    param = ["v", "any"],
+    return_type = "hash?"
+)]
+#[function]
+fn hash(args: &[Value]) -> Result<HashTable, RuntimeError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(RuntimeError::new(
+            "Expected an even number of arguments: keys each followed by a value".to_string(),
+            None,
+        ));
+    }
+    let mut table = Table::new(false);
+    for entry in args.chunks(2) {
+        table.insert(entry[0].clone(), entry[1].clone());
+    }
+    Ok(HashTable::new(table))
+}
+
+#[function]
+fn hash_q(value: Value) -> bool {
+    matches!(value, Value::Hash(_))
+}
+
+#[scamper_doc(
+    name = "hash-ref",
+    description = "Returns the value `table` maps `key` to. If there is none, returns `default`, or the result of calling it if it is a procedure, or fails if it isn't given.",
+    param = ["table", "hash?"],
+    param = ["key", "any"],
+    param = ["default", "any"],
+    return_type = "any"
+)]
+#[function]
+fn hash_ref(table: HashTable, key: Value, default: &[Value]) -> Result<Value, RuntimeError> {
+    if let Some(value) = table.borrow().get(&key) {
+        return Ok(value.clone());
+    }
+    match default {
+        [] => Err(RuntimeError::new(
+            format!("no value found for key {}", key),
+            None,
+        )),
+        [default] => match Function::from_value(default) {
+            Some(f) => f.call(&[]),
+            None => Ok(default.clone()),
+        },
+        _ => Err(RuntimeError::new(
+            format!("expected 2 to 3 arguments, found {}", default.len() + 2),
+            None,
+        )),
+    }
+}
+
+#[function]
+fn hash_set_bang(table: HashTable, key: Value, value: Value) -> Result<Value, RuntimeError> {
+    if !table.borrow().is_mutable() {
+        return Err(RuntimeError::new(
+            "Expected a mutable hash table, use hash-set to extend an immutable one".to_string(),
+            None,
+        ));
+    }
+    table.insert(key, value);
+    Ok(Value::Void)
+}
+
+#[scamper_doc(
+    name = "hash-set",
+    description = "Returns an immutable hash table like `table`, but mapping `key` to `value`. `table` is left unchanged.",
+    param = ["table", "hash?"],
+    param = ["key", "any"],
+    param = ["value", "any"],
+    return_type = "hash?"
+)]
+#[function]
+fn hash_set(table: HashTable, key: Value, value: Value) -> Result<HashTable, RuntimeError> {
+    if table.borrow().is_mutable() {
+        return Err(RuntimeError::new(
+            "Expected an immutable hash table, use hash-set! to change a mutable one".to_string(),
+            None,
+        ));
+    }
+    // the copy shares the entries of `table` instead of copying them
+    let mut table = table.borrow().clone();
+    table.insert(key, value);
+    Ok(HashTable::new(table))
+}
+
+#[function]
+fn hash_has_key(table: HashTable, key: Value) -> bool {
+    table.borrow().get(&key).is_some()
+}
+
+#[function]
+fn hash_count(table: HashTable) -> i64 {
+    table.borrow().len() as i64
+}
+
+#[function]
+fn hash_keys(table: HashTable) -> List {
+    let keys = table
+        .borrow()
+        .entries()
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    List::from(keys)
+}
+
+#[function]
+fn hash_values(table: HashTable) -> List {
+    let values = table
+        .borrow()
+        .entries()
+        .map(|(_, value)| value.clone())
+        .collect::<Vec<_>>();
+    List::from(values)
+}
+
+#[scamper_doc(
+    name = "hash-for-each",
+    description = "Calls `(f key value)` for each entry of `table`, in the order the keys were added.",
+    param = ["table", "hash?"],
+    param = ["f", "procedure?"],
+    return_type = "void?"
+)]
+#[function]
+fn hash_for_each(table: HashTable, f: Function) -> Result<Value, RuntimeError> {
+    // the table may change while it is iterated
+    let entries = table.borrow().clone();
+    for (key, value) in entries.entries() {
+        f.call(&[key.clone(), value.clone()])?;
+    }
+    Ok(Value::Void)
+}
+
+/// A port that collects the text written to it, so that a string can be built
+/// up piece by piece without copying it each time. Clones share the same text.
+#[derive(Debug, Clone, ForeignValue)]
+pub struct OutputPort(Rc<RefCell<String>>);
+
+/// A port that reads the characters of a string in order. Clones share the
+/// same position.
+#[derive(Debug, Clone, ForeignValue)]
+pub struct InputPort(Rc<RefCell<StringReader>>);
+
+#[derive(Debug)]
+pub struct StringReader {
+    text: String,
+    // byte offset of the next character to read
+    pos: usize,
+}
+
+impl StringReader {
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+}
+
+/// The value read from a port that has no characters left.
+#[derive(Debug, Clone, ForeignValue)]
+pub struct Eof;
+
+#[scamper_doc(
+    name = "open-output-string",
+    description = "Returns a new output port that collects the text written to it, which can be retrieved with `get-output-string`.",
+    return_type = "output-port?"
+)]
+#[function]
+fn open_output_string() -> OutputPort {
+    OutputPort(Rc::new(RefCell::new(String::new())))
+}
+
+#[scamper_doc(
+    name = "open-input-string",
+    description = "Returns a new input port that reads the characters of `s`.",
+    param = ["s", "string?"],
+    return_type = "input-port?"
+)]
+#[function]
+fn open_input_string(s: String) -> InputPort {
+    InputPort(Rc::new(RefCell::new(StringReader { text: s, pos: 0 })))
+}
+
+#[function]
+fn output_port_q(v: Value) -> bool {
+    OutputPort::from_value(&v).is_some()
+}
+
+#[function]
+fn input_port_q(v: Value) -> bool {
+    InputPort::from_value(&v).is_some()
+}
+
+#[scamper_doc(
+    name = "write-string",
+    description = "Appends `s` to the text of the output port `port`.",
+    param = ["s", "string?"],
+    param = ["port", "output-port?"],
+    return_type = "void?"
+)]
+#[function]
+fn write_string(s: String, port: OutputPort) -> Value {
+    port.0.borrow_mut().push_str(&s);
+    Value::Void
+}
+
+#[scamper_doc(
+    name = "write-char",
+    description = "Appends `c` to the text of the output port `port`.",
+    param = ["c", "char?"],
+    param = ["port", "output-port?"],
+    return_type = "void?"
+)]
+#[function]
+fn write_char(c: char, port: OutputPort) -> Value {
+    port.0.borrow_mut().push(c);
+    Value::Void
+}
+
+#[scamper_doc(
+    name = "get-output-string",
+    description = "Returns the text written to the output port `port` so far.",
+    param = ["port", "output-port?"],
+    return_type = "string?"
+)]
+#[function]
+fn get_output_string(port: OutputPort) -> String {
+    port.0.borrow().clone()
+}
+
+#[scamper_doc(
+    name = "read-char",
+    description = "Reads the next character of the input port `port`, or returns `eof` if none are left.",
+    param = ["port", "input-port?"],
+    return_type = "char?"
+)]
+#[function]
+fn read_char(port: InputPort) -> Value {
+    match port.0.borrow_mut().next() {
+        Some(c) => Value::Char(c),
+        None => Eof.into_value().unwrap(),
+    }
+}
+
+#[scamper_doc(
+    name = "peek-char",
+    description = "Returns the next character of the input port `port` without reading it, or `eof` if none are left.",
+    param = ["port", "input-port?"],
+    return_type = "char?"
+)]
+#[function]
+fn peek_char(port: InputPort) -> Value {
+    match port.0.borrow().peek() {
+        Some(c) => Value::Char(c),
+        None => Eof.into_value().unwrap(),
+    }
+}
+
+#[scamper_doc(
+    name = "read-line",
+    description = "Reads the characters of the input port `port` up to the next line break, which is read but not returned, or returns `eof` if none are left.",
+    param = ["port", "input-port?"],
+    return_type = "string?"
+)]
+#[function]
+fn read_line(port: InputPort) -> Value {
+    let mut reader = port.0.borrow_mut();
+    if reader.peek().is_none() {
+        return Eof.into_value().unwrap();
+    }
+    let rest = &reader.text[reader.pos..];
+    let (line, len) = match rest.find('\n') {
+        Some(i) => (&rest[..i], i + 1),
+        None => (rest, rest.len()),
+    };
+    let line = line.strip_suffix('\r').unwrap_or(line).to_string();
+    reader.pos += len;
+    Value::String(line)
+}
+
+#[function]
+fn eof_object_q(v: Value) -> bool {
+    Eof::from_value(&v).is_some()
+}
+
 #[function]
 fn procedure_q(v: Value) -> bool {
     matches!(v, Value::Function(_, _) | Value::Closure { .. })
@@ -1412,19 +1937,11 @@ fn vector_map(args: &[Value]) -> Result<Vector, RuntimeError> {
         None,
     ))?;
 
-    let mut lists = Vec::new();
+    let mut vectors = Vec::new();
     for arg in args.iter().skip(1) {
-        match arg {
-            Value::List(l) => lists.push(l.clone()),
-            Value::Null => {
-                if !lists.is_empty() {
-                    return Err(RuntimeError::new(
-							format!("vector-map: the vectors passed to the function call do not have the same length"),
-							 None,
-								));
-                }
-            }
-            _ => {
+        match Vector::from_value(arg) {
+            Some(v) => vectors.push(v.values_vec()),
+            None => {
                 return Err(RuntimeError::new(
                     format!("vector-map: all arguments after the first must be vectors"),
                     None,
@@ -1433,23 +1950,31 @@ fn vector_map(args: &[Value]) -> Result<Vector, RuntimeError> {
         }
     }
 
-    if lists.is_empty() {
-        return Ok(Vector::empty());
-    }
-
-    let list_len = lists[0].len();
-    if lists.iter().any(|l| l.len() != list_len) {
+    let vec_len = vectors[0].len();
+    if vectors.iter().any(|v| v.len() != vec_len) {
         return Err(RuntimeError::new(
             format!("the vectors passed to the function call do not have the same length"),
             None,
         ));
     }
 
-    let result = map_prim(f, lists)?;
+    let result = map_prim(f, vectors)?;
 
     Ok(result.into())
 }
 
+#[function]
+fn vector_map_bang(f: Function, vec: Vector) -> Result<Value, RuntimeError> {
+    // the vector isn't borrowed across calls so that `f` may inspect it
+    for i in 0..vec.len() {
+        let Some(value) = vec.get(i) else {
+            break;
+        };
+        vec.set(i, f.call(&[value])?);
+    }
+    Ok(Value::Void)
+}
+
 #[function]
 fn vector_for_each(f: Function, vec: Vector) -> Result<Value, RuntimeError> {
     for value in vec.values_vec() {
@@ -1493,6 +2018,70 @@ fn error(message: String) -> Result<Value, RuntimeError> {
     Err(RuntimeError::new(message, None))
 }
 
+#[scamper_doc(
+    name = "raise",
+    description = "Raises `v` as an exception, to be caught by an enclosing `with-handler`.",
+    param = ["v", "any"],
+    return_type = "void?"
+)]
+#[function]
+fn raise(value: Value) -> Result<Value, RuntimeError> {
+    Err(RuntimeError::raise(value))
+}
+
+#[scamper_doc(
+    name = "with-handler",
+    description = "Calls `(fn v1 ... vk)`. If an exception is raised, returns the result of calling `handler` with the raised value instead, or with an error object for errors not raised by `raise`.",
+    param = ["handler", "procedure?"],
+    param = ["fn", "procedure?"],
+    param = ["v", "any"],
+    return_type = "any"
+)]
+#[function]
+fn with_handler(handler: Function, f: Function, args: &[Value]) -> Result<Value, RuntimeError> {
+    match f.call(args) {
+        Ok(value) => Ok(value),
+        // jumps to continuations aren't exceptions
+        Err(err) if err.control.is_some() => Err(err),
+        Err(err) => handler.call(&[err.condition()]),
+    }
+}
+
+#[scamper_doc(
+    name = "call/cc",
+    description = "Calls `fn` with the current continuation, a procedure that returns its argument from the call to `call/cc` when called, abandoning the computation in progress. Also available as `call-with-current-continuation`.",
+    param = ["fn", "procedure?"],
+    return_type = "any"
+)]
+#[function]
+fn call_cc(receiver: Function) -> Result<Value, RuntimeError> {
+    // the program's execution stack captures the continuation of this call
+    Err(RuntimeError::control(Control::Capture(receiver.value())))
+}
+
+#[scamper_doc(
+    name = "dynamic-wind",
+    description = "Calls `before`, then `thunk`, then `after`, returning the result of `thunk`. `after` is called even if `thunk` is left early by an exception or a continuation, and `before` is called again when a continuation returns into `thunk`.",
+    param = ["before", "procedure?"],
+    param = ["thunk", "procedure?"],
+    param = ["after", "procedure?"],
+    return_type = "any"
+)]
+#[function]
+fn dynamic_wind(before: Function, thunk: Function, after: Function) -> Result<Value, RuntimeError> {
+    crate::interpreter::dynamic_wind(&before, &thunk, &after)
+}
+
+#[function]
+fn error_object_q(value: Value) -> bool {
+    ErrorObject::from_value(&value).is_some()
+}
+
+#[function]
+fn error_object_message(object: ErrorObject) -> String {
+    object.message
+}
+
 #[function]
 fn qq() -> Result<Value, RuntimeError> {
     Err(RuntimeError::new(
@@ -1510,24 +2099,21 @@ fn compose(funcs: &[Function]) -> Result<Value, RuntimeError> {
             None,
         ));
     }
-    let mut operations: Vec<Operation> = Vec::new();
-
-    for func in funcs.iter() {
-        operations.push(Operation::value(func.value(), Span { loc: 0, len: 0 }));
-    }
-    operations.push(Operation::var(String::from("x"), Span { loc: 0, len: 0 }));
-    for _ in funcs.iter().rev() {
-        operations.push(Operation::ap(1, Span { loc: 0, len: 0 }));
-    }
-
-    Ok(Value::Closure(
-        Closure {
-            params: vec![String::from("x")],
-            body: operations,
-            env: None,
-        },
-        None,
-    ))
+    let funcs = funcs.to_vec();
+    let composed = move |args: &[Value]| {
+        if args.len() != 1 {
+            return Err(RuntimeError::new(
+                format!("expected 1 argument, found {}", args.len()),
+                None,
+            ));
+        }
+        // the last function is applied first
+        funcs
+            .iter()
+            .rev()
+            .try_fold(args[0].clone(), |acc, f| f.call(&[acc]))
+    };
+    Ok(Value::Function(NativeFn::new(composed), None))
 }
 
 #[function]
@@ -1562,31 +2148,37 @@ fn range(args: &[Number]) -> Result<List, RuntimeError> {
         ));
     }
     let m = if args.len() == 1 {
-        0.0
+        Number::Integer(0)
     } else {
-        args[0].as_f64()
+        args[0].clone()
     };
     let n = if args.len() == 1 {
-        args[0].as_f64()
+        args[0].clone()
     } else {
-        args[1].as_f64()
+        args[1].clone()
     };
     let step = if args.len() == 3 {
-        args[2].as_f64()
+        args[2].clone()
     } else {
-        1.0
+        Number::Integer(1)
     };
-    if step == 0.0 {
+    if step.is_zero() {
         return Err(RuntimeError::new(
             "step argument must be non-zero".to_string(),
             None,
         ));
     }
     let mut result = Vec::new();
-    let mut i = m;
-    while (step > 0.0 && i < n) || (step < 0.0 && i > n) {
-        result.push(Value::Float(i));
-        i += step;
+    // the elements are inexact if any of the arguments are
+    let mut i = if args.iter().all(Number::is_exact) {
+        m
+    } else {
+        m.to_inexact()
+    };
+    let zero = Number::Integer(0);
+    while (step > zero && i < n) || (step < zero && i > n) {
+        result.push(i.clone().into_value().unwrap());
+        i = i + step.clone();
     }
     Ok(result.into())
 }
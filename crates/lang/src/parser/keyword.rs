@@ -1,5 +1,8 @@
 pub const Define: &str = "define";
+pub const DefineSyntax: &str = "define-syntax";
+pub const SyntaxRules: &str = "syntax-rules";
 pub const Import: &str = "import";
+pub const Provide: &str = "provide";
 pub const Display: &str = "display";
 pub const Struct: &str = "struct";
 
@@ -15,9 +18,33 @@ pub const Lambda: &str = "lambda";
 pub const Begin: &str = "begin";
 pub const Cond: &str = "cond";
 pub const Quote: &str = "quote";
+pub const Quasiquote: &str = "quasiquote";
+pub const Unquote: &str = "unquote";
+pub const UnquoteSplicing: &str = "unquote-splicing";
 pub const Section: &str = "section";
+pub const Set: &str = "set!";
 
-pub const RESERVED_WORDS: [&str; 15] = [
-    And, Begin, Cond, Define, If, Import, Lambda, Let, LetStar, LetRec, Match, Or, Quote, Section,
+pub const RESERVED_WORDS: [&str; 22] = [
+    And,
+    Begin,
+    Cond,
+    Define,
+    DefineSyntax,
+    If,
+    Import,
+    Lambda,
+    Let,
+    LetStar,
+    LetRec,
+    Match,
+    Or,
+    Provide,
+    Quasiquote,
+    Quote,
+    Section,
+    Set,
     Struct,
+    SyntaxRules,
+    Unquote,
+    UnquoteSplicing,
 ];
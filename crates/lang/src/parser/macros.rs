@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{ParserValue, ParserValueKind, Result};
+use crate::ast::{Span, Value};
+use crate::diagnostics::ParseError;
+
+/// Separates an identifier introduced by a macro expansion from the mark of
+/// that expansion. Lexed identifiers can never contain it.
+const MARK: char = '\u{1}';
+
+const ELLIPSIS: &str = "...";
+
+/// Macros by name.
+pub type Macros = HashMap<String, Rc<Macro>>;
+
+/// Returns an identifier without the marks of the macro expansions that
+/// introduced it, i.e. the way it was written in the source.
+pub fn base_name(name: &str) -> &str {
+    name.split(MARK).next().unwrap_or(name)
+}
+
+/// Returns an identifier that refers to the top-level binding `name` even
+/// where a local variable shadows it, for code the parser generates. Mark 0
+/// is never given to a macro expansion.
+pub(crate) fn top_level(name: &str) -> String {
+    format!("{name}{MARK}0")
+}
+
+/// Returns the file whose macro introduced an identifier, if a macro defined
+/// in an imported file did.
+pub(crate) fn origin(name: &str) -> Option<&str> {
+    name.splitn(3, MARK).nth(2)
+}
+
+/// Returns the hidden name an importer binds the top-level binding `name` of
+/// the file at `path` to, so that identifiers the file's macros introduce can
+/// refer to bindings the file doesn't provide.
+pub(crate) fn imported_from(name: &str, path: &str) -> String {
+    format!("{name}{MARK}{MARK}{path}")
+}
+
+/// A macro defined with `syntax-rules`.
+#[derive(Debug)]
+pub struct Macro {
+    name: String,
+    // the file the macro is defined in, if it isn't the program itself
+    origin: Option<String>,
+    // identifiers that match themselves in patterns
+    literals: Vec<String>,
+    rules: Vec<(ParserValue, ParserValue)>,
+}
+
+// what a pattern variable matched; variables under an ellipsis match a
+// sequence of forms
+#[derive(Debug, Clone)]
+enum Binding {
+    One(ParserValue),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<String, Binding>;
+
+impl Macro {
+    /// Parses the `(syntax-rules (literal ...) [pattern template] ...)` form
+    /// that defines the macro `name` in the file at `origin`, or in the
+    /// program if it is `None`.
+    pub fn parse(name: String, spec: &ParserValue, origin: Option<String>) -> Result<Self> {
+        let error = || {
+            ParseError::new(
+                "define-syntax expects a syntax-rules form: (syntax-rules (literal ...) [pattern template] ...)",
+                Some(spec.span),
+            )
+        };
+        let ParserValueKind::List(items) = &spec.kind else {
+            return Err(error());
+        };
+        let Some((head, rest)) = items.split_first() else {
+            return Err(error());
+        };
+        if !matches!(&head.kind, ParserValueKind::Symbol(s) if base_name(s) == super::keyword::SyntaxRules)
+        {
+            return Err(error());
+        }
+        let Some((literals, rules)) = rest.split_first() else {
+            return Err(error());
+        };
+
+        let literals = match &literals.kind {
+            ParserValueKind::List(literals) => literals
+                .iter()
+                .map(|literal| match &literal.kind {
+                    ParserValueKind::Symbol(s) => Ok(s.clone()),
+                    _ => Err(ParseError::new(
+                        "syntax-rules literals must be identifiers",
+                        Some(literal.span),
+                    )),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            ParserValueKind::Value(Value::Null) => Vec::new(),
+            _ => {
+                return Err(ParseError::new(
+                    "the first component of syntax-rules must be a list of literals",
+                    Some(literals.span),
+                ))
+            }
+        };
+
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let (ParserValueKind::Vector(parts) | ParserValueKind::List(parts)) = &rule.kind
+                else {
+                    return Err(ParseError::new(
+                        "syntax-rules rules must be pairs of a pattern and a template",
+                        Some(rule.span),
+                    ));
+                };
+                let [pattern, template] = parts.as_slice() else {
+                    return Err(ParseError::new(
+                        "syntax-rules rules must be pairs of a pattern and a template",
+                        Some(rule.span),
+                    ));
+                };
+                if !matches!(pattern.kind, ParserValueKind::List(_)) {
+                    return Err(ParseError::new(
+                        "syntax-rules patterns must be lists that begin with the macro's name",
+                        Some(pattern.span),
+                    ));
+                }
+                Ok((pattern.clone(), template.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            name,
+            origin,
+            literals,
+            rules,
+        })
+    }
+
+    /// Rewrites a use of the macro with the template of the first rule whose
+    /// pattern matches it. Identifiers the template introduces are marked with
+    /// `mark` and the file the macro comes from, and everything it introduces
+    /// is located at the use site.
+    pub fn expand(&self, form: &ParserValue, mark: u32) -> Result<ParserValue> {
+        let ParserValueKind::List(items) = &form.kind else {
+            return Err(ParseError::new(
+                format!("macro `{}` must be used as the head of a list", self.name),
+                Some(form.span),
+            ));
+        };
+        for (pattern, template) in &self.rules {
+            let ParserValueKind::List(patterns) = &pattern.kind else {
+                continue;
+            };
+            // the macro's own name is never matched
+            let mut bindings = Bindings::new();
+            if self.match_all(&patterns[1..], &items[1..], &mut bindings) {
+                let expansion = Expansion {
+                    mark,
+                    origin: self.origin.as_deref(),
+                    span: form.span,
+                };
+                return expansion.instantiate(template, &bindings);
+            }
+        }
+        Err(ParseError::new(
+            format!("no syntax rule of macro `{}` matches this use", self.name),
+            Some(form.span),
+        ))
+    }
+
+    fn match_form(
+        &self,
+        pattern: &ParserValue,
+        form: &ParserValue,
+        bindings: &mut Bindings,
+    ) -> bool {
+        match (&pattern.kind, &form.kind) {
+            (ParserValueKind::Symbol(p), _) if p == "_" => true,
+            (ParserValueKind::Symbol(p), _) if self.literals.contains(p) => {
+                matches!(&form.kind, ParserValueKind::Symbol(f) if base_name(f) == p)
+            }
+            (ParserValueKind::Symbol(p), _) => {
+                bindings.insert(p.clone(), Binding::One(form.clone()));
+                true
+            }
+            (ParserValueKind::List(patterns), ParserValueKind::List(forms))
+            | (ParserValueKind::Vector(patterns), ParserValueKind::Vector(forms)) => {
+                self.match_all(patterns, forms, bindings)
+            }
+            (ParserValueKind::List(patterns), ParserValueKind::Value(Value::Null)) => {
+                self.match_all(patterns, &[], bindings)
+            }
+            (ParserValueKind::Value(p), ParserValueKind::Value(f)) => p == f,
+            _ => false,
+        }
+    }
+
+    // matches a sequence of forms, where the pattern before an ellipsis
+    // matches any number of them
+    fn match_all(
+        &self,
+        patterns: &[ParserValue],
+        forms: &[ParserValue],
+        bindings: &mut Bindings,
+    ) -> bool {
+        let ellipsis = patterns.iter().position(is_ellipsis);
+        let Some(ellipsis) = ellipsis.filter(|&i| i > 0) else {
+            return patterns.len() == forms.len()
+                && patterns
+                    .iter()
+                    .zip(forms)
+                    .all(|(pattern, form)| self.match_form(pattern, form, bindings));
+        };
+
+        let before = &patterns[..ellipsis - 1];
+        let repeated = &patterns[ellipsis - 1];
+        let after = &patterns[ellipsis + 1..];
+        if forms.len() < before.len() + after.len() {
+            return false;
+        }
+        let (first, rest) = forms.split_at(before.len());
+        let (middle, last) = rest.split_at(rest.len() - after.len());
+        if !self.match_all(before, first, bindings) || !self.match_all(after, last, bindings) {
+            return false;
+        }
+
+        let mut variables = Vec::new();
+        self.variables(repeated, &mut variables);
+        let mut sequences = vec![Vec::new(); variables.len()];
+        for form in middle {
+            let mut inner = Bindings::new();
+            if !self.match_form(repeated, form, &mut inner) {
+                return false;
+            }
+            for (variable, sequence) in variables.iter().zip(&mut sequences) {
+                if let Some(binding) = inner.remove(variable) {
+                    sequence.push(binding);
+                }
+            }
+        }
+        for (variable, sequence) in variables.into_iter().zip(sequences) {
+            bindings.insert(variable, Binding::Many(sequence));
+        }
+        true
+    }
+
+    // the pattern variables a pattern binds
+    fn variables(&self, pattern: &ParserValue, variables: &mut Vec<String>) {
+        match &pattern.kind {
+            ParserValueKind::Symbol(s) => {
+                if s != "_" && s != ELLIPSIS && !self.literals.contains(s) {
+                    variables.push(s.clone());
+                }
+            }
+            ParserValueKind::List(patterns) | ParserValueKind::Vector(patterns) => {
+                for pattern in patterns {
+                    self.variables(pattern, variables);
+                }
+            }
+            ParserValueKind::Value(_) => {}
+        }
+    }
+}
+
+struct Expansion<'a> {
+    mark: u32,
+    origin: Option<&'a str>,
+    // the use site of the macro
+    span: Span,
+}
+
+impl Expansion<'_> {
+    fn instantiate(&self, template: &ParserValue, bindings: &Bindings) -> Result<ParserValue> {
+        match &template.kind {
+            ParserValueKind::Symbol(s) => match bindings.get(s) {
+                Some(Binding::One(form)) => Ok(form.clone()),
+                Some(Binding::Many(_)) => Err(ParseError::new(
+                    format!(
+                        "pattern variable `{s}` must be followed by an ellipsis in the template"
+                    ),
+                    Some(self.span),
+                )),
+                None => Ok(ParserValue::sym(
+                    match self.origin {
+                        Some(origin) => format!("{s}{MARK}{}{MARK}{origin}", self.mark),
+                        None => format!("{s}{MARK}{}", self.mark),
+                    },
+                    self.span,
+                )),
+            },
+            ParserValueKind::List(templates) => Ok(ParserValue::list(
+                self.instantiate_all(templates, bindings)?,
+                self.span,
+            )),
+            ParserValueKind::Vector(templates) => Ok(ParserValue::vector(
+                self.instantiate_all(templates, bindings)?,
+                self.span,
+            )),
+            ParserValueKind::Value(value) => Ok(ParserValue::value(value.clone(), self.span)),
+        }
+    }
+
+    fn instantiate_all(
+        &self,
+        templates: &[ParserValue],
+        bindings: &Bindings,
+    ) -> Result<Vec<ParserValue>> {
+        let mut forms = Vec::new();
+        let mut templates = templates.iter().peekable();
+        while let Some(template) = templates.next() {
+            if !templates.peek().is_some_and(|t| is_ellipsis(t)) {
+                forms.push(self.instantiate(template, bindings)?);
+                continue;
+            }
+            templates.next();
+
+            // the template repeats once for each form its sequences matched
+            let mut sequences = Vec::new();
+            collect_sequences(template, bindings, &mut sequences);
+            let Some(len) = sequences.first().map(|(_, sequence)| sequence.len()) else {
+                return Err(ParseError::new(
+                    "an ellipsis in a template must follow a pattern variable that was matched by an ellipsis",
+                    Some(self.span),
+                ));
+            };
+            if sequences.iter().any(|(_, sequence)| sequence.len() != len) {
+                return Err(ParseError::new(
+                    "pattern variables under the same ellipsis matched different numbers of forms",
+                    Some(self.span),
+                ));
+            }
+            for i in 0..len {
+                let mut inner = bindings.clone();
+                for (name, sequence) in &sequences {
+                    inner.insert(name.clone(), sequence[i].clone());
+                }
+                forms.push(self.instantiate(template, &inner)?);
+            }
+        }
+        Ok(forms)
+    }
+}
+
+// the pattern variables bound to sequences that a template uses
+fn collect_sequences(
+    template: &ParserValue,
+    bindings: &Bindings,
+    sequences: &mut Vec<(String, Vec<Binding>)>,
+) {
+    match &template.kind {
+        ParserValueKind::Symbol(s) => {
+            if let Some(Binding::Many(sequence)) = bindings.get(s) {
+                if !sequences.iter().any(|(name, _)| name == s) {
+                    sequences.push((s.clone(), sequence.clone()));
+                }
+            }
+        }
+        ParserValueKind::List(templates) | ParserValueKind::Vector(templates) => {
+            for template in templates {
+                collect_sequences(template, bindings, sequences);
+            }
+        }
+        ParserValueKind::Value(_) => {}
+    }
+}
+
+fn is_ellipsis(value: &ParserValue) -> bool {
+    matches!(&value.kind, ParserValueKind::Symbol(s) if s == ELLIPSIS)
+}
@@ -1,5 +1,7 @@
 #[allow(non_upper_case_globals)]
 pub mod keyword;
+mod macros;
+mod resolve;
 
 #[cfg(test)]
 pub mod tests;
@@ -7,6 +9,14 @@ pub mod tests;
 use crate::ast::*;
 use crate::diagnostics::ParseError;
 use crate::lexer::{LiteralKind, Token, TokenKind};
+use crate::modules::Files;
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc};
+
+use macros::top_level;
+pub use macros::{base_name, Macro, Macros};
+pub(crate) use macros::{imported_from, origin};
+pub use resolve::resolve;
+pub(crate) use resolve::resolve_module;
 
 type Result<T> = std::result::Result<T, ParseError>;
 
@@ -22,10 +32,14 @@ const NAMED_CHAR_VALUES: [(&str, char); 9] = [
     ("tab", '\t'),
 ];
 
-const SPECIAL_FORMS: [&str; 11] = [
+// how deeply macro uses may expand into further macro uses
+const MAX_EXPANSION_DEPTH: usize = 128;
+
+const SPECIAL_FORMS: [&str; 16] = [
     keyword::Lambda,
     keyword::Let,
     keyword::LetStar,
+    keyword::LetRec,
     keyword::And,
     keyword::Or,
     keyword::If,
@@ -33,7 +47,11 @@ const SPECIAL_FORMS: [&str; 11] = [
     keyword::Match,
     keyword::Cond,
     keyword::Quote,
+    keyword::Quasiquote,
+    keyword::Unquote,
+    keyword::UnquoteSplicing,
     keyword::Section,
+    keyword::Set,
 ];
 
 #[derive(Clone)]
@@ -70,6 +88,14 @@ pub struct Parser<'a> {
     pub stream: TokenStream,
     pub label_counter: u32,
     pub hole_counter: u32,
+    /// Macros in scope, by name.
+    pub macros: Macros,
+    /// Names of the macros the program itself defines, in order.
+    pub defined_macros: Vec<String>,
+    // where the macros of imported files come from, if anywhere
+    files: Option<&'a Files>,
+    mark_counter: u32,
+    expansion_depth: usize,
 }
 
 impl<'a> Parser<'a> {
@@ -83,6 +109,11 @@ impl<'a> Parser<'a> {
             stream,
             label_counter: 0,
             hole_counter: 0,
+            macros: Macros::new(),
+            defined_macros: Vec::new(),
+            files: None,
+            mark_counter: 0,
+            expansion_depth: 0,
         }
     }
 
@@ -96,6 +127,11 @@ impl<'a> Parser<'a> {
             stream,
             label_counter: 0,
             hole_counter: 0,
+            macros: Macros::new(),
+            defined_macros: Vec::new(),
+            files: None,
+            mark_counter: 0,
+            expansion_depth: 0,
         })
     }
 
@@ -172,6 +208,41 @@ impl<'a> Parser<'a> {
         label
     }
 
+    // expands a use of a macro, then lowers the result with `lower`
+    fn expand<T>(
+        &mut self,
+        mac: Rc<Macro>,
+        value: &ParserValue,
+        lower: impl FnOnce(&mut Self, ParserValue) -> Result<T>,
+    ) -> Result<T> {
+        if self.expansion_depth >= MAX_EXPANSION_DEPTH {
+            return Err(ParseError::new(
+                "macro expansion is too deeply nested",
+                Some(value.span),
+            ));
+        }
+        self.mark_counter += 1;
+        let expanded = mac.expand(value, self.mark_counter)?;
+        self.expansion_depth += 1;
+        let result = lower(self, expanded);
+        self.expansion_depth -= 1;
+        result
+    }
+
+    // the macro a form uses, if its head names one
+    fn macro_of(&self, value: &ParserValue) -> Option<Rc<Macro>> {
+        let ParserValueKind::List(values) = &value.kind else {
+            return None;
+        };
+        let ParserValueKind::Symbol(head) = &values.first()?.kind else {
+            return None;
+        };
+        self.macros
+            .get(head)
+            .or_else(|| self.macros.get(base_name(head)))
+            .cloned()
+    }
+
     pub fn fresh_hole(&mut self) -> String {
         let hole = format!("_{}", self.hole_counter);
         self.hole_counter += 1;
@@ -227,14 +298,14 @@ impl Into<Value> for ParserValue {
     fn into(self) -> Value {
         match self.kind {
             ParserValueKind::Value(value) => value,
-            ParserValueKind::Symbol(sym) => Value::Symbol(sym),
+            ParserValueKind::Symbol(sym) => Value::Symbol(base_name(&sym).to_string()),
             ParserValueKind::List(values) => {
                 let values: Vec<Value> = values.into_iter().map(|v| v.into()).collect();
                 Value::List(values)
             }
             ParserValueKind::Vector(values) => {
                 let values: Vec<Value> = values.into_iter().map(|v| v.into()).collect();
-                Value::Vector(values)
+                Value::Vector(Rc::new(RefCell::new(values)))
             }
         }
     }
@@ -287,14 +358,15 @@ impl<'a> Parser<'a> {
                     span: begin.to(&end),
                 })
             }
-        } else if self.eat(&TokenKind::Quote) {
-            // treat as (quote next_value)
-            let quote_span = self.prev_token.span;
+        } else if let Some(form) = quote_form(&self.token.kind) {
+            // treat as (quote next_value), (quasiquote next_value), ...
+            let quote_span = self.token.span;
+            self.next();
             let next_value = self.parse_value()?;
-            let span = next_value.span;
+            let span = quote_span.to(&next_value.span);
             Ok(ParserValue {
                 kind: ParserValueKind::List(vec![
-                    ParserValue::sym("quote".to_string(), quote_span),
+                    ParserValue::sym(form.to_string(), quote_span),
                     next_value,
                 ]),
                 span,
@@ -309,12 +381,17 @@ impl<'a> Parser<'a> {
         let str = self.token.as_str(&self.src);
         let kind = match self.token.kind {
             TokenKind::Literal(literal) => match literal {
-                LiteralKind::Float => {
-                    ParserValueKind::Value(Value::Float(str.parse::<f64>().expect("invalid float")))
+                LiteralKind::Integer | LiteralKind::Rational | LiteralKind::Float => {
+                    match Number::parse(str) {
+                        Some(n) => ParserValueKind::Value(n.into_value().unwrap()),
+                        None => {
+                            return Err(ParseError::new(
+                                format!("Invalid number literal `{str}`"),
+                                Some(self.token.span),
+                            ))
+                        }
+                    }
                 }
-                LiteralKind::Integer => ParserValueKind::Value(Value::Integer(
-                    str.parse::<i64>().expect("invalid integer"),
-                )),
                 LiteralKind::String => {
                     ParserValueKind::Value(Value::String(str.trim_matches('"').to_string()))
                 }
@@ -374,11 +451,20 @@ impl<'a> Parser<'a> {
     }
 
     pub fn parse_stmt(&mut self) -> Result<Option<Statement>> {
-        if self.token.is_eof() {
-            return Ok(None);
+        while !self.token.is_eof() {
+            let value = self.parse_value()?;
+            if let Some(stmt) = self.lower_stmt(value)? {
+                return Ok(Some(stmt));
+            }
         }
+        Ok(None)
+    }
 
-        let value = self.parse_value()?;
+    // lowers a top-level form, which is only a statement if it doesn't define a macro
+    fn lower_stmt(&mut self, value: ParserValue) -> Result<Option<Statement>> {
+        if let Some(mac) = self.macro_of(&value) {
+            return self.expand(mac, &value, Self::lower_stmt);
+        }
 
         if let ParserValueKind::List(ref values) = value.kind {
             if values.is_empty() {
@@ -392,51 +478,93 @@ impl<'a> Parser<'a> {
             let args = &values[1..];
 
             if let ParserValueKind::Symbol(ref sym) = head.kind {
-                match sym.as_str() {
-                    keyword::Define => {
+                match base_name(sym) {
+                    keyword::DefineSyntax => {
                         if args.len() != 2 {
                             return Err(ParseError::new(
-                                "define statements must have 2 sub-components: an identifier and a body",
+                                "define-syntax statements must have 2 sub-components: a name and a syntax-rules form",
                                 Some(value.span),
                             ));
                         }
-
-                        let name = match &args[0].kind {
-                            ParserValueKind::Symbol(sym) => sym.clone(),
-                            _ => return Err(ParseError::new(
-                                "the first component of a define statement must be an identifier",
+                        let ParserValueKind::Symbol(name) = &args[0].kind else {
+                            return Err(ParseError::new(
+                                "the first component of a define-syntax statement must be an identifier",
                                 Some(args[0].span),
-                            )),
-                        };
-                        let body = match value.kind {
-                            ParserValueKind::List(values) => self.lower(
-                                values
-                                    .into_iter()
-                                    .nth(2)
-                                    .expect("define statement must have a body"),
-                            )?,
-                            _ => todo!(),
+                            ));
                         };
+                        if keyword::RESERVED_WORDS.contains(&base_name(name)) {
+                            return Err(ParseError::new(
+                                format!(
+                                    "\"{}\" is a reserved word and cannot be used as an identifier",
+                                    base_name(name)
+                                ),
+                                Some(args[0].span),
+                            ));
+                        }
+                        let origin = self.files.and_then(Files::current);
+                        let mac = Macro::parse(name.clone(), &args[1], origin)?;
+                        self.macros.insert(name.clone(), Rc::new(mac));
+                        self.defined_macros.push(name.clone());
+                        return Ok(None);
+                    }
+                    keyword::Define => {
+                        let (name, body) = self.parse_define(args, value.span)?;
+                        let body = self.lower(body)?;
                         return Ok(Some(Statement::binding(name, body, value.span)));
                     }
                     keyword::Import => {
-                        if args.len() != 1 {
+                        if args.is_empty() {
                             return Err(ParseError::new(
-                                "import statements must have 1 argument: the name of a module",
+                                "import statements must have at least 1 argument: the name of a module",
                                 Some(value.span),
                             ));
                         }
 
-                        let name = match &args[0].kind {
-                            ParserValueKind::Symbol(sym) => sym.clone(),
+                        let source = match &args[0].kind {
+                            ParserValueKind::Symbol(sym) => ImportSource::Module(sym.clone()),
+                            // files are imported by the path they have from
+                            // the program, wherever the import is
+                            ParserValueKind::Value(Value::String(path)) => {
+                                ImportSource::File(match self.files {
+                                    Some(files) => files.locate(path),
+                                    None => path.clone(),
+                                })
+                            }
                             _ => {
                                 return Err(ParseError::new(
-                                    "the argument of an import statement must be a module name",
+                                    "the argument of an import statement must be a module name or a file path",
                                     Some(args[0].span),
                                 ))
                             }
                         };
-                        return Ok(Some(Statement::import(name, value.span)));
+                        let mut filter = parse_import_filter(&args[1..])?;
+                        if let (ImportSource::File(path), Some(files)) = (&source, self.files) {
+                            files.prepare(path, value.span)?;
+                            let macros = files.macros(path);
+                            for (name, mac) in &macros {
+                                if let Some(name) = filter.rename(name) {
+                                    self.macros.insert(name, Rc::clone(mac));
+                                }
+                            }
+                            // the bindings left to import are the values
+                            if let Some(only) = &mut filter.only {
+                                only.retain(|name| !macros.contains_key(name));
+                            }
+                        }
+                        return Ok(Some(Statement::import(source, filter, value.span)));
+                    }
+                    keyword::Provide => {
+                        let names = args
+                            .iter()
+                            .map(|arg| match &arg.kind {
+                                ParserValueKind::Symbol(sym) => Ok(sym.clone()),
+                                _ => Err(ParseError::new(
+                                    "the arguments of a provide statement must be identifiers",
+                                    Some(arg.span),
+                                )),
+                            })
+                            .collect::<Result<Vec<String>>>()?;
+                        return Ok(Some(Statement::provide(names, value.span)));
                     }
                     keyword::Display => {
                         if args.len() != 1 {
@@ -482,11 +610,17 @@ impl<'a> Parser<'a> {
     }
 
     pub fn lower(&mut self, value: ParserValue) -> Result<Vec<Operation>> {
+        if let Some(mac) = self.macro_of(&value) {
+            return self.expand(mac, &value, Self::lower);
+        }
+
         match value.kind {
             ParserValueKind::Value(val) => Ok(vec![Operation::value(val, value.span)]),
             ParserValueKind::Symbol(sym) => Ok(vec![Operation::var(sym, value.span)]),
             ParserValueKind::Vector(vec) => Ok(vec![Operation::value(
-                Value::Vector(vec.into_iter().map(|v| v.into()).collect()),
+                Value::Vector(Rc::new(RefCell::new(
+                    vec.into_iter().map(|v| v.into()).collect(),
+                ))),
                 value.span,
             )]),
 
@@ -500,7 +634,7 @@ impl<'a> Parser<'a> {
 
                 // handle special forms (if, let, etc.)
                 if let ParserValueKind::Symbol(sym) = &head.kind {
-                    let form = sym.as_ref();
+                    let form = base_name(sym);
                     if SPECIAL_FORMS.contains(&form) {
                         let args = &values[1..];
                         return self.handle_special_form(form, args, value.span);
@@ -532,6 +666,7 @@ impl<'a> Parser<'a> {
             keyword::Lambda => self.parse_lambda(args, span),
             keyword::Let => self.parse_let(args, span),
             keyword::LetStar => self.parse_let_star(args, span),
+            keyword::LetRec => self.parse_letrec(args, span),
             keyword::And => self.parse_and(args, span),
             keyword::Or => self.parse_or(args, span),
             keyword::If => self.parse_if(args, span),
@@ -539,53 +674,118 @@ impl<'a> Parser<'a> {
             keyword::Match => self.parse_match(args, span),
             keyword::Cond => self.parse_cond(args, span),
             keyword::Quote => self.parse_quote(args, span),
+            keyword::Quasiquote => self.parse_quasiquote(args, span),
+            keyword::Unquote | keyword::UnquoteSplicing => Err(ParseError::new(
+                format!("{form} must be used inside of a quasiquote"),
+                Some(span),
+            )),
             keyword::Section => self.parse_section(args, span),
+            keyword::Set => self.parse_set(args, span),
             _ => todo!(),
         }
     }
 
     pub fn parse_lambda(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
-        if args.len() != 2 {
+        if args.len() < 2 {
             return Err(ParseError::new(
                 "lambda expression must have 2 sub-components: a parameter list and a body",
                 Some(span),
             ));
         }
 
-        let ParserValueKind::List(lmbda_args) = &args[0].kind else {
-            return Err(ParseError::new(
-                "the first component of a lambda expression must be a parameter list",
-                Some(args[0].span),
-            ));
-        };
-
-        let mut params = vec![];
-        for arg in lmbda_args {
-            if let ParserValueKind::Symbol(sym) = &arg.kind {
-                params.push(sym.clone());
-            } else {
-                return Err(ParseError::new(
-                    "parameters must only be identifiers",
-                    Some(arg.span),
-                ));
-            }
-        }
+        let params = self.parse_params(&args[0])?;
 
         Ok(vec![Operation::closure(
             params,
-            self.lower(args[1].clone())?,
+            self.parse_body(&args[1..], span)?,
             span,
         )])
     }
 
+    // parses the parameters of a lambda: `(a b)`, `(a [b default] . rest)` or
+    // `args`, which collects all the arguments in a list
+    fn parse_params(&mut self, value: &ParserValue) -> Result<Params> {
+        let items = match &value.kind {
+            ParserValueKind::Symbol(rest) => {
+                return Ok(Params {
+                    names: vec![rest.clone()],
+                    required: 0,
+                    defaults: Vec::new(),
+                    rest: true,
+                })
+            }
+            ParserValueKind::List(items) => items,
+            _ => {
+                return Err(ParseError::new(
+                    "the first component of a lambda expression must be a parameter list",
+                    Some(value.span),
+                ))
+            }
+        };
+
+        let mut params = Params::default();
+        let mut items = items.iter();
+        while let Some(item) = items.next() {
+            match &item.kind {
+                ParserValueKind::Symbol(dot) if dot == "." => {
+                    let (Some(rest), None) = (items.next(), items.next()) else {
+                        return Err(ParseError::new(
+                            "a `.` in a parameter list must be followed by exactly one parameter",
+                            Some(item.span),
+                        ));
+                    };
+                    let ParserValueKind::Symbol(rest) = &rest.kind else {
+                        return Err(ParseError::new(
+                            "parameters must only be identifiers",
+                            Some(rest.span),
+                        ));
+                    };
+                    params.names.push(rest.clone());
+                    params.rest = true;
+                }
+                ParserValueKind::Symbol(name) => {
+                    if !params.defaults.is_empty() {
+                        return Err(ParseError::new(
+                            "required parameters must come before optional ones",
+                            Some(item.span),
+                        ));
+                    }
+                    params.names.push(name.clone());
+                    params.required += 1;
+                }
+                ParserValueKind::Vector(_) => {
+                    let (name, default) = binding_pair(item).map_err(|_| {
+                        ParseError::new(
+                            "optional parameters must be given as a pair of a name and a default value",
+                            Some(item.span),
+                        )
+                    })?;
+                    params.names.push(name);
+                    params.defaults.push(self.lower(default)?);
+                }
+                _ => {
+                    return Err(ParseError::new(
+                        "parameters must only be identifiers",
+                        Some(item.span),
+                    ))
+                }
+            }
+        }
+        Ok(params)
+    }
+
     pub fn parse_let(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
-        if args.len() != 2 {
+        if args.len() < 2 {
             return Err(ParseError::new(
                 "let expression must have 2 sub-components: a binding list and a body",
                 Some(self.token.span),
             ));
         }
 
+        if let ParserValueKind::Symbol(name) = &args[0].kind {
+            return self.parse_named_let(name, &args[1..], span);
+        }
+
         let ParserValueKind::List(bindings) = &args[0].kind else {
             return Err(ParseError::new(
                 "let expression bindings must be given as a list",
@@ -608,13 +808,67 @@ impl<'a> Parser<'a> {
             .flat_map(|(_, ops)| ops)
             .collect::<Vec<_>>();
 
-        ops.push(Operation::let_(names, self.lower(args[1].clone())?, span));
+        ops.push(Operation::let_(
+            names,
+            self.parse_body(&args[1..], span)?,
+            span,
+        ));
 
         Ok(ops)
     }
 
+    // `(let loop ([x e] ...) body ...)` calls a local function `loop` that
+    // takes the bindings as parameters, with their values as initial arguments
+    fn parse_named_let(
+        &mut self,
+        name: &str,
+        args: &[ParserValue],
+        span: Span,
+    ) -> Result<Vec<Operation>> {
+        if args.len() < 2 {
+            return Err(ParseError::new(
+                "named let expression must have 3 sub-components: a name, a binding list and a body",
+                Some(span),
+            ));
+        }
+
+        let ParserValueKind::List(bindings) = &args[0].kind else {
+            return Err(ParseError::new(
+                "let expression bindings must be given as a list",
+                Some(args[0].span),
+            ));
+        };
+        let bindings = bindings
+            .iter()
+            .map(binding_pair)
+            .collect::<Result<Vec<_>>>()?;
+
+        let (params, values): (Vec<_>, Vec<_>) = bindings
+            .into_iter()
+            .map(|(param, value)| (ParserValue::sym(param, span), value))
+            .unzip();
+        let mut lambda = vec![
+            ParserValue::sym(keyword::Lambda.to_string(), span),
+            ParserValue::list(params, args[0].span),
+        ];
+        lambda.extend(args[1..].iter().cloned());
+
+        let mut ops = self.lower_letrec(
+            vec![(name.to_string(), ParserValue::list(lambda, span))],
+            vec![Operation::var(name.to_string(), span)],
+            span,
+        )?;
+        // the initial values are evaluated outside the scope of the function
+        let arity = values.len() as u32;
+        for value in values {
+            ops.extend(self.lower(value)?);
+        }
+        ops.push(Operation::ap(arity, span));
+        Ok(ops)
+    }
+
     fn parse_let_star(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
-        if args.len() != 2 {
+        if args.len() < 2 {
             return Err(ParseError::new(
                 "let* expression must have 2 sub-components: a binding list and a body",
                 Some(self.token.span),
@@ -635,14 +889,12 @@ impl<'a> Parser<'a> {
             ));
         };
 
-        let mut value = ParserValue::list(
-            vec![
-                ParserValue::sym(String::from("let"), span),
-                ParserValue::list(vec![bindings.last().unwrap().clone()], span),
-                args[1].clone(),
-            ],
-            span,
-        );
+        let mut innermost = vec![
+            ParserValue::sym(String::from("let"), span),
+            ParserValue::list(vec![bindings.last().unwrap().clone()], span),
+        ];
+        innermost.extend(args[1..].iter().cloned());
+        let mut value = ParserValue::list(innermost, span);
         for binding in bindings.iter().rev().skip(1) {
             value = ParserValue::list(
                 vec![
@@ -657,70 +909,201 @@ impl<'a> Parser<'a> {
         self.lower(value)
     }
 
-    fn parse_binding(&mut self, value: &ParserValue) -> Result<(String, Vec<Operation>)> {
-        let ParserValueKind::Vector(binding) = &value.kind else {
+    pub fn parse_letrec(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
+        if args.len() < 2 {
             return Err(ParseError::new(
-                "binding pair must be given as a vector",
-                Some(value.span),
+                "letrec expression must have 2 sub-components: a binding list and a body",
+                Some(span),
+            ));
+        }
+
+        let ParserValueKind::List(bindings) = &args[0].kind else {
+            return Err(ParseError::new(
+                "letrec expression bindings must be given as a list",
+                Some(args[0].span),
             ));
         };
 
-        if binding.len() != 2 {
+        let bindings = bindings
+            .iter()
+            .map(binding_pair)
+            .collect::<Result<Vec<_>>>()?;
+        let body = self.parse_body(&args[1..], span)?;
+        self.lower_letrec(bindings, body, span)
+    }
+
+    // binds `names` in a new frame before evaluating their values in order, so
+    // that the values can refer to each other (like letrec*)
+    fn lower_letrec(
+        &mut self,
+        bindings: Vec<(String, ParserValue)>,
+        body: Vec<Operation>,
+        span: Span,
+    ) -> Result<Vec<Operation>> {
+        let names = bindings
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        let mut ops = vec![Operation::value(Value::Void, span); names.len()];
+        let mut inner = Vec::new();
+        for (name, value) in bindings {
+            let value_span = value.span;
+            inner.extend(self.lower(value)?);
+            inner.push(Operation::set(name, value_span));
+            inner.push(Operation::pop(span));
+        }
+        inner.extend(body);
+        ops.push(Operation::let_(names, inner, span));
+        Ok(ops)
+    }
+
+    // splits the arguments of a define into the name it binds and the
+    // expression of its value, which is a lambda for `(define (f x ...) body)`
+    fn parse_define(&mut self, args: &[ParserValue], span: Span) -> Result<(String, ParserValue)> {
+        let Some((target, body)) = args.split_first() else {
             return Err(ParseError::new(
-                "binding must be a pair of a name and value",
-                Some(value.span),
+                "define statements must have 2 sub-components: an identifier and a body",
+                Some(span),
             ));
+        };
+        match &target.kind {
+            ParserValueKind::Symbol(name) => {
+                if body.len() != 1 {
+                    return Err(ParseError::new(
+                        "define statements must have 2 sub-components: an identifier and a body",
+                        Some(span),
+                    ));
+                }
+                Ok((name.clone(), body[0].clone()))
+            }
+            ParserValueKind::List(signature) if !signature.is_empty() => {
+                let ParserValueKind::Symbol(name) = &signature[0].kind else {
+                    return Err(ParseError::new(
+                        "the name of a function definition must be an identifier",
+                        Some(signature[0].span),
+                    ));
+                };
+                if body.is_empty() {
+                    return Err(ParseError::new(
+                        "function definitions must have a body",
+                        Some(span),
+                    ));
+                }
+                let mut lambda = vec![
+                    ParserValue::sym(keyword::Lambda.to_string(), target.span),
+                    ParserValue::list(signature[1..].to_vec(), target.span),
+                ];
+                lambda.extend(body.iter().cloned());
+                Ok((name.clone(), ParserValue::list(lambda, span)))
+            }
+            _ => Err(ParseError::new(
+                "the first component of a define statement must be an identifier or a function header",
+                Some(target.span),
+            )),
         }
+    }
 
-        let name = match &binding[0].kind {
-            ParserValueKind::Symbol(sym) => sym.clone(),
-            _ => {
+    // lowers the body of a lambda, let or branch: definitions, which are bound
+    // like letrec* around the rest of the body, followed by expressions
+    fn parse_body(&mut self, forms: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
+        let mut definitions: Vec<(String, ParserValue)> = Vec::new();
+        let mut expressions = Vec::new();
+        for form in forms {
+            // macro uses may expand into definitions
+            let mut form = form.clone();
+            let mut expansions = 0;
+            while let Some(mac) = self.macro_of(&form) {
+                if expansions >= MAX_EXPANSION_DEPTH {
+                    return Err(ParseError::new(
+                        "macro expansion is too deeply nested",
+                        Some(form.span),
+                    ));
+                }
+                self.mark_counter += 1;
+                form = mac.expand(&form, self.mark_counter)?;
+                expansions += 1;
+            }
+
+            let ParserValueKind::List(items) = &form.kind else {
+                expressions.push(form);
+                continue;
+            };
+            let is_define = matches!(
+                items.first().map(|head| &head.kind),
+                Some(ParserValueKind::Symbol(head)) if base_name(head) == keyword::Define
+            );
+            if !is_define {
+                expressions.push(form);
+                continue;
+            }
+
+            if !expressions.is_empty() {
                 return Err(ParseError::new(
-                    "the first component of a binding must be a symbol",
-                    Some(value.span),
-                ))
+                    "definitions must come before the expressions of a body",
+                    Some(form.span),
+                ));
             }
-        };
+            let (name, value) = self.parse_define(&items[1..], form.span)?;
+            if definitions.iter().any(|(defined, _)| *defined == name) {
+                return Err(ParseError::new(
+                    format!(
+                        "`{}` is defined more than once in the same body",
+                        base_name(&name)
+                    ),
+                    Some(form.span),
+                ));
+            }
+            definitions.push((name, value));
+        }
+
+        if expressions.is_empty() {
+            return Err(ParseError::new(
+                "a body must end with an expression",
+                Some(span),
+            ));
+        }
+        // the expressions of a body run in sequence, like a begin
+        let body = self.parse_begin(&expressions, span)?;
+        if definitions.is_empty() {
+            return Ok(body);
+        }
+        self.lower_letrec(definitions, body, span)
+    }
 
-        Ok((name, self.lower(binding[1].clone())?))
+    fn parse_binding(&mut self, value: &ParserValue) -> Result<(String, Vec<Operation>)> {
+        let (name, value) = binding_pair(value)?;
+        Ok((name, self.lower(value)?))
     }
 
+    // the last operand is left unchecked so that it stays in tail position
     pub fn parse_and(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
+        let Some((last, rest)) = args.split_last() else {
+            return Ok(vec![Operation::value(Value::Boolean(true), span)]);
+        };
         let label = self.fresh_label();
-        Ok(args
-            .iter()
-            .map(|arg| self.lower(arg.clone()))
-            .collect::<Result<Vec<_>>>()?
-            .into_iter()
-            .flat_map(|ops| {
-                let mut ops = ops;
-                ops.push(Operation::and(label.clone(), span));
-                ops
-            })
-            .chain(vec![
-                Operation::value(Value::Boolean(true), span),
-                Operation::label(label.clone()),
-            ])
-            .collect())
+        let mut ops = Vec::new();
+        for arg in rest {
+            ops.extend(self.lower(arg.clone())?);
+            ops.push(Operation::and(label.clone(), span));
+        }
+        ops.extend(self.lower(last.clone())?);
+        ops.push(Operation::label(label));
+        Ok(ops)
     }
 
     pub fn parse_or(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
+        let Some((last, rest)) = args.split_last() else {
+            return Ok(vec![Operation::value(Value::Boolean(false), span)]);
+        };
         let label = self.fresh_label();
-        Ok(args
-            .iter()
-            .map(|arg| self.lower(arg.clone()))
-            .collect::<Result<Vec<_>>>()?
-            .into_iter()
-            .flat_map(|ops| {
-                let mut ops = ops;
-                ops.push(Operation::or(label.clone(), span));
-                ops
-            })
-            .chain(vec![
-                Operation::value(Value::Boolean(false), span),
-                Operation::label(label.clone()),
-            ])
-            .collect())
+        let mut ops = Vec::new();
+        for arg in rest {
+            ops.extend(self.lower(arg.clone())?);
+            ops.push(Operation::or(label.clone(), span));
+        }
+        ops.extend(self.lower(last.clone())?);
+        ops.push(Operation::label(label));
+        Ok(ops)
     }
 
     pub fn parse_if(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
@@ -748,14 +1131,35 @@ impl<'a> Parser<'a> {
             ));
         }
 
-        let mut ops = args
-            .iter()
-            .map(|arg| self.lower(arg.clone()))
-            .collect::<Result<Vec<_>>>()?
-            .into_iter()
-            .flat_map(|ops| ops)
-            .collect::<Vec<_>>();
-        ops.push(Operation::seq(args.len(), span));
+        // discard the value of every sub-expression but the last
+        let mut ops = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                ops.push(Operation::pop(span));
+            }
+            ops.extend(self.lower(arg.clone())?);
+        }
+        Ok(ops)
+    }
+
+    pub fn parse_set(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
+        if args.len() != 2 {
+            return Err(ParseError::new(
+                "set! expression must have 2 sub-components: an identifier and a value",
+                Some(span),
+            ));
+        }
+
+        let ParserValueKind::Symbol(name) = &args[0].kind else {
+            return Err(ParseError::new(
+                "the first component of a set! expression must be an identifier",
+                Some(args[0].span),
+            ));
+        };
+
+        let mut ops = self.lower(args[1].clone())?;
+        // errors about the binding point at the identifier
+        ops.push(Operation::set(name.clone(), args[0].span));
         Ok(ops)
     }
 
@@ -775,7 +1179,7 @@ impl<'a> Parser<'a> {
             .collect::<Result<Vec<_>>>()?;
 
         let mut ops = scrutinee;
-        ops.push(Operation::match_(branches, span));
+        ops.push(Operation::match_(branches, args[0].span, span));
         Ok(ops)
     }
 
@@ -786,13 +1190,141 @@ impl<'a> Parser<'a> {
                 Some(value.span),
             ));
         };
-        if branch.len() != 2 {
+        if branch.len() < 2 {
             return Err(ParseError::new(
-                "match branches must be given as a pair of a pattern and an expression",
+                "match branches must be given as a pattern followed by a body",
                 Some(value.span),
             ));
         }
-        Ok((branch[0].clone().into(), self.lower(branch[1].clone())?))
+        let mut variables = Vec::new();
+        let mut predicates = Vec::new();
+        let pattern = self.parse_pattern(&branch[0], &mut variables, &mut predicates)?;
+        if let Some(slot) = pattern.repeated_variable() {
+            return Err(ParseError::new(
+                format!(
+                    "pattern binds `{}` more than once",
+                    base_name(&variables[slot])
+                ),
+                Some(branch[0].span),
+            ));
+        }
+        Ok(MatchBranch {
+            pattern,
+            variables,
+            predicates,
+            body: self.parse_body(&branch[1..], value.span)?,
+        })
+    }
+
+    // parses a pattern, numbering the variables it binds by their position in
+    // `variables` and lowering the predicates of `?` patterns into `predicates`
+    fn parse_pattern(
+        &mut self,
+        value: &ParserValue,
+        variables: &mut Vec<String>,
+        predicates: &mut Vec<Block>,
+    ) -> Result<Pattern> {
+        let items = match &value.kind {
+            ParserValueKind::Value(value) => return Ok(Pattern::Literal(value.clone())),
+            ParserValueKind::Symbol(name) => {
+                return Ok(match base_name(name) {
+                    "_" => Pattern::Wildcard,
+                    "null" => Pattern::List(Vec::new()),
+                    _ => {
+                        let slot = match variables.iter().position(|v| v == name) {
+                            Some(slot) => slot,
+                            None => {
+                                variables.push(name.clone());
+                                variables.len() - 1
+                            }
+                        };
+                        Pattern::Variable(slot)
+                    }
+                })
+            }
+            ParserValueKind::List(items) => items,
+            ParserValueKind::Vector(_) => {
+                return Err(ParseError::new(
+                    "patterns must be written with parentheses",
+                    Some(value.span),
+                ))
+            }
+        };
+
+        let Some((head, args)) = items.split_first() else {
+            return Ok(Pattern::List(Vec::new()));
+        };
+        let ParserValueKind::Symbol(head) = &head.kind else {
+            return Err(ParseError::new(
+                "pattern must begin with the name of a constructor",
+                Some(head.span),
+            ));
+        };
+        let mut sub_patterns = |parser: &mut Self, args: &[ParserValue]| {
+            args.iter()
+                .map(|arg| parser.parse_pattern(arg, variables, predicates))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        match base_name(head) {
+            "quote" if args.len() == 1 => Ok(Pattern::Literal(args[0].clone().into())),
+            "cons" | "pair" => {
+                if args.len() != 2 {
+                    return Err(ParseError::new(
+                        format!("{head} pattern must have a head and a tail pattern"),
+                        Some(value.span),
+                    ));
+                }
+                let mut patterns = sub_patterns(self, args)?.into_iter();
+                let head = patterns.next().unwrap();
+                let tail = patterns.next().unwrap();
+                Ok(Pattern::Cons(Box::new(head), Box::new(tail)))
+            }
+            "list" => Ok(Pattern::List(sub_patterns(self, args)?)),
+            "vector" => Ok(Pattern::Vector(sub_patterns(self, args)?)),
+            "and" => Ok(Pattern::And(sub_patterns(self, args)?)),
+            "?" => {
+                let Some((predicate, args)) = args.split_first() else {
+                    return Err(ParseError::new(
+                        "? pattern must have a predicate",
+                        Some(value.span),
+                    ));
+                };
+                let predicate_ops = self.lower(predicate.clone())?;
+                let mut patterns = Vec::new();
+                for arg in args {
+                    patterns.push(self.parse_pattern(arg, variables, predicates)?);
+                }
+                predicates.push(predicate_ops);
+                Ok(Pattern::Predicate {
+                    predicate: predicates.len() - 1,
+                    patterns,
+                })
+            }
+            "or" => {
+                let alternatives = sub_patterns(self, args)?;
+                // the branch body can use any variable, so every alternative
+                // has to bind all of them
+                let mut bound = alternatives.iter().map(|alternative| {
+                    let mut slots = BTreeSet::new();
+                    alternative.variables(&mut slots);
+                    slots
+                });
+                if let Some(first) = bound.next() {
+                    if bound.any(|slots| slots != first) {
+                        return Err(ParseError::new(
+                            "every alternative of an or pattern must bind the same variables",
+                            Some(value.span),
+                        ));
+                    }
+                }
+                Ok(Pattern::Or(alternatives))
+            }
+            _ => Ok(Pattern::Struct {
+                kind: base_name(head).to_string(),
+                fields: sub_patterns(self, args)?,
+            }),
+        }
     }
 
     pub fn parse_cond(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
@@ -839,16 +1371,16 @@ impl<'a> Parser<'a> {
             ));
         };
 
-        if branch.len() != 2 {
+        if branch.len() < 2 {
             return Err(ParseError::new(
-                "cond branch must be a pair of expressions",
+                "cond branch must be given as a condition followed by a body",
                 Some(value.span),
             ));
         }
 
         Ok((
             self.lower(branch[0].clone())?,
-            self.lower(branch[1].clone())?,
+            self.parse_body(&branch[1..], value.span)?,
         ))
     }
 
@@ -862,6 +1394,93 @@ impl<'a> Parser<'a> {
         Ok(vec![Operation::value(args[0].clone().into(), span)])
     }
 
+    pub fn parse_quasiquote(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
+        if args.len() != 1 {
+            return Err(ParseError::new(
+                "quasiquote expression must have exactly one sub-expression",
+                Some(span),
+            ));
+        }
+        self.quasiquote(&args[0], 1)
+    }
+
+    // lowers a quasiquoted template into operations that build it, where
+    // `depth` is the number of quasiquotes the template is nested in. Only
+    // unquotes at depth 1 are evaluated; parts of the template without them
+    // are constants, like in a quote.
+    fn quasiquote(&mut self, template: &ParserValue, depth: usize) -> Result<Vec<Operation>> {
+        if !has_unquote(template, depth) {
+            return Ok(vec![Operation::value(
+                template.clone().into(),
+                template.span,
+            )]);
+        }
+        let items = match &template.kind {
+            ParserValueKind::List(items) => items,
+            ParserValueKind::Vector(items) => {
+                let mut ops = vec![Operation::var(top_level("list->vector"), template.span)];
+                ops.extend(self.quasiquote_list(items, depth, template.span)?);
+                ops.push(Operation::ap(1, template.span));
+                return Ok(ops);
+            }
+            _ => unreachable!("only lists and vectors contain unquotes"),
+        };
+
+        match unquote_form(items) {
+            Some((keyword::Unquote, expr)) if depth == 1 => self.lower(expr.clone()),
+            Some((keyword::UnquoteSplicing, _)) if depth == 1 => Err(ParseError::new(
+                "unquote-splicing must be used inside of a list",
+                Some(template.span),
+            )),
+            // nested quasiquotes keep their unquotes, evaluating only those
+            // that belong to the outermost one
+            Some((form, inner)) => {
+                let depth = match form {
+                    keyword::Quasiquote => depth + 1,
+                    _ => depth - 1,
+                };
+                let mut ops = vec![
+                    Operation::var(top_level("list"), template.span),
+                    Operation::value(Value::Symbol(form.to_string()), items[0].span),
+                ];
+                ops.extend(self.quasiquote(inner, depth)?);
+                ops.push(Operation::ap(2, template.span));
+                Ok(ops)
+            }
+            None => self.quasiquote_list(items, depth, template.span),
+        }
+    }
+
+    // builds the list of quasiquoted items from the right, consing each item
+    // onto the rest and appending spliced lists
+    fn quasiquote_list(
+        &mut self,
+        items: &[ParserValue],
+        depth: usize,
+        span: Span,
+    ) -> Result<Vec<Operation>> {
+        let mut ops = vec![Operation::value(Value::Null, span)];
+        for item in items.iter().rev() {
+            let spliced = match &item.kind {
+                ParserValueKind::List(forms) if depth == 1 => match unquote_form(forms) {
+                    Some((keyword::UnquoteSplicing, expr)) => Some(expr),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let (function, item_ops) = match spliced {
+                Some(expr) => ("append", self.lower(expr.clone())?),
+                None => ("cons", self.quasiquote(item, depth)?),
+            };
+            let mut built = vec![Operation::var(top_level(function), item.span)];
+            built.extend(item_ops);
+            built.extend(ops);
+            built.push(Operation::ap(2, item.span));
+            ops = built;
+        }
+        Ok(ops)
+    }
+
     pub fn parse_section(&mut self, args: &[ParserValue], span: Span) -> Result<Vec<Operation>> {
         if args.is_empty() {
             return Err(ParseError::new(
@@ -899,7 +1518,7 @@ impl<'a> Parser<'a> {
         v: ParserValue,
     ) -> Result<ParserValue> {
         match v.kind {
-            ParserValueKind::Symbol(s) if s == "_" => {
+            ParserValueKind::Symbol(s) if base_name(&s) == "_" => {
                 let x = self.fresh_hole();
                 bvars.push(x.clone());
                 Ok(ParserValue::sym(x, v.span))
@@ -908,7 +1527,7 @@ impl<'a> Parser<'a> {
             ParserValueKind::List(ref values) => {
                 let head = values[0].clone();
                 if let ParserValueKind::Symbol(s) = head.kind {
-                    if s == "section" {
+                    if base_name(&s) == keyword::Section {
                         return Ok(v);
                     }
                 }
@@ -939,9 +1558,205 @@ impl<'a> Parser<'a> {
     }
 }
 
+// parses the `:prefix` and `:only` options that follow the module of an import
+fn parse_import_filter(options: &[ParserValue]) -> Result<ImportFilter> {
+    let mut filter = ImportFilter::default();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let ParserValueKind::Symbol(name) = &option.kind else {
+            return Err(ParseError::new(
+                "expected an import option, :prefix or :only",
+                Some(option.span),
+            ));
+        };
+        let Some(arg) = options.next() else {
+            return Err(ParseError::new(
+                format!("the import option {name} must be followed by an argument"),
+                Some(option.span),
+            ));
+        };
+        match name.as_str() {
+            ":prefix" if filter.prefix.is_none() => {
+                let ParserValueKind::Symbol(prefix) = &arg.kind else {
+                    return Err(ParseError::new(
+                        "the argument of :prefix must be an identifier",
+                        Some(arg.span),
+                    ));
+                };
+                filter.prefix = Some(prefix.clone());
+            }
+            ":only" if filter.only.is_none() => {
+                let ParserValueKind::List(names) = &arg.kind else {
+                    return Err(ParseError::new(
+                        "the argument of :only must be a list of identifiers",
+                        Some(arg.span),
+                    ));
+                };
+                let names = names
+                    .iter()
+                    .map(|name| match &name.kind {
+                        ParserValueKind::Symbol(sym) => Ok(sym.clone()),
+                        _ => Err(ParseError::new(
+                            "the argument of :only must be a list of identifiers",
+                            Some(name.span),
+                        )),
+                    })
+                    .collect::<Result<Vec<String>>>()?;
+                filter.only = Some(names);
+            }
+            ":prefix" | ":only" => {
+                return Err(ParseError::new(
+                    format!("the import option {name} is given more than once"),
+                    Some(option.span),
+                ))
+            }
+            _ => {
+                return Err(ParseError::new(
+                    format!("unknown import option {name}, expected :prefix or :only"),
+                    Some(option.span),
+                ))
+            }
+        }
+    }
+    Ok(filter)
+}
+
 // parses a list of tokens into an ast struct
 pub fn parse(src: &str) -> Result<Ast> {
     let mut parser = Parser::new_raw(src)?;
     let statements = parser.parse_statements()?;
     Ok(Ast { statements })
 }
+
+/// A program parsed by [`parse_with`].
+pub struct Parsed {
+    pub ast: Ast,
+    /// The macros in scope at the end of the program.
+    pub macros: Macros,
+    /// Names of the macros the program itself defines.
+    pub defined_macros: Vec<String>,
+}
+
+/// Parses a program that can use `macros` as well as those of the files it
+/// imports through `files`.
+pub fn parse_with(src: &str, macros: Macros, files: &Files) -> Result<Parsed> {
+    let mut parser = Parser::new_raw(src)?;
+    parser.macros = macros;
+    parser.files = Some(files);
+    let statements = parser.parse_statements()?;
+    Ok(Parsed {
+        ast: Ast { statements },
+        macros: parser.macros,
+        defined_macros: parser.defined_macros,
+    })
+}
+
+/// Returns the paths of the files a program imports, or none if it doesn't
+/// parse. Used to fetch imported files ahead of running a program.
+pub fn file_imports(src: &str) -> Vec<String> {
+    let Ok(ast) = parse(src) else {
+        return Vec::new();
+    };
+    ast.statements
+        .into_iter()
+        .filter_map(|stmt| match stmt.kind {
+            StatementKind::Import {
+                source: ImportSource::File(path),
+                ..
+            } => Some(path),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the paths a program passes to `load-image` as string literals, or
+/// none if it doesn't lex. Used to fetch images ahead of running a program.
+pub fn loaded_images(src: &str) -> Vec<String> {
+    let Ok(tokens) = crate::lexer::tokenize(src) else {
+        return Vec::new();
+    };
+    tokens
+        .windows(2)
+        .filter_map(|pair| match (&pair[0].kind, &pair[1].kind) {
+            (TokenKind::Sequence, TokenKind::Literal(LiteralKind::String))
+                if pair[0].as_str(src) == "load-image" =>
+            {
+                Some(pair[1].as_str(src).trim_matches('"').to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// splits a `[name value]` binding of a let form
+fn binding_pair(value: &ParserValue) -> Result<(String, ParserValue)> {
+    let ParserValueKind::Vector(binding) = &value.kind else {
+        return Err(ParseError::new(
+            "binding pair must be given as a vector",
+            Some(value.span),
+        ));
+    };
+
+    if binding.len() != 2 {
+        return Err(ParseError::new(
+            "binding must be a pair of a name and value",
+            Some(value.span),
+        ));
+    }
+
+    let name = match &binding[0].kind {
+        ParserValueKind::Symbol(sym) => sym.clone(),
+        _ => {
+            return Err(ParseError::new(
+                "the first component of a binding must be a symbol",
+                Some(value.span),
+            ))
+        }
+    };
+
+    Ok((name, binding[1].clone()))
+}
+
+// the form that a quote prefix such as `'` or `,@` abbreviates
+fn quote_form(token: &TokenKind) -> Option<&'static str> {
+    match token {
+        TokenKind::Quote => Some(keyword::Quote),
+        TokenKind::Quasiquote => Some(keyword::Quasiquote),
+        TokenKind::Unquote => Some(keyword::Unquote),
+        TokenKind::UnquoteSplicing => Some(keyword::UnquoteSplicing),
+        _ => None,
+    }
+}
+
+// splits a `(quasiquote x)`, `(unquote x)` or `(unquote-splicing x)` form
+// into its keyword and sub-expression
+fn unquote_form(items: &[ParserValue]) -> Option<(&'static str, &ParserValue)> {
+    let [head, arg] = items else {
+        return None;
+    };
+    let ParserValueKind::Symbol(head) = &head.kind else {
+        return None;
+    };
+    let form = [
+        keyword::Quasiquote,
+        keyword::Unquote,
+        keyword::UnquoteSplicing,
+    ]
+    .into_iter()
+    .find(|&form| base_name(head) == form)?;
+    Some((form, arg))
+}
+
+// whether a template nested in `depth` quasiquotes has unquotes to evaluate
+fn has_unquote(template: &ParserValue, depth: usize) -> bool {
+    match &template.kind {
+        ParserValueKind::List(items) => match unquote_form(items) {
+            Some((keyword::Quasiquote, inner)) => has_unquote(inner, depth + 1),
+            Some(_) if depth == 1 => true,
+            Some((_, inner)) => has_unquote(inner, depth - 1),
+            None => items.iter().any(|item| has_unquote(item, depth)),
+        },
+        ParserValueKind::Vector(items) => items.iter().any(|item| has_unquote(item, depth)),
+        _ => false,
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::{HashMap, HashSet};
+
+use super::keyword::RESERVED_WORDS;
+use super::{base_name, imported_from, origin, Result};
+use crate::ast::*;
+use crate::diagnostics::ParseError;
+use crate::interpreter::{Env, Globals};
+use crate::modules::Files;
+
+enum Address {
+    Local { depth: usize, index: usize },
+    Global { index: usize },
+}
+
+/// Rewrites the variables of a program into frame/slot addresses, declaring the
+/// globals the program defines in `globals`. Files the program imports are
+/// prepared through `files`. Fails if the program refers to an identifier that
+/// isn't bound anywhere, in which case `globals` is left as it was.
+pub fn resolve(ast: Ast, globals: &mut Globals, files: &Files) -> Result<Ast> {
+    resolve_module(ast, globals, files).map(|resolution| resolution.ast)
+}
+
+/// A resolved program, along with what resolving it found out about it.
+pub(crate) struct Resolution {
+    pub ast: Ast,
+    /// The names the program makes available to files that import it.
+    pub provided: Vec<String>,
+    pub warnings: Vec<ParseError>,
+}
+
+/// Resolves a program like [`resolve`], also returning what it provides and
+/// any warnings about it.
+pub(crate) fn resolve_module(ast: Ast, globals: &mut Globals, files: &Files) -> Result<Resolution> {
+    let len = globals.len();
+    let mut resolver = Resolver {
+        module: files.current(),
+        globals,
+        files,
+        defined: HashSet::new(),
+        imported: HashMap::new(),
+        warnings: Vec::new(),
+        scopes: Vec::new(),
+    };
+    let result = resolver.resolve_program(ast);
+    if result.is_err() {
+        resolver.globals.truncate(len);
+    }
+    let (ast, provided) = result?;
+    Ok(Resolution {
+        ast,
+        provided,
+        warnings: resolver.warnings,
+    })
+}
+
+struct Resolver<'a> {
+    // the file being resolved, if it isn't the program itself
+    module: Option<String>,
+    globals: &'a mut Globals,
+    files: &'a Files,
+    // names bound by the program's own statements
+    defined: HashSet<String>,
+    // the module each name the program imports comes from
+    imported: HashMap<String, String>,
+    warnings: Vec<ParseError>,
+    // local frames that are visible, innermost last
+    scopes: Vec<Vec<String>>,
+}
+
+impl<'a> Resolver<'a> {
+    // resolves the program's statements, returning them along with the names
+    // the program provides
+    fn resolve_program(&mut self, ast: Ast) -> Result<(Ast, Vec<String>)> {
+        // names bound by the program's bindings and structs, in order
+        let mut definitions = Vec::new();
+        let mut provided: Option<Vec<(String, Span)>> = None;
+
+        // top-level definitions are visible everywhere, even before they run
+        for stmt in &ast.statements {
+            match &stmt.kind {
+                StatementKind::Binding { name, .. } => {
+                    check_identifier(name, stmt.span)?;
+                    self.define(name);
+                    definitions.push(name.clone());
+                }
+                StatementKind::Struct { id, fields } => {
+                    check_identifier(id, stmt.span)?;
+                    for field in fields {
+                        check_identifier(field, stmt.span)?;
+                    }
+                    let s = Struct {
+                        kind: id.clone(),
+                        fields: fields.clone(),
+                        values: vec![],
+                    };
+                    let mut env = Env::new();
+                    s.add_to(&mut env, None, None);
+                    for name in env.names() {
+                        self.define(name);
+                        definitions.push(name.clone());
+                    }
+                }
+                StatementKind::Import {
+                    source: ImportSource::Module(mod_name),
+                    filter,
+                } => {
+                    let Some(module) = crate::modules::load(mod_name) else {
+                        return Err(ParseError::new(
+                            format!("Module {} not found", mod_name),
+                            Some(stmt.span),
+                        ));
+                    };
+                    let bindings = module
+                        .into_iter()
+                        .map(|(name, value)| (name, Some(value)))
+                        .collect();
+                    self.import(&format!("module {mod_name}"), bindings, filter, stmt.span)?;
+                }
+                StatementKind::Import {
+                    source: ImportSource::File(path),
+                    filter,
+                } => {
+                    let names = self.files.prepare(path, stmt.span)?;
+                    // the values are known if the file already ran
+                    let values = self.files.values(path);
+                    let bindings = names
+                        .into_iter()
+                        .map(|name| {
+                            let value = values.as_ref().and_then(|env| env.get(name.as_str()));
+                            (name, value)
+                        })
+                        .collect();
+                    self.import(&format!("\"{path}\""), bindings, filter, stmt.span)?;
+                }
+                StatementKind::Provide { names } => {
+                    let provided = provided.get_or_insert_with(Vec::new);
+                    provided.extend(names.iter().map(|name| (name.clone(), stmt.span)));
+                }
+                _ => {}
+            }
+        }
+
+        // a program without provide statements provides all its definitions
+        let provided = match provided {
+            Some(provided) => provided
+                .into_iter()
+                .map(|(name, span)| match self.globals.index_of(&name) {
+                    Some(_) => Ok(name),
+                    None => Err(ParseError::new(
+                        format!("Cannot provide unbound identifier `{name}`"),
+                        Some(span),
+                    )),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            None => definitions,
+        };
+
+        let statements = ast
+            .statements
+            .into_iter()
+            .map(|stmt| self.resolve_statement(stmt))
+            .collect::<Result<Vec<_>>>()?;
+        Ok((Ast { statements }, provided))
+    }
+
+    // declares the bindings of `module` that an import brings into scope,
+    // given their values where they are already known
+    fn import(
+        &mut self,
+        module: &str,
+        bindings: Vec<(String, Option<Value>)>,
+        filter: &ImportFilter,
+        span: Span,
+    ) -> Result<()> {
+        if let Some(only) = &filter.only {
+            for name in only {
+                if !bindings.iter().any(|(n, _)| n == name) {
+                    return Err(ParseError::new(
+                        format!("`{name}` is not provided by {module}"),
+                        Some(span),
+                    ));
+                }
+            }
+        }
+
+        let mut shadowed = Vec::new();
+        for (name, value) in bindings {
+            let Some(name) = filter.rename(&name) else {
+                continue;
+            };
+            // importing the same binding again doesn't shadow anything
+            let reimported = self.imported.get(&name).is_some_and(|m| m == module)
+                || self.globals.index_of(&name).is_some_and(|index| {
+                    match (self.globals.get(index), &value) {
+                        (Some(Value::Function(a, _)), Some(Value::Function(b, _))) => a == *b,
+                        (Some(a), Some(b)) => a == *b,
+                        _ => false,
+                    }
+                });
+            if self.globals.index_of(&name).is_some() && !reimported {
+                shadowed.push(format!("`{name}`"));
+            }
+            self.imported.insert(name.clone(), module.to_string());
+            self.define(&name);
+        }
+
+        if !shadowed.is_empty() {
+            shadowed.sort();
+            self.warnings.push(
+                ParseError::warn(
+                    format!(
+                        "Importing {module} shadows existing bindings: {}",
+                        shadowed.join(", ")
+                    ),
+                    Some(span),
+                )
+                .with_help("use :prefix or :only to import the module without shadowing"),
+            );
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str) {
+        self.globals.declare(name);
+        self.defined.insert(name.to_string());
+    }
+
+    fn resolve_statement(&mut self, stmt: Statement) -> Result<Statement> {
+        let kind = match stmt.kind {
+            StatementKind::Binding { name, body } => StatementKind::Binding {
+                name,
+                body: self.resolve_block(body)?,
+            },
+            StatementKind::Expression { body } => StatementKind::Expression {
+                body: self.resolve_block(body)?,
+            },
+            StatementKind::Display { body } => StatementKind::Display {
+                body: self.resolve_block(body)?,
+            },
+            kind => kind,
+        };
+        Ok(Statement::new(kind, stmt.span))
+    }
+
+    fn resolve_block(&mut self, block: Block) -> Result<Block> {
+        block.into_iter().map(|op| self.resolve_op(op)).collect()
+    }
+
+    // resolves a block that runs in a new frame holding `names`
+    fn resolve_scoped(&mut self, names: Vec<String>, block: Block, span: Span) -> Result<Block> {
+        for name in &names {
+            check_identifier(name, span)?;
+        }
+        self.scopes.push(names);
+        let block = self.resolve_block(block);
+        self.scopes.pop();
+        block
+    }
+
+    fn resolve_op(&mut self, op: Operation) -> Result<Operation> {
+        let span = op.span;
+        let kind = match op.kind {
+            OperationKind::Variable { name } => match self.lookup(&name, span)? {
+                Address::Local { depth, index } => OperationKind::Local { depth, index },
+                Address::Global { index } => OperationKind::Global { index },
+            },
+            OperationKind::Set { name } => match self.lookup(&name, span)? {
+                Address::Local { depth, index } => OperationKind::SetLocal { depth, index },
+                Address::Global { index } => {
+                    let global = self.globals.name(index).to_string();
+                    if self.globals.is_builtin(index) && !self.defined.contains(&global) {
+                        return Err(ParseError::new(
+                            format!("Cannot assign to built-in identifier `{global}`"),
+                            Some(span),
+                        ));
+                    }
+                    OperationKind::SetGlobal { index }
+                }
+            },
+            OperationKind::Closure { mut params, body } => {
+                for name in &params.names {
+                    check_identifier(name, span)?;
+                }
+                // defaults run in the closure's frame, like its body
+                self.scopes.push(params.names.clone());
+                let resolved = params
+                    .defaults
+                    .into_iter()
+                    .map(|default| self.resolve_block(default))
+                    .collect::<Result<Vec<_>>>()
+                    .and_then(|defaults| Ok((defaults, self.resolve_block(body)?)));
+                self.scopes.pop();
+                let (defaults, body) = resolved?;
+                params.defaults = defaults;
+                OperationKind::Closure { params, body }
+            }
+            OperationKind::Let { names, body } => OperationKind::Let {
+                body: self.resolve_scoped(names.clone(), body, span)?,
+                names,
+            },
+            OperationKind::If {
+                if_block,
+                else_block,
+            } => OperationKind::If {
+                if_block: self.resolve_block(if_block)?,
+                else_block: self.resolve_block(else_block)?,
+            },
+            OperationKind::Cond { body, end } => OperationKind::Cond {
+                body: self.resolve_block(body)?,
+                end,
+            },
+            OperationKind::Match {
+                branches,
+                scrutinee,
+            } => OperationKind::Match {
+                branches: branches
+                    .into_iter()
+                    .map(|branch| {
+                        let mut kinds = Vec::new();
+                        branch.pattern.structs(&mut kinds);
+                        // a struct pattern is named after the struct's
+                        // constructor, which has to be defined somewhere
+                        if let Some(kind) = kinds.into_iter().find(|kind| self.find(kind).is_none())
+                        {
+                            return Err(ParseError::new(
+                                format!("Unknown struct `{kind}` in pattern"),
+                                Some(span),
+                            ));
+                        }
+                        let predicates = branch
+                            .predicates
+                            .into_iter()
+                            .map(|predicate| self.resolve_block(predicate))
+                            .collect::<Result<Vec<_>>>()?;
+                        Ok(MatchBranch {
+                            body: self.resolve_scoped(
+                                branch.variables.clone(),
+                                branch.body,
+                                span,
+                            )?,
+                            predicates,
+                            ..branch
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                scrutinee,
+            },
+            kind => kind,
+        };
+        Ok(Operation { kind, span })
+    }
+
+    fn lookup(&mut self, name: &str, span: Span) -> Result<Address> {
+        check_identifier(name, span)?;
+        self.find(name).ok_or_else(|| unbound(name, span))
+    }
+
+    fn find(&mut self, name: &str) -> Option<Address> {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            // later bindings in the same frame shadow earlier ones
+            if let Some(index) = scope.iter().rposition(|n| n == name) {
+                return Some(Address::Local { depth, index });
+            }
+        }
+        // identifiers a macro from another file introduces refer to that
+        // file's bindings, which importing it binds to hidden names
+        if let Some(origin) = origin(name) {
+            let base = base_name(name);
+            if self.module.as_deref() != Some(origin) && self.files.binds(origin, base) {
+                let index = self.globals.declare(&imported_from(base, origin));
+                return Some(Address::Global { index });
+            }
+        }
+        // identifiers a macro introduces without binding them refer to the
+        // top-level bindings where the macro was defined, never to locals at
+        // its use site
+        self.globals
+            .index_of(name)
+            .or_else(|| self.globals.index_of(base_name(name)))
+            .map(|index| Address::Global { index })
+    }
+}
+
+fn unbound(name: &str, span: Span) -> ParseError {
+    ParseError::new(
+        format!("Referenced unbound identifier `{}`", base_name(name)),
+        Some(span),
+    )
+}
+
+fn check_identifier(identifier: &str, span: Span) -> Result<()> {
+    let identifier = base_name(identifier);
+    if RESERVED_WORDS.contains(&identifier) {
+        Err(ParseError::new(
+            format!(
+                "\"{}\" is a reserved word and cannot be used as an identifier",
+                identifier
+            ),
+            Some(span),
+        ))
+    } else {
+        Ok(())
+    }
+}
@@ -173,7 +173,7 @@ fn closures() {
             statements: vec![Statement::binding(
                 String::from("add"),
                 vec![Operation::closure(
-                    vec![String::from("a"), String::from("b")],
+                    Params::fixed(vec![String::from("a"), String::from("b")]),
                     vec![
                         Operation::var(String::from("+"), Span { loc: 27, len: 1 }),
                         Operation::var(String::from("a"), Span { loc: 29, len: 1 }),
@@ -209,3 +209,66 @@ fn if_expr() {
         }
     );
 }
+
+#[test]
+fn resolved_addresses() {
+    let src = "(define a 1)\n(lambda (x) (lambda (y) (a x y)))";
+    let mut globals = crate::interpreter::Globals::new();
+    let ast = resolve(
+        parse(src).expect("parse failed"),
+        &mut globals,
+        &crate::modules::Files::default(),
+    )
+    .expect("resolve failed");
+
+    let StatementKind::Expression { body } = &ast.statements[1].kind else {
+        panic!("expected an expression");
+    };
+    let OperationKind::Closure { body, .. } = &body[0].kind else {
+        panic!("expected a closure");
+    };
+    let OperationKind::Closure { body, .. } = &body[0].kind else {
+        panic!("expected a closure");
+    };
+    let kinds = body.iter().map(|op| op.kind.clone()).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            OperationKind::Global {
+                index: globals.index_of("a").expect("a was not declared")
+            },
+            OperationKind::Local { depth: 1, index: 0 },
+            OperationKind::Local { depth: 0, index: 0 },
+            OperationKind::Application { arity: 2 },
+        ]
+    );
+}
+
+#[test]
+fn import_stmt() {
+    let src = "(import image :prefix img/ :only (circle overlay)) (import \"helpers.scm\")";
+    let ast = parse(src).expect("parse failed");
+    assert_eq!(
+        ast.statements,
+        vec![
+            Statement::import(
+                ImportSource::Module(String::from("image")),
+                ImportFilter {
+                    only: Some(vec![String::from("circle"), String::from("overlay")]),
+                    prefix: Some(String::from("img/")),
+                },
+                Span { loc: 0, len: 50 }
+            ),
+            Statement::import(
+                ImportSource::File(String::from("helpers.scm")),
+                ImportFilter::default(),
+                Span { loc: 51, len: 22 }
+            ),
+        ]
+    );
+    assert_eq!(file_imports(src), vec!["helpers.scm"]);
+    assert_eq!(
+        loaded_images("(define pic (load-image \"cat.png\")) (load-image path)"),
+        vec!["cat.png"]
+    );
+}
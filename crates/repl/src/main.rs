@@ -1,5 +1,6 @@
 use std::env;
 use std::io::Read;
+use std::path::Path;
 use std::process;
 
 use interpreter::Output;
@@ -34,7 +35,9 @@ fn main() {
 
     // println!("{}", src);
 
-    let engine = Engine::new();
+    // files are imported relative to the program's directory
+    let root = Path::new(file_name).parent().unwrap_or(Path::new("."));
+    let engine = Engine::new().with_loader(modules::DiskLoader::new(root));
 
     match engine.run(&src) {
         Ok(_) => (),
@@ -47,7 +50,7 @@ fn main() {
 fn repl() {
     println!("Welcome to Scamper (Rust) v0.1");
 
-    let engine = Engine::new();
+    let engine = Engine::new().with_loader(modules::DiskLoader::new("."));
 
     let mut rl = Editor::<(), rustyline::history::DefaultHistory>::new()
         .expect("Failed to create line editor");
@@ -69,6 +72,9 @@ fn repl() {
                                 Output::Error(err) => {
                                     err.emit(&line);
                                 }
+                                Output::Warning(warning) => {
+                                    warning.emit("repl", &line);
+                                }
                             };
                         }
                     }
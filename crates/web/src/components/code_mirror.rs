@@ -1,6 +1,7 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::bindings::{create_diagnostic, create_editor, Diagnostic, EditorView};
+use crate::fs_worker::ImportedFiles;
 use leptos::*;
 use scamper_rs::{diagnostics::error::ErrorLevel, Engine};
 use wasm_bindgen::prelude::*;
@@ -34,27 +35,28 @@ pub fn CodeMirror(
 
             let onlint = Closure::wrap(Box::new(move |editor: EditorView| {
                 let code = editor.get_doc();
-                let engine = Engine::new();
-                match engine.check_syntax(&code) {
-                    Ok(_) => vec![],
-                    Err(e) => {
-                        if let Some(span) = e.span {
-                            vec![create_diagnostic(
-                                span.loc,
-                                span.loc + span.len as u32,
-                                match e.level {
-                                    ErrorLevel::Error => "error",
-                                    ErrorLevel::Warning => "warning",
-                                    _ => "info",
-                                }
-                                .to_string(),
-                                e.message,
-                            )]
-                        } else {
-                            vec![]
-                        }
-                    }
-                }
+                let engine = Engine::new().with_loader(ImportedFiles);
+                let errors = match engine.check_syntax(&code) {
+                    Ok(warnings) => warnings,
+                    Err(e) => vec![e],
+                };
+                errors
+                    .into_iter()
+                    .filter_map(|e| {
+                        let span = e.span?;
+                        Some(create_diagnostic(
+                            span.loc,
+                            span.loc + span.len as u32,
+                            match e.level {
+                                ErrorLevel::Error => "error",
+                                ErrorLevel::Warning => "warning",
+                                _ => "info",
+                            }
+                            .to_string(),
+                            e.message,
+                        ))
+                    })
+                    .collect()
             })
                 as Box<dyn Fn(EditorView) -> Vec<Diagnostic>>);
 
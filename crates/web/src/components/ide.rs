@@ -1,6 +1,6 @@
 use super::{CodeMirror, RenderedValue, ValueOrError};
 use crate::bindings::create_split;
-use crate::fs_worker::{FsRequest, FsResponse, FsWorker};
+use crate::fs_worker::{read_imports, FsRequest, FsResponse, FsWorker, ImportedFiles};
 use crate::{URL_PREFIX, VERSION};
 use ev::Event;
 use futures::stream::StreamExt;
@@ -77,6 +77,8 @@ pub fn Ide() -> impl IntoView {
 
             match result {
                 FsResponse::FileContent(text) => {
+                    // so that the editor can check imports before the first run
+                    read_imports(&text).await;
                     set_input.set(text.clone());
                     set_start_input.set(Some(text));
                     set_loading.set(false);
@@ -226,21 +228,28 @@ pub fn Ide() -> impl IntoView {
     // handle run button click
     let run_click = move |_| {
         let code = input.get();
-        let engine = Engine::new();
-
-        let values = match engine.run(&code) {
-            Ok(values) => values
-                .into_iter()
-                .map(|v| match v {
-                    Output::Value(v) => ValueOrError::Value(v),
-                    Output::Error(err) => ValueOrError::Error(err.emit_to_string(&code)),
-                })
-                .collect(),
-            Err(err) => vec![ValueOrError::Error(err.emit_to_web_string(&code))],
-        };
+        spawn_local(async move {
+            // imported files are read up front, as the interpreter runs synchronously
+            read_imports(&code).await;
+            let engine = Engine::new().with_loader(ImportedFiles);
+
+            let values = match engine.run(&code) {
+                Ok(values) => values
+                    .into_iter()
+                    .map(|v| match v {
+                        Output::Value(v) => ValueOrError::Value(v),
+                        Output::Error(err) => ValueOrError::Error(err.emit_to_string(&code)),
+                        Output::Warning(warning) => {
+                            ValueOrError::Error(warning.emit_to_web_string(&code))
+                        }
+                    })
+                    .collect(),
+                Err(err) => vec![ValueOrError::Error(err.emit_to_web_string(&code))],
+            };
 
-        set_output.set(values);
-        set_dirty.set(false);
+            set_output.set(values);
+            set_dirty.set(false);
+        });
     };
 
     view! {
@@ -1,8 +1,8 @@
 use leptos::html::Canvas;
 use leptos::*;
-use scamper_rs::modules::image::{Align, Drawing, Mode};
-use wasm_bindgen::{JsCast, JsValue};
-use web_sys::{js_sys::Array, CanvasRenderingContext2d};
+use scamper_rs::modules::image::{Align, Bitmap, Drawing, Mode, PathCommand};
+use wasm_bindgen::{Clamped, JsCast, JsValue};
+use web_sys::{js_sys::Array, CanvasRenderingContext2d, ImageData, Path2d};
 
 fn render(x: f64, y: f64, drawing: &Drawing, canvas: &leptos::HtmlElement<Canvas>) {
     let context = canvas
@@ -186,18 +186,24 @@ fn render(x: f64, y: f64, drawing: &Drawing, canvas: &leptos::HtmlElement<Canvas
                 .unwrap();
         }
         Drawing::Text(text) => {
+            // text is drawn from the outlines of the bundled face that measured
+            // it, rather than with the fonts of the browser
+            let path = Path2d::new().unwrap();
+            for command in text.outline() {
+                match command {
+                    PathCommand::MoveTo(px, py) => path.move_to(x + px, y + py),
+                    PathCommand::LineTo(px, py) => path.line_to(x + px, y + py),
+                    PathCommand::QuadTo(x1, y1, px, py) => {
+                        path.quadratic_curve_to(x + x1, y + y1, x + px, y + py)
+                    }
+                    PathCommand::CubicTo(x1, y1, x2, y2, px, py) => {
+                        path.bezier_curve_to(x + x1, y + y1, x + x2, y + y2, x + px, y + py)
+                    }
+                    PathCommand::Close => path.close_path(),
+                }
+            }
             context.set_fill_style_str(&text.color.to_string());
-            context.set_font(&text.font.to_string(text.size));
-            context.set_text_align("left");
-            let metrics = context.measure_text(&text.text).unwrap();
-            context
-                .fill_text(
-                    &text.text,
-                    x,
-                    // add the space up to the baseline so that the text is aligned with the top
-                    y + metrics.actual_bounding_box_ascent() + 1.0,
-                )
-                .unwrap();
+            context.fill_with_path_2d(&path);
         }
     }
 }
@@ -230,3 +236,36 @@ pub fn DrawingView(drawing: Drawing) -> impl IntoView {
         <canvas _ref=canvas_ref></canvas>
     }
 }
+
+#[component]
+pub fn BitmapView(bitmap: Bitmap) -> impl IntoView {
+    let canvas_ref = create_node_ref::<Canvas>();
+
+    canvas_ref.on_load(move |canvas_ref| {
+        let _ = canvas_ref.on_mount(move |canvas| {
+            let raster = bitmap.raster();
+            canvas.set_width(raster.width());
+            canvas.set_height(raster.height());
+
+            let context = canvas
+                .get_context("2d")
+                .unwrap()
+                .unwrap()
+                .dyn_into::<CanvasRenderingContext2d>()
+                .unwrap();
+
+            // an image without pixels has nothing to put on the canvas
+            if let Ok(image_data) = ImageData::new_with_u8_clamped_array_and_sh(
+                Clamped(raster.data()),
+                raster.width(),
+                raster.height(),
+            ) {
+                context.put_image_data(&image_data, 0.0, 0.0).unwrap();
+            }
+        });
+    });
+
+    view! {
+        <canvas _ref=canvas_ref></canvas>
+    }
+}
@@ -5,7 +5,7 @@ mod music;
 pub mod value;
 
 pub use code_block::CodeBlock;
-pub use drawing::DrawingView;
+pub use drawing::{BitmapView, DrawingView};
 pub use lab::LabElementView;
 pub use music::CompositionView;
 pub use value::RenderedValue;
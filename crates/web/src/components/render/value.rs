@@ -1,7 +1,7 @@
-use super::{CodeBlock, CompositionView, DrawingView, LabElementView};
+use super::{BitmapView, CodeBlock, CompositionView, DrawingView, LabElementView};
 use leptos::*;
 use scamper_rs::ast::{FromValue, Value};
-use scamper_rs::modules::image::{Drawing, Hsv, Rgb};
+use scamper_rs::modules::image::{Bitmap, Drawing, Hsv, Rgb};
 use scamper_rs::modules::lab::LabElement;
 use scamper_rs::modules::music::Composition;
 
@@ -43,6 +43,13 @@ fn custom_view(value: &Value) -> Option<View> {
                     }
                     .into_view(),
                 );
+            } else if let Some(bitmap) = item.downcast_ref::<Bitmap>() {
+                return Some(
+                    view! {
+                        <BitmapView bitmap=bitmap.clone() />
+                    }
+                    .into_view(),
+                );
             } else if let Some(element) = item.downcast_ref::<LabElement>() {
                 return Some(
                     view! {
@@ -108,6 +115,53 @@ pub fn RenderedValue(value: ValueOrError) -> impl IntoView {
                         </code>
                     }
                     .into_view(),
+                    Value::Hash(table) => {
+                        let (mutable, entries) = {
+                            let table = table.borrow();
+                            (
+                                table.is_mutable(),
+                                table.entries().cloned().collect::<Vec<_>>(),
+                            )
+                        };
+                        view! {
+                            <code class="hljs">
+                                <CodeBlock>
+                                    {if mutable { "(make-hash (list" } else { "(hash" }}
+                                </CodeBlock>
+                                {entries
+                                    .into_iter()
+                                    .map(|(key, value)| {
+                                        let render = |value: &Value| {
+                                            if let Some(view) = custom_view(value) {
+                                                view
+                                            } else {
+                                                view! {
+                                                    {value.to_string()}
+                                                }.into_view()
+                                            }
+                                        };
+                                        view! {
+                                            <CodeBlock>
+                                                {if mutable { " (pair " } else { " " }}
+                                            </CodeBlock>
+                                            {render(&key)}
+                                            <CodeBlock>
+                                                " "
+                                            </CodeBlock>
+                                            {render(&value)}
+                                            <CodeBlock>
+                                                {if mutable { ")" } else { "" }}
+                                            </CodeBlock>
+                                        }
+                                    })
+                                    .collect::<Vec<_>>()}
+                                <CodeBlock>
+                                    {if mutable { "))" } else { ")" }}
+                                </CodeBlock>
+                            </code>
+                        }
+                        .into_view()
+                    }
                     Value::Pair(px, py) => view! {
                         <code class="hljs">
                             <CodeBlock>
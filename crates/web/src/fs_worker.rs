@@ -2,7 +2,9 @@ use crate::URL_PREFIX;
 use futures::StreamExt;
 use gloo_worker::Spawnable;
 use gloo_worker::{HandlerId, Worker, WorkerScope};
+use scamper_rs::modules::{import_path, FileLoader};
 use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::js_sys::{global, Reflect};
 use web_sys::wasm_bindgen::{JsCast, JsValue};
@@ -14,6 +16,7 @@ use web_sys::{
 #[derive(Serialize, Deserialize)]
 pub enum FsRequest {
     ReadFile(String),          // path
+    ReadBytes(String),         // path
     WriteFile(String, String), // (path, content)
     MoveFile(String, String),  // (source, destination)
 }
@@ -21,6 +24,7 @@ pub enum FsRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub enum FsResponse {
     FileContent(String),
+    FileBytes(Vec<u8>),
     WriteComplete,
     MoveComplete,
     Error(String),
@@ -108,7 +112,7 @@ impl Worker for FsWorker {
 
                     let file_handle = if needs_file_handle {
                         let path = match &msg {
-                            FsRequest::ReadFile(path) => path,
+                            FsRequest::ReadFile(path) | FsRequest::ReadBytes(path) => path,
                             FsRequest::WriteFile(path, _) => &path.clone(),
                             FsRequest::MoveFile(_, _) => unreachable!(),
                         };
@@ -165,6 +169,16 @@ impl FsWorker {
                         Err(e) => scope.respond(id, FsResponse::Error(e)),
                     }
                 }
+                FsRequest::ReadBytes(_) => {
+                    let Some(file_handle) = file_handle else {
+                        scope.respond(id, FsResponse::Error("Failed to get file handle".into()));
+                        return;
+                    };
+                    match Self::read_bytes(&file_handle).await {
+                        Ok(bytes) => scope.respond(id, FsResponse::FileBytes(bytes)),
+                        Err(e) => scope.respond(id, FsResponse::Error(e)),
+                    }
+                }
                 FsRequest::WriteFile(_, content) => {
                     let Some(file_handle) = file_handle else {
                         scope.respond(id, FsResponse::Error("Failed to get file handle".into()));
@@ -206,6 +220,11 @@ impl FsWorker {
     }
 
     async fn read_file(handle: &FileSystemSyncAccessHandle) -> Result<String, String> {
+        let buffer = Self::read_bytes(handle).await?;
+        String::from_utf8(buffer).map_err(|_| "Invalid UTF-8".to_string())
+    }
+
+    async fn read_bytes(handle: &FileSystemSyncAccessHandle) -> Result<Vec<u8>, String> {
         let size = handle
             .get_size()
             .map_err(|_| "Failed to get file size".to_string())? as usize;
@@ -213,7 +232,7 @@ impl FsWorker {
         handle
             .read_with_u8_array(&mut buffer)
             .map_err(|_| "Failed to read file".to_string())?;
-        String::from_utf8(buffer).map_err(|_| "Invalid UTF-8".to_string())
+        Ok(buffer)
     }
 
     async fn write_file(handle: &FileSystemSyncAccessHandle, content: &str) -> Result<(), String> {
@@ -303,3 +322,77 @@ pub async fn send_request(request: FsRequest) -> Option<FsResponse> {
 
     rx.next().await
 }
+
+thread_local! {
+    // contents of the files programs import, as last read from the file system
+    static IMPORTED_FILES: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+    // contents of the images programs load, as last read from the file system
+    static LOADED_IMAGES: RefCell<HashMap<String, Vec<u8>>> = RefCell::new(HashMap::new());
+}
+
+/// Reads the files a program imports, and the files those import in turn, so
+/// that [`ImportedFiles`] can serve them to the interpreter. The images the
+/// files load with a literal path are read as well. Files read for earlier
+/// programs are forgotten, so a run never sees stale or deleted files.
+pub async fn read_imports(src: &str) {
+    let imports = |importer: Option<&str>, src: &str| {
+        scamper_rs::parser::file_imports(src)
+            .into_iter()
+            .map(|path| import_path(importer, &path))
+            .collect::<Vec<_>>()
+    };
+    let mut pending = imports(None, src);
+    let mut images = scamper_rs::parser::loaded_images(src);
+    let mut read = HashMap::new();
+    while let Some(path) = pending.pop() {
+        if read.contains_key(&path) {
+            continue;
+        }
+        if let Some(FsResponse::FileContent(content)) =
+            send_request(FsRequest::ReadFile(path.clone())).await
+        {
+            pending.extend(imports(Some(path.as_str()), &content));
+            images.extend(scamper_rs::parser::loaded_images(&content));
+            read.insert(path, content);
+        }
+    }
+    let mut loaded = HashMap::new();
+    for path in images {
+        if loaded.contains_key(&path) {
+            continue;
+        }
+        if let Some(FsResponse::FileBytes(bytes)) =
+            send_request(FsRequest::ReadBytes(path.clone())).await
+        {
+            loaded.insert(path, bytes);
+        }
+    }
+    IMPORTED_FILES.with(|files| *files.borrow_mut() = read);
+    LOADED_IMAGES.with(|images| *images.borrow_mut() = loaded);
+}
+
+/// Serves imported files from the contents last read by [`read_imports`],
+/// since the interpreter can't wait on the file system worker.
+pub struct ImportedFiles;
+
+impl FileLoader for ImportedFiles {
+    fn read(&self, path: &str) -> Result<String, String> {
+        IMPORTED_FILES.with(|files| {
+            files
+                .borrow()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| "file not found".to_string())
+        })
+    }
+
+    fn read_bytes(&self, path: &str) -> Result<Vec<u8>, String> {
+        LOADED_IMAGES.with(|images| {
+            images
+                .borrow()
+                .get(path)
+                .cloned()
+                .ok_or_else(|| "file not found".to_string())
+        })
+    }
+}